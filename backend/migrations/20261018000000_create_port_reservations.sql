@@ -0,0 +1,11 @@
+-- Port reservation ledger: holds a host port between validation and the worker's create_container.
+CREATE TABLE IF NOT EXISTS port_reservations (
+    port INTEGER PRIMARY KEY,
+    task_id TEXT NOT NULL,
+    owner_id INTEGER,
+    status TEXT NOT NULL DEFAULT 'held',
+    expires_at INTEGER NOT NULL,
+    created_at TEXT DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_port_reservations_expires_at ON port_reservations(expires_at);
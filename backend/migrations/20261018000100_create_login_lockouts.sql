@@ -0,0 +1,9 @@
+-- Failed login tracking and progressive lockout per username (also for unknown usernames).
+CREATE TABLE IF NOT EXISTS login_lockouts (
+    username TEXT PRIMARY KEY,
+    failed_count INTEGER NOT NULL DEFAULT 0,
+    lockout_level INTEGER NOT NULL DEFAULT 0,
+    locked_until INTEGER,
+    last_failure_at INTEGER NOT NULL,
+    last_ip TEXT
+);
@@ -0,0 +1,2 @@
+-- Disabled users cannot log in, refresh or use existing tokens.
+ALTER TABLE users ADD COLUMN is_active INTEGER NOT NULL DEFAULT 1;
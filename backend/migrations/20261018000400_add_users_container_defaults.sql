@@ -0,0 +1,2 @@
+-- Per-user defaults for new containers (JSON, see db::user::ContainerDefaults).
+ALTER TABLE users ADD COLUMN container_defaults TEXT;
@@ -0,0 +1,3 @@
+-- Role-based permissions (admin, operator, user, viewer); existing staff become admins.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+UPDATE users SET role = 'admin' WHERE is_staff = 1;
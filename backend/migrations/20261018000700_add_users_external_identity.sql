@@ -0,0 +1,13 @@
+-- Users provisioned by an external identity provider (OIDC `sub`); local accounts keep auth_provider 'local'.
+ALTER TABLE users ADD COLUMN auth_provider TEXT NOT NULL DEFAULT 'local';
+ALTER TABLE users ADD COLUMN external_id TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_external_id ON users(auth_provider, external_id)
+    WHERE external_id IS NOT NULL;
+
+-- Pending OIDC logins: state -> PKCE verifier and nonce, single use, short-lived.
+CREATE TABLE IF NOT EXISTS oidc_login_states (
+    state TEXT PRIMARY KEY,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,18 @@
+-- Console session recordings (asciicast v2). The .cast file lives in CONSOLE_RECORDING_DIR; `file` is its name there.
+-- user_id has no foreign key so recordings outlive deleted users. ended_at is NULL while the session is open.
+CREATE TABLE IF NOT EXISTS console_recordings (
+    id TEXT PRIMARY KEY,
+    container_id TEXT NOT NULL,
+    container_name TEXT,
+    user_id INTEGER,
+    username TEXT NOT NULL,
+    mode TEXT NOT NULL,
+    file TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER,
+    size_bytes INTEGER NOT NULL DEFAULT 0,
+    truncated INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_console_recordings_container_id ON console_recordings(container_id);
+CREATE INDEX IF NOT EXISTS idx_console_recordings_ended_at ON console_recordings(ended_at);
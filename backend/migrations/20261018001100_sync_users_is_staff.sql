@@ -0,0 +1,2 @@
+-- is_staff is now derived from the role (admin); bring existing rows in line.
+UPDATE users SET is_staff = (role = 'admin');
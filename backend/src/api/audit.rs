@@ -0,0 +1,230 @@
+//! 稽核紀錄查詢（`audit.read`）：GET /audit 依條件回傳 JSON（由新到舊、以 `before` 分頁），
+//! `format=jsonl` / `format=csv` 則分頁讀取、以串流匯出所有符合的事件。
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use futures_util::{Stream, StreamExt};
+use serde::Deserialize;
+
+use crate::auth_extractor::Require;
+use crate::db::audit::{self, AuditEvent, AuditFilter, Outcome};
+use crate::permissions::perm;
+use crate::AppState;
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(serde_json::json!({ "error": msg.into() })))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    tracing::warn!("audit: db error: {}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// JSON 回應預設與最多的筆數。
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+/// 匯出時每次向 SQLite 讀取的筆數。
+const EXPORT_PAGE: i64 = 1000;
+
+const CSV_HEADER: &str = "id,created_at,user_id,username,action,target_type,target_id,outcome,ip,details\n";
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub user_id: Option<i64>,
+    /// username（含登入失敗時嘗試的帳號）。
+    #[serde(default)]
+    pub user: Option<String>,
+    /// 完整名稱或前綴，例如 `container` 符合 `container.*`。
+    #[serde(default)]
+    pub action: Option<String>,
+    #[serde(default)]
+    pub target_type: Option<String>,
+    /// target_id，例如容器 ID / 名稱或 user id。
+    #[serde(default)]
+    pub target: Option<String>,
+    /// success / failure / denied。
+    #[serde(default)]
+    pub outcome: Option<String>,
+    /// Unix 秒，含。
+    #[serde(default)]
+    pub since: Option<i64>,
+    /// Unix 秒，不含。
+    #[serde(default)]
+    pub until: Option<i64>,
+    /// 只回傳 id 小於此值的事件（下一頁）。
+    #[serde(default)]
+    pub before: Option<i64>,
+    /// JSON 預設 100、最多 1000；匯出時未指定則不限。
+    #[serde(default)]
+    pub limit: Option<i64>,
+    /// json（預設）、jsonl、csv。
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    JsonLines,
+    Csv,
+}
+
+impl AuditQuery {
+    fn filter(&self) -> Result<AuditFilter, ApiError> {
+        let outcome = match self.outcome.as_deref() {
+            Some(s) => Some(
+                Outcome::parse(s)
+                    .ok_or_else(|| error(StatusCode::BAD_REQUEST, "outcome must be success, failure or denied"))?,
+            ),
+            None => None,
+        };
+        let text = |v: &Option<String>| v.as_deref().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string);
+        Ok(AuditFilter {
+            user_id: self.user_id,
+            username: text(&self.user),
+            action: text(&self.action),
+            target_type: text(&self.target_type),
+            target_id: text(&self.target),
+            outcome,
+            since: self.since,
+            until: self.until,
+            before: self.before,
+        })
+    }
+
+    fn format(&self) -> Result<Format, ApiError> {
+        match self.format.as_deref().unwrap_or("json") {
+            "json" => Ok(Format::Json),
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            "csv" => Ok(Format::Csv),
+            _ => Err(error(StatusCode::BAD_REQUEST, "format must be json, jsonl or csv")),
+        }
+    }
+}
+
+/// CSV 欄位：必要時加引號；以 = + - @ 開頭的值前面加 `'`，避免試算表當成公式執行（username 可由登入者任意輸入）。
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn csv_line(e: &AuditEvent) -> String {
+    let opt = |v: &Option<String>| v.as_deref().map(csv_field).unwrap_or_default();
+    format!(
+        "{},{},{},{},{},{},{},{},{},{}\n",
+        e.id,
+        e.created_at,
+        e.user_id.map(|id| id.to_string()).unwrap_or_default(),
+        opt(&e.username),
+        csv_field(&e.action),
+        opt(&e.target_type),
+        opt(&e.target_id),
+        csv_field(&e.outcome),
+        opt(&e.ip),
+        csv_field(&e.details.to_string()),
+    )
+}
+
+fn json_line(e: &AuditEvent) -> String {
+    let mut line = serde_json::to_string(e).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+/// 以 `before` 逐頁讀取（每頁 EXPORT_PAGE 筆），直到沒有更多事件或達到 `limit`。
+fn export_stream(
+    pool: sqlx::SqlitePool,
+    filter: AuditFilter,
+    limit: Option<i64>,
+    format: Format,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> {
+    let header = (format == Format::Csv).then(|| Ok(Bytes::from_static(CSV_HEADER.as_bytes())));
+    let pages = futures_util::stream::unfold(Some((filter, limit)), move |cursor| {
+        let pool = pool.clone();
+        async move {
+            let (mut filter, remaining) = cursor?;
+            let page = remaining.map_or(EXPORT_PAGE, |r| r.min(EXPORT_PAGE));
+            if page <= 0 {
+                return None;
+            }
+            match audit::list(&pool, &filter, page).await {
+                Ok(events) if events.is_empty() => None,
+                Ok(events) => {
+                    let body: String = events
+                        .iter()
+                        .map(|e| if format == Format::Csv { csv_line(e) } else { json_line(e) })
+                        .collect();
+                    let next = (events.len() as i64 == page).then(|| {
+                        filter.before = events.last().map(|e| e.id);
+                        (filter, remaining.map(|r| r - page))
+                    });
+                    Some((Ok(Bytes::from(body)), next))
+                }
+                Err(e) => {
+                    tracing::warn!("audit: export failed: {}", e);
+                    Some((Err(std::io::Error::other(e)), None))
+                }
+            }
+        }
+    });
+    futures_util::stream::iter(header).chain(pages)
+}
+
+/// GET /audit?user=&action=&target_type=&target=&outcome=&since=&until=&before=&limit=&format=（`audit.read`）
+async fn list_events(
+    _auth: Require<perm::AuditRead>,
+    State(state): State<AppState>,
+    Query(q): Query<AuditQuery>,
+) -> Result<Response, ApiError> {
+    let filter = q.filter()?;
+    let format = q.format()?;
+    if q.limit.is_some_and(|l| l < 1) {
+        return Err(error(StatusCode::BAD_REQUEST, "limit must be positive"));
+    }
+    if format == Format::Json {
+        let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+        let events = audit::list(&state.pool, &filter, limit).await.map_err(db_error)?;
+        return Ok(Json(events).into_response());
+    }
+    let (content_type, ext) = match format {
+        Format::Csv => ("text/csv; charset=utf-8", "csv"),
+        _ => ("application/x-ndjson", "jsonl"),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"audit-{}.{}\"", now, ext),
+            ),
+        ],
+        Body::from_stream(export_stream(state.pool.clone(), filter, q.limit, format)),
+    )
+        .into_response())
+}
+
+/// 掛載 /audit（`audit.read`）。
+pub fn router() -> Router<AppState> {
+    Router::new().route("/audit", get(list_events))
+}
@@ -1,14 +1,25 @@
-//! JWT 認證 API：取得 token、refresh、驗證 token。
+//! JWT 認證 API：取得 token、refresh（refresh token 單次使用並輪替）、驗證 token、登出。
 //! 登入以 username/password 換取 access/refresh token；無 Google 登入。
+//! 登入有 per-IP / per-username 限流與 SQLite 記錄的漸進式鎖定（`auth.manage` 可查看與重設）。
+//! DISABLE_PASSWORD_LOGIN 時只能經由 OIDC 登入（見 `api::oidc`）；/auth/methods 告知前端可用的登入方式。
 
 use axum::{
-    extract::State,
-    routing::post,
+    extract::{ConnectInfo, Path, State},
+    http::HeaderMap,
+    routing::{delete, get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
-use crate::db::{get_by_username, verify_password};
+use crate::api::mfa::{login_challenge, MfaChallenge};
+use crate::audit::{self, Event};
+use crate::auth_extractor::{authenticate_access_token, AuthUser, Require};
+use crate::permissions::perm;
+use crate::db::login_lockout::{self, LoginLockout};
+use crate::db::refresh_token::{self, RotateOutcome};
+use crate::db::user::{get_row_by_id, set_tokens_valid_after};
+use crate::rate_limit::{client_ip, ClientIp};
 use crate::AppState;
 
 #[derive(Deserialize)]
@@ -23,6 +34,14 @@ pub struct TokenResponse {
     pub refresh_token: String,
 }
 
+/// /auth/token 的回應：直接簽發 token，或（已啟用 MFA 時）第二步的 challenge。
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenResponse),
+    Mfa(MfaChallenge),
+}
+
 #[derive(Deserialize)]
 pub struct RefreshRequest {
     pub refresh_token: String,
@@ -31,6 +50,8 @@ pub struct RefreshRequest {
 #[derive(Serialize)]
 pub struct RefreshResponse {
     pub access_token: String,
+    /// 輪替後的新 refresh token（舊的已失效）。
+    pub refresh_token: String,
 }
 
 #[derive(Deserialize)]
@@ -38,69 +59,274 @@ pub struct VerifyRequest {
     pub token: Option<String>,
 }
 
+fn invalid_credentials() -> (axum::http::StatusCode, Json<serde_json::Value>) {
+    (
+        axum::http::StatusCode::UNAUTHORIZED,
+        Json(serde_json::json!({ "detail": "Invalid credentials." })),
+    )
+}
+
+fn locked_out(until: i64) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    (
+        axum::http::StatusCode::TOO_MANY_REQUESTS,
+        Json(serde_json::json!({
+            "detail": "Too many failed login attempts. Try again later.",
+            "retry_after": (until - now).max(1),
+        })),
+    )
+}
+
+fn db_error(e: sqlx::Error) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+    tracing::warn!("auth/token db error: {}", e);
+    (
+        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "detail": "database error" })),
+    )
+}
+
+/// `auth.login` 稽核事件（method 為 password / oidc）；有 reason 時為失敗。
+pub(crate) fn login_event(method: &str, ip: Option<&str>, reason: Option<&str>) -> Event {
+    let event = Event::new("auth.login").ip(ip);
+    match reason {
+        Some(reason) => event
+            .failed()
+            .details(serde_json::json!({ "method": method, "reason": reason })),
+        None => event.details(serde_json::json!({ "method": method })),
+    }
+}
+
+/// 登入：限流 → 鎖定檢查 → 帳密驗證（`Authenticator`：本機 Argon2 或 LDAP）→ 記錄成功/失敗
+/// → 已啟用 MFA（或 staff 要求 MFA）時改回 challenge，由 /auth/mfa/verify 完成登入。
 async fn token(
     State(state): State<AppState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(body): Json<TokenRequest>,
-) -> Result<Json<TokenResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
-    let row = get_by_username(&state.pool, &body.username)
+) -> Result<Json<LoginResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    if state.config.disable_password_login {
+        return Err((
+            axum::http::StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "detail": "Password login is disabled." })),
+        ));
+    }
+    let ip = client_ip(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        state.config.forwarded_trust(),
+    );
+    state
+        .limits
+        .login_ip
+        .check(ip.as_deref().unwrap_or("unknown"))
+        .await?;
+    state.limits.login_user.check(&body.username).await?;
+    if let Some(until) = login_lockout::locked_until(&state.pool, &body.username)
         .await
-        .map_err(|e| {
-            tracing::warn!("auth/token get_by_username error: {}", e);
-            (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({ "detail": "database error" })),
+        .map_err(db_error)?
+    {
+        tracing::info!("auth/token: locked out username={:?}", body.username);
+        audit::record(
+            &state.pool,
+            login_event("password", ip.as_deref(), Some("locked_out")).actor(None, &body.username),
+        )
+        .await;
+        return Err(locked_out(until));
+    }
+    let row = match state
+        .authenticator
+        .authenticate(&state.pool, &body.username, &body.password)
+        .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            tracing::warn!("auth/token: authentication backend error: {}", e);
+            audit::record(
+                &state.pool,
+                login_event("password", ip.as_deref(), Some("backend_unavailable")).actor(None, &body.username),
             )
-        })?;
-    let row = row.ok_or_else(|| {
-        tracing::info!("auth/token: user not found username={:?}", body.username);
-        (
-            axum::http::StatusCode::UNAUTHORIZED,
-            Json(serde_json::json!({ "detail": "Invalid credentials." })),
+            .await;
+            return Err((
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "detail": "Authentication service is unavailable." })),
+            ));
+        }
+    };
+    let row = match row {
+        Some(row) => row,
+        None => {
+            tracing::info!("auth/token: invalid credentials username={:?}", body.username);
+            let locked = login_lockout::record_failure(&state.pool, &body.username, ip.as_deref())
+                .await
+                .map_err(db_error)?;
+            if let Some(until) = locked {
+                tracing::warn!(
+                    "auth/token: username={:?} locked until {} (last ip {:?})",
+                    body.username,
+                    until,
+                    ip
+                );
+            }
+            audit::record(
+                &state.pool,
+                login_event("password", ip.as_deref(), Some("invalid_credentials")).actor(None, &body.username),
+            )
+            .await;
+            return Err(invalid_credentials());
+        }
+    };
+    login_lockout::record_success(&state.pool, &body.username)
+        .await
+        .map_err(db_error)?;
+    if !row.is_active() {
+        tracing::info!("auth/token: disabled user username={:?}", body.username);
+        audit::record(
+            &state.pool,
+            login_event("password", ip.as_deref(), Some("account_disabled")).actor(Some(row.id), &row.username),
         )
-    })?;
-    if !verify_password(&row.password_hash, &body.password) {
-        tracing::info!("auth/token: password mismatch username={:?}", body.username);
+        .await;
         return Err((
-            axum::http::StatusCode::UNAUTHORIZED,
-            Json(serde_json::json!({ "detail": "Invalid credentials." })),
+            axum::http::StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "detail": "Account is disabled." })),
         ));
     }
-    let (access, refresh) = crate::jwt::issue_tokens(
-        row.id,
-        &row.username,
-        state.config.jwt_secret.as_bytes(),
-        3600,  // access 1h
-        86400, // refresh 1d
+    if let Some(challenge) = login_challenge(&state, &row, "password").await? {
+        return Ok(Json(LoginResponse::Mfa(challenge)));
+    }
+    let tokens = issue_session(&state, row.id, &row.username)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "detail": e })),
+            )
+        })?;
+    audit::record(
+        &state.pool,
+        login_event("password", ip.as_deref(), None).actor(Some(row.id), &row.username),
     )
-    .map_err(|_| {
-        (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({ "detail": "token issue failed" })),
-        )
-    })?;
-    Ok(Json(TokenResponse {
+    .await;
+    Ok(Json(LoginResponse::Tokens(tokens)))
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// 登入成功後簽發 access/refresh token，並以新的 family 記錄 refresh jti。
+pub(crate) async fn issue_session(
+    state: &AppState,
+    user_id: i64,
+    username: &str,
+) -> Result<TokenResponse, &'static str> {
+    let jti = uuid::Uuid::new_v4().to_string();
+    let (access, refresh) = state.jwt.issue_tokens(
+        user_id,
+        username,
+        state.config.access_token_ttl_secs,
+        state.config.refresh_token_ttl_secs,
+        &jti,
+    )
+    .map_err(|_| "token issue failed")?;
+    refresh_token::insert(&state.pool, &jti, user_id, &jti, now() + state.config.refresh_token_ttl_secs)
+        .await
+        .map_err(|e| {
+            tracing::warn!("auth: storing refresh token failed: {}", e);
+            "database error"
+        })?;
+    Ok(TokenResponse {
         access_token: access,
         refresh_token: refresh,
-    }))
+    })
 }
 
+/// 以 refresh token 換新的 access token 與新的 refresh token（舊的立即失效）；
+/// 已用過的 refresh token 再次出現視為外洩，撤銷整個 family。
 async fn refresh(
     State(state): State<AppState>,
     Json(body): Json<RefreshRequest>,
 ) -> Result<Json<RefreshResponse>, (axum::http::StatusCode, &'static str)> {
-    let claims = crate::jwt::verify_refresh(&body.refresh_token, state.config.jwt_secret.as_bytes())
+    let claims = state.jwt.verify_refresh(&body.refresh_token)
         .map_err(|_| (axum::http::StatusCode::UNAUTHORIZED, "invalid refresh token"))?;
-    let (access, _) = crate::jwt::issue_tokens(
-        claims.user_id,
-        &claims.sub,
-        state.config.jwt_secret.as_bytes(),
-        3600,
-        86400,
+    let user = get_row_by_id(&state.pool, claims.user_id)
+        .await
+        .map_err(|_| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "database error"))?
+        .ok_or((axum::http::StatusCode::UNAUTHORIZED, "invalid refresh token"))?;
+    if !user.token_still_valid(claims.iat) {
+        return Err((axum::http::StatusCode::UNAUTHORIZED, "refresh token revoked"));
+    }
+    if !user.is_active() {
+        return Err((axum::http::StatusCode::UNAUTHORIZED, "user disabled"));
+    }
+    let new_jti = uuid::Uuid::new_v4().to_string();
+    let (access, refresh) = state.jwt.issue_tokens(
+        user.id,
+        &user.username,
+        state.config.access_token_ttl_secs,
+        state.config.refresh_token_ttl_secs,
+        &new_jti,
     )
     .map_err(|_| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "token issue failed"))?;
-    Ok(Json(RefreshResponse {
-        access_token: access,
-    }))
+    let outcome = refresh_token::rotate(
+        &state.pool,
+        &claims.jti,
+        &new_jti,
+        user.id,
+        now() + state.config.refresh_token_ttl_secs,
+    )
+    .await
+    .map_err(|_| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "database error"))?;
+    match outcome {
+        RotateOutcome::Rotated => Ok(Json(RefreshResponse {
+            access_token: access,
+            refresh_token: refresh,
+        })),
+        RotateOutcome::Reused => {
+            tracing::warn!(
+                "auth/refresh: reuse of refresh token detected for user {}; session revoked",
+                user.username
+            );
+            Err((axum::http::StatusCode::UNAUTHORIZED, "refresh token reuse detected"))
+        }
+        RotateOutcome::Invalid => Err((axum::http::StatusCode::UNAUTHORIZED, "invalid refresh token")),
+    }
+}
+
+/// POST /auth/logout：撤銷此 refresh token 所屬的 session（已簽發的 access token 仍有效至過期）。
+async fn logout(
+    State(state): State<AppState>,
+    Json(body): Json<RefreshRequest>,
+) -> Result<Json<serde_json::Value>, (axum::http::StatusCode, &'static str)> {
+    let claims = state.jwt.verify_refresh(&body.refresh_token)
+        .map_err(|_| (axum::http::StatusCode::UNAUTHORIZED, "invalid refresh token"))?;
+    refresh_token::revoke_family_of(&state.pool, &claims.jti, claims.user_id)
+        .await
+        .map_err(|_| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "database error"))?;
+    Ok(Json(serde_json::json!({})))
+}
+
+/// POST /auth/logout-all：撤銷目前使用者所有 refresh token，並讓之前簽發的 access token 立即失效。
+async fn logout_all(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<serde_json::Value>, (axum::http::StatusCode, &'static str)> {
+    revoke_user_sessions(&state.pool, auth.0.id)
+        .await
+        .map_err(|_| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "database error"))?;
+    Ok(Json(serde_json::json!({})))
+}
+
+/// 撤銷使用者所有 session（logout-all；改密碼、停用帳號等也會用到）。
+pub(crate) async fn revoke_user_sessions(pool: &sqlx::SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    set_tokens_valid_after(pool, user_id, now()).await?;
+    refresh_token::revoke_all_for_user(pool, user_id).await?;
+    Ok(())
 }
 
 async fn verify(
@@ -113,7 +339,7 @@ async fn verify(
             Json(serde_json::json!({ "detail": "token required" })),
         )
     })?;
-    crate::jwt::verify_access(&token, state.config.jwt_secret.as_bytes()).map_err(|_| {
+    authenticate_access_token(&state, &token).await.map_err(|_| {
         (
             axum::http::StatusCode::UNAUTHORIZED,
             Json(serde_json::json!({ "detail": "Token is invalid or expired" })),
@@ -122,10 +348,59 @@ async fn verify(
     Ok(Json(serde_json::json!({})))
 }
 
-/// 掛載 /auth/token（登入）、/auth/token/refresh、/auth/token/verify。
+/// GET /auth/methods：可用的登入方式（未登入即可查詢）。
+async fn methods(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "password": !state.config.disable_password_login,
+        "oidc": state.oidc.is_some(),
+    }))
+}
+
+/// GET /auth/lockouts：查看登入失敗與鎖定紀錄（`auth.manage`）。
+async fn list_lockouts(
+    _auth: Require<perm::AuthManage>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<LoginLockout>>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    Ok(Json(login_lockout::list(&state.pool).await.map_err(db_error)?))
+}
+
+/// DELETE /auth/lockouts/:username：解除鎖定並清除失敗次數（`auth.manage`）。
+async fn reset_lockout(
+    Require(auth, _): Require<perm::AuthManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(username): Path<String>,
+) -> Result<Json<serde_json::Value>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    if !login_lockout::reset(&state.pool, &username)
+        .await
+        .map_err(db_error)?
+    {
+        return Err((
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "No lockout for this username" })),
+        ));
+    }
+    tracing::info!("auth: lockout for {:?} reset by {}", username, auth.0.username);
+    audit::record(
+        &state.pool,
+        Event::new("auth.lockout_reset")
+            .user(&auth.0)
+            .target("username", &username)
+            .ip(ip.as_deref()),
+    )
+    .await;
+    Ok(Json(serde_json::json!({})))
+}
+
+/// 掛載 /auth/methods、/auth/token（登入）、/auth/token/refresh、/auth/token/verify、/auth/logout(-all)、/auth/lockouts（`auth.manage`）。
 pub fn router() -> Router<AppState> {
     Router::new()
+        .route("/auth/methods", get(methods))
         .route("/auth/token", post(token))
         .route("/auth/token/refresh", post(refresh))
         .route("/auth/token/verify", post(verify))
+        .route("/auth/logout", post(logout))
+        .route("/auth/logout-all", post(logout_all))
+        .route("/auth/lockouts", get(list_lockouts))
+        .route("/auth/lockouts/:username", delete(reset_lockout))
 }
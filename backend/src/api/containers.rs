@@ -1,16 +1,24 @@
-//! 容器 REST API：列出 GUI 容器、建立（丟進佇列）、啟動/停止/刪除/重啟、取得 console 元資料。
+//! 容器 REST API：列出 GUI 容器、建立（丟進佇列）、啟動/停止/刪除/重啟、取得 console 元資料、日誌、資源用量。
 //! 與 Django xterm views 對齊（list、run、control、console meta）。
 
 use axum::{
-    extract::{Path, State},
+    body::Body,
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::auth_extractor::AuthUser;
+use crate::audit::{self, Event};
+use crate::auth_extractor::{AuthUser, Require};
+use crate::db::port_reservation;
 use crate::docker;
+use crate::docker::gpu::{self, GpuRequest};
 use crate::docker::ports;
+use crate::permissions::{perm, Permission};
+use crate::rate_limit::ClientIp;
 use crate::AppState;
 
 #[derive(Serialize)]
@@ -18,28 +26,130 @@ pub struct ContainersResponse {
     pub containers: Vec<docker::ContainerInfo>,
 }
 
+#[derive(Deserialize)]
+pub struct ListContainersQuery {
+    /// 是否計算容器大小（size_raw / size_fs）；成本高，預設不計算。
+    #[serde(default)]
+    pub size: bool,
+}
+
+/// 只列出使用者可存取的容器（見 `docker::user_can_access`）。
 async fn list_containers(
-    _auth: AuthUser,
+    auth: AuthUser,
     State(state): State<AppState>,
+    Query(q): Query<ListContainersQuery>,
 ) -> Result<Json<ContainersResponse>, (axum::http::StatusCode, String)> {
-    let containers = docker::list_containers_gui_vnc(&state.docker)
-        .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // 大小不在索引內；要求大小時直接查 Docker
+    let containers = if q.size {
+        docker::list_containers_gui_vnc(&state.docker, true).await
+    } else {
+        containers_snapshot(&state).await
+    }
+    .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .into_iter()
+    .filter(|c| docker::user_can_access(&auth.0, c.owner_id))
+    .collect();
     Ok(Json(ContainersResponse { containers }))
 }
 
+/// 管理中的容器（不含大小）：優先用容器索引，尚未同步時直接查 Docker。
+pub(crate) async fn containers_snapshot(
+    state: &AppState,
+) -> Result<Vec<docker::ContainerInfo>, bollard::errors::Error> {
+    match state.containers.list().await {
+        Some(c) => Ok(c),
+        None => docker::list_containers_gui_vnc(&state.docker, false).await,
+    }
+}
+
+/// 沒有 `containers.unlimited` 的使用者容器數（含尚在佇列中的建立）達到 MAX_CONTAINERS_PER_USER 時回 403。
+pub(crate) async fn check_quota(
+    state: &AppState,
+    auth: &AuthUser,
+) -> Result<(), (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let max = match state.config.max_containers_per_user {
+        Some(max) if !auth.0.can(Permission::ContainersUnlimited) => max as usize,
+        _ => return Ok(()),
+    };
+    let owned = containers_snapshot(state)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?
+        .iter()
+        .filter(|c| c.owner_id == Some(auth.0.id))
+        .count();
+    let pending = port_reservation::pending_for_owner(&state.pool, auth.0.id)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?;
+    if owned + pending as usize >= max {
+        return Err((
+            axum::http::StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": format!("Container quota exceeded ({} containers)", max) })),
+        ));
+    }
+    Ok(())
+}
+
 async fn console_meta(
-    auth: AuthUser,
+    Require(auth, _): Require<perm::ConsoleAttach>,
     State(state): State<AppState>,
     Path((action, id)): Path<(String, String)>,
-) -> Result<Json<docker::ConsoleMeta>, (axum::http::StatusCode, String)> {
-    let _ = auth;
+) -> Result<Json<docker::ConsoleMeta>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    authorize_container(&state, &auth, &id).await?;
     let meta = docker::get_console_meta(&state.docker, &id, &action)
         .await
-        .map_err(|e| (axum::http::StatusCode::NOT_FOUND, e.to_string()))?;
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?;
     Ok(Json(meta))
 }
 
+/// GET /containers/:id/logs：以 chunked 回傳 stdout/stderr；`follow=true` 時持續串流直到容器停止或連線中斷。
+async fn container_logs(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<docker::logs::LogsParams>,
+) -> Result<Response, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    authorize_container(&state, &auth, &id).await?;
+    let stream = docker::logs::log_stream(&state.docker, &id, &params);
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+/// GET /containers/:id/stats：單筆 CPU/記憶體/網路/磁碟/PIDs 取樣。
+async fn container_stats(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<docker::stats::ContainerStats>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    authorize_container(&state, &auth, &id).await?;
+    let stats = docker::stats::container_stats(&state.docker, &id)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?;
+    Ok(Json(stats))
+}
+
 #[derive(Deserialize)]
 pub struct RunContainerBody {
     pub container_name: String,
@@ -48,10 +158,17 @@ pub struct RunContainerBody {
     pub password: String,
     pub vnc_password: String,
     pub root_password: String,
+    /// 未指定時套用個人容器預設值（見 PATCH /me），再預設為 false。
+    #[serde(default)]
+    pub privileged: Option<bool>,
     #[serde(default)]
-    pub privileged: bool,
+    pub nvdocker: Option<bool>,
+    /// nvdocker 時配置的 GPU 數量（未指定時用個人預設值，再預設 1）；與 device_ids 擇一。
     #[serde(default)]
-    pub nvdocker: bool,
+    pub gpu_count: Option<u32>,
+    /// nvdocker 時指定的 GPU（index 或 UUID，見 GET /api/gpus）。
+    #[serde(default)]
+    pub device_ids: Option<Vec<String>>,
 }
 
 #[derive(Serialize)]
@@ -60,32 +177,59 @@ pub struct RunContainerResponse {
     pub task_id: String,
 }
 
-async fn run_container(
-    auth: AuthUser,
-    State(state): State<AppState>,
-    Json(body): Json<RunContainerBody>,
-) -> Result<Json<RunContainerResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
-    let _ = auth;
-    let name = body.container_name.replace('/', "-");
+/// 檢查目前使用者可否存取容器（見 `docker::user_can_access`）；容器不存在回 404，無權限回 403。
+pub(crate) async fn authorize_container(
+    state: &AppState,
+    auth: &AuthUser,
+    id: &str,
+) -> Result<(), (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let owner = docker::container_owner(&state.docker, id).await.map_err(|e| {
+        (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+    })?;
+    if !docker::user_can_access(&auth.0, owner) {
+        return Err((
+            axum::http::StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "You do not have access to this container" })),
+        ));
+    }
+    Ok(())
+}
+
+/// 驗證新容器名稱與 SSH 埠（名稱規則、埠是否被容器或其他服務佔用）；回傳正規化後的名稱與埠。
+pub(crate) async fn validate_new_container(
+    state: &AppState,
+    container_name: &str,
+    ssh: &str,
+) -> Result<(String, u16), (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let name = container_name.replace('/', "-");
     if name.len() < 2 {
         return Err((
             axum::http::StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": "Container name must be at least 2 characters long" })),
         ));
     }
-    if !name.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) {
+    if !name.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) {
         return Err((
             axum::http::StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": "Container name must start with a letter [a-zA-Z]" })),
         ));
     }
-    let ssh_port: u16 = body.ssh.parse().map_err(|_| {
+    let ssh_port: u16 = ssh.parse().map_err(|_| {
         (
             axum::http::StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": "Non-integer value provided" })),
         )
     })?;
-    if docker::is_port_used_by_container(&state.docker, ssh_port)
+    if !ports::PortPolicy::from_config(&state.config).allows(ssh_port) {
+        return Err((
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": format!("Port [{}] is outside the allowed port range", ssh_port) })),
+        ));
+    }
+    if docker::port_used_by_container(&state.docker, &state.containers, ssh_port)
         .await
         .map_err(|e| {
             (
@@ -99,14 +243,147 @@ async fn run_container(
             Json(serde_json::json!({ "error": format!("Port [{}] is already in use by container", ssh_port) })),
         ));
     }
-    if ports::check_port_in_use(&state.config.host_for_port_check, ssh_port) {
+    if ports::check_port_in_use(&ports::check_hosts(&state.config), ssh_port).await {
         return Err((
             axum::http::StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": format!("Port [{}] is already in use by other services", ssh_port) })),
         ));
     }
-    let task_id = crate::queue::enqueue_run_image(
+    Ok((name, ssh_port))
+}
+
+/// 以 task_id 保留 ssh port 直到 worker 建立容器（見 `db::port_reservation`）；已被其他待處理請求保留時回 409。
+pub(crate) async fn reserve_port(
+    state: &AppState,
+    port: u16,
+    task_id: &str,
+    owner_id: i64,
+) -> Result<(), (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let acquired = port_reservation::acquire(
+        &state.pool,
+        port,
+        task_id,
+        owner_id,
+        state.config.port_reservation_ttl_secs,
+    )
+    .await
+    .map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+    })?;
+    if !acquired {
+        return Err((
+            axum::http::StatusCode::CONFLICT,
+            Json(serde_json::json!({ "error": format!("Port [{}] is reserved by another pending request", port) })),
+        ));
+    }
+    Ok(())
+}
+
+/// 容器操作的稽核事件（`container.create`、`container.stop` 等）；container 為 ID 或名稱。
+pub(crate) fn container_event(action: &str, auth: &AuthUser, container: &str, ip: Option<&str>) -> Event {
+    Event::new(format!("container.{}", action))
+        .user(&auth.0)
+        .target("container", container)
+        .ip(ip)
+}
+
+/// 權限或存取檢查失敗的稽核事件：403 記為 denied，其餘（例如容器不存在）為 failure。
+pub(crate) fn rejected(event: Event, status: axum::http::StatusCode) -> Event {
+    if status == axum::http::StatusCode::FORBIDDEN {
+        event.denied()
+    } else {
+        event.failed()
+    }
+}
+
+/// privileged / nvdocker 需要角色另外具備 `containers.privileged` / `containers.gpu`。
+pub(crate) fn require_container_options(
+    auth: &AuthUser,
+    privileged: bool,
+    nvdocker: bool,
+) -> Result<(), (axum::http::StatusCode, Json<serde_json::Value>)> {
+    if privileged {
+        auth.require(Permission::ContainersPrivileged)?;
+    }
+    if nvdocker {
+        auth.require(Permission::ContainersGpu)?;
+    }
+    Ok(())
+}
+
+/// 入列前先以目前清單與佔用檢查 GPU 請求，讓明顯無法滿足的請求直接回 400；實際配置仍由 worker 決定。
+pub(crate) async fn precheck_gpus(
+    state: &AppState,
+    req: &GpuRequest,
+) -> Result<(), (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let devices = state.gpus.devices().await.map_err(|e| {
+        (
+            axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": format!("GPU inventory unavailable: {}", e) })),
+        )
+    })?;
+    let held = gpu::held_devices(&state.docker).await.map_err(|e| {
+        (
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+    })?;
+    gpu::select_devices(&devices, &held, req).map_err(|e| {
+        (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e })),
+        )
+    })?;
+    Ok(())
+}
+
+async fn run_container(
+    Require(auth, _): Require<perm::ContainersCreate>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<RunContainerBody>,
+) -> Result<Json<RunContainerResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let defaults = crate::db::user::get_container_defaults(&state.pool, auth.0.id)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?;
+    let privileged = body.privileged.or(defaults.privileged).unwrap_or(false);
+    let nvdocker = body.nvdocker.or(defaults.nvdocker).unwrap_or(false);
+    if let Err(e) = require_container_options(&auth, privileged, nvdocker) {
+        let event = container_event("create", &auth, &body.container_name, ip.as_deref())
+            .details(serde_json::json!({ "privileged": privileged, "nvdocker": nvdocker }));
+        audit::record(&state.pool, rejected(event, e.0)).await;
+        return Err(e);
+    }
+    let (name, ssh_port) = validate_new_container(&state, &body.container_name, &body.ssh).await?;
+    check_quota(&state, &auth).await?;
+    let gpus = GpuRequest {
+        count: body.gpu_count.or(defaults.gpu_count),
+        device_ids: body.device_ids.clone(),
+    };
+    if nvdocker {
+        precheck_gpus(&state, &gpus).await?;
+    }
+    let task_id = crate::queue::new_task_id();
+    reserve_port(&state, ssh_port, &task_id, auth.0.id).await?;
+    let event = container_event("create", &auth, &name, ip.as_deref()).details(serde_json::json!({
+        "task_id": &task_id,
+        "ssh_port": ssh_port,
+        "privileged": privileged,
+        "nvdocker": nvdocker,
+        "gpu_count": gpus.count,
+        "device_ids": &gpus.device_ids,
+    }));
+    if let Err(e) = crate::queue::enqueue_run_image(
         &state.config.redis_url,
+        &task_id,
         &state.config.docker_network,
         docker::GUI_IMAGE_TAG_PREFIX,
         ssh_port,
@@ -115,16 +392,21 @@ async fn run_container(
         &body.password,
         &body.vnc_password,
         &body.root_password,
-        body.privileged,
-        body.nvdocker,
+        privileged,
+        nvdocker,
+        gpus,
+        auth.0.id,
     )
     .await
-    .map_err(|e| {
-        (
+    {
+        let _ = port_reservation::release(&state.pool, ssh_port, &task_id).await;
+        audit::record(&state.pool, event.failed()).await;
+        return Err((
             axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({ "error": e.to_string() })),
-        )
-    })?;
+            Json(serde_json::json!({ "error": e })),
+        ));
+    }
+    audit::record(&state.pool, event).await;
     Ok(Json(RunContainerResponse {
         container_name: name,
         task_id,
@@ -142,12 +424,13 @@ pub struct ContainersControlResponse {
     pub task_id: Option<String>,
 }
 
+/// 啟動/停止/重啟/刪除：viewer 等沒有 `containers.create` 的角色不能操作，且只能操作自己可存取的容器。
 async fn containers_control(
-    auth: AuthUser,
+    Require(auth, _): Require<perm::ContainersCreate>,
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Json(body): Json<ContainersControlBody>,
 ) -> Result<Json<ContainersControlResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
-    let _ = auth;
     let valid = ["start", "stop", "restart", "remove"];
     if !valid.contains(&body.cmd.as_str()) {
         return Err((
@@ -155,6 +438,11 @@ async fn containers_control(
             Json(serde_json::json!({ "error": "invalid cmd" })),
         ));
     }
+    let event = container_event(&body.cmd, &auth, &body.id, ip.as_deref());
+    if let Err(e) = authorize_container(&state, &auth, &body.id).await {
+        audit::record(&state.pool, rejected(event, e.0)).await;
+        return Err(e);
+    }
     let task_id = if ["start", "restart", "stop", "remove"].contains(&body.cmd.as_str()) {
         let waiting_msg = serde_json::json!({
             "message": {
@@ -169,12 +457,19 @@ async fn containers_control(
     } else {
         None
     };
+    let event = match &task_id {
+        Some(task_id) => event.details(serde_json::json!({ "task_id": task_id })),
+        None => event.failed(),
+    };
+    audit::record(&state.pool, event).await;
     Ok(Json(ContainersControlResponse { task_id }))
 }
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/containers", get(list_containers))
+        .route("/containers/:id/logs", get(container_logs))
+        .route("/containers/:id/stats", get(container_stats))
         .route("/console/:action/:id", get(console_meta))
         .route("/container/new", post(run_container))
         .route("/containers/control", post(containers_control))
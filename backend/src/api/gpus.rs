@@ -0,0 +1,33 @@
+//! GPU 清單 API：列出主機 GPU 與目前由哪個容器持有，供建立容器時選擇 device_ids。
+
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::auth_extractor::AuthUser;
+use crate::docker::gpu;
+use crate::AppState;
+
+#[derive(Serialize)]
+pub struct GpusResponse {
+    pub gpus: Vec<gpu::GpuStatus>,
+}
+
+async fn list_gpus(
+    _auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<GpusResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let gpus = gpu::gpu_status(&state.docker, state.gpus.as_ref())
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "error": e })),
+            )
+        })?;
+    Ok(Json(GpusResponse { gpus }))
+}
+
+/// GET /gpus：需 JWT。
+pub fn router() -> Router<AppState> {
+    Router::new().route("/gpus", get(list_gpus))
+}
@@ -0,0 +1,327 @@
+//! 目前使用者的自助 API：查看自己（含容器配額與用量）、修改 email 與容器預設值、變更密碼、管理個人 API token。
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::api::auth::{issue_session, revoke_user_sessions, TokenResponse};
+use crate::api::containers::containers_snapshot;
+use crate::audit::{self, Event};
+use crate::auth_extractor::AuthUser;
+use crate::db::api_token::{self, ApiToken};
+use crate::db::port_reservation;
+use crate::db::user::{self, ContainerDefaults, User};
+use crate::docker::gpu;
+use crate::permissions::{Permission, Scope};
+use crate::rate_limit::ClientIp;
+use crate::AppState;
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(serde_json::json!({ "error": msg.into() })))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    tracing::warn!("me: db error: {}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+#[derive(Serialize)]
+pub struct Quota {
+    /// 可擁有的容器數（MAX_CONTAINERS_PER_USER）；null 表示不限（有 `containers.unlimited` 永遠不限）。
+    pub max_containers: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Usage {
+    pub containers: usize,
+    pub running: usize,
+    /// 已入列、尚未由 worker 建立的容器。
+    pub pending: i64,
+    /// 自己容器佔用的 GPU 數。
+    pub gpus: usize,
+}
+
+#[derive(Serialize)]
+pub struct MeResponse {
+    #[serde(flatten)]
+    pub user: User,
+    /// 角色具備的權限（例如 `containers.create`）。
+    pub permissions: &'static [Permission],
+    pub container_defaults: ContainerDefaults,
+    pub quota: Quota,
+    /// Docker 無法連線時為 null。
+    pub usage: Option<Usage>,
+}
+
+/// 計算用量；Docker 查詢失敗時回 None（/me 仍可用於顯示登入身分）。
+async fn usage(state: &AppState, user_id: i64) -> Result<Option<Usage>, ApiError> {
+    let pending = port_reservation::pending_for_owner(&state.pool, user_id)
+        .await
+        .map_err(db_error)?;
+    let owned: Vec<_> = match containers_snapshot(state).await {
+        Ok(list) => list.into_iter().filter(|c| c.owner_id == Some(user_id)).collect(),
+        Err(e) => {
+            tracing::warn!("me: listing containers failed: {}", e);
+            return Ok(None);
+        }
+    };
+    let names: HashSet<&str> = owned.iter().map(|c| c.name.as_str()).collect();
+    let gpus = match gpu::held_devices(&state.docker).await {
+        Ok(held) => held.values().filter(|name| names.contains(name.as_str())).count(),
+        Err(e) => {
+            tracing::warn!("me: listing GPU holders failed: {}", e);
+            0
+        }
+    };
+    Ok(Some(Usage {
+        containers: owned.len(),
+        running: owned.iter().filter(|c| c.status == "running").count(),
+        pending,
+        gpus,
+    }))
+}
+
+async fn me_response(state: &AppState, user: User) -> Result<MeResponse, ApiError> {
+    let container_defaults = user::get_container_defaults(&state.pool, user.id)
+        .await
+        .map_err(db_error)?;
+    let quota = Quota {
+        max_containers: if user.can(Permission::ContainersUnlimited) {
+            None
+        } else {
+            state.config.max_containers_per_user
+        },
+    };
+    let usage = usage(state, user.id).await?;
+    Ok(MeResponse {
+        permissions: user.role.permissions(),
+        user,
+        container_defaults,
+        quota,
+        usage,
+    })
+}
+
+/// GET /me
+async fn get_me(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<MeResponse>, ApiError> {
+    Ok(Json(me_response(&state, auth.0).await?))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMeBody {
+    /// 空字串清除 email。
+    #[serde(default)]
+    pub email: Option<String>,
+    /// 整組取代個人容器預設值。
+    #[serde(default)]
+    pub container_defaults: Option<ContainerDefaults>,
+}
+
+/// PATCH /me：只更新有給的欄位。
+async fn update_me(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Json(body): Json<UpdateMeBody>,
+) -> Result<Json<MeResponse>, ApiError> {
+    let id = auth.0.id;
+    if let Some(email) = &body.email {
+        let email = Some(email.trim()).filter(|e| !e.is_empty());
+        user::set_email(&state.pool, id, email).await.map_err(db_error)?;
+    }
+    if let Some(defaults) = &body.container_defaults {
+        if defaults.gpu_count == Some(0) {
+            return Err(error(StatusCode::BAD_REQUEST, "gpu_count must be at least 1"));
+        }
+        user::set_container_defaults(&state.pool, id, defaults)
+            .await
+            .map_err(db_error)?;
+    }
+    let user = user::get_by_id(&state.pool, id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "User not found"))?;
+    Ok(Json(me_response(&state, user).await?))
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordBody {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// POST /me/password：驗證舊密碼後重新雜湊，撤銷所有既有 session，並為目前裝置簽發新的一組 token。
+/// 與登入共用 per-username 限流，避免被拿來暴力猜測舊密碼。
+async fn change_password(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<ChangePasswordBody>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    state.limits.login_user.check(&auth.0.username).await?;
+    if body.new_password.is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST, "new_password must not be empty"));
+    }
+    let row = user::get_row_by_id(&state.pool, auth.0.id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "User not found"))?;
+    if row.auth_provider != "local" {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "This account signs in through an identity provider and has no local password",
+        ));
+    }
+    let hash = row.password_hash.clone();
+    let (old, new) = (body.old_password, body.new_password);
+    let new_hash = tokio::task::spawn_blocking(move || {
+        if !user::verify_password(&hash, &old) {
+            return Ok(None);
+        }
+        user::hash_password(&new).map(Some)
+    })
+    .await
+    .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let event = Event::new("me.password_change").user(&auth.0).ip(ip.as_deref());
+    let Some(new_hash) = new_hash else {
+        audit::record(
+            &state.pool,
+            event.failed().details(serde_json::json!({ "reason": "old_password_incorrect" })),
+        )
+        .await;
+        return Err(error(StatusCode::BAD_REQUEST, "Old password is incorrect"));
+    };
+    user::set_password_hash(&state.pool, row.id, &new_hash)
+        .await
+        .map_err(db_error)?;
+    revoke_user_sessions(&state.pool, row.id).await.map_err(db_error)?;
+    tracing::info!("me: {} changed password; other sessions revoked", row.username);
+    audit::record(&state.pool, event).await;
+    let tokens = issue_session(&state, row.id, &row.username)
+        .await
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(tokens))
+}
+
+/// GET /me/tokens：自己的 API token（不含明文）。
+async fn list_tokens(
+    auth: AuthUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ApiToken>>, ApiError> {
+    Ok(Json(
+        api_token::list_for_user(&state.pool, auth.0.id)
+            .await
+            .map_err(db_error)?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenBody {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// 幾天後到期；未指定則不會過期（仍可撤銷）。
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedToken {
+    /// 明文 token，只在建立時回傳這一次。
+    pub token: String,
+    #[serde(flatten)]
+    pub record: ApiToken,
+}
+
+/// POST /me/tokens：建立個人 API token（只接受 JWT 登入，API token 不能再建立 token）。
+async fn create_token(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<CreateTokenBody>,
+) -> Result<(StatusCode, Json<CreatedToken>), ApiError> {
+    let name = body.name.trim();
+    if name.is_empty() || name.len() > 100 {
+        return Err(error(StatusCode::BAD_REQUEST, "name must be 1-100 characters"));
+    }
+    if body.scopes.is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST, "at least one scope is required"));
+    }
+    let mut scopes: Vec<Scope> = Vec::new();
+    for scope in body.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    let expires_at = match body.expires_in_days {
+        Some(0) => return Err(error(StatusCode::BAD_REQUEST, "expires_in_days must be at least 1")),
+        Some(days) => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            Some(now + i64::from(days) * 86400)
+        }
+        None => None,
+    };
+    let (token, record) = api_token::create(&state.pool, auth.0.id, name, &scopes, expires_at)
+        .await
+        .map_err(db_error)?;
+    tracing::info!("me: {} created API token {:?} (id={})", auth.0.username, name, record.id);
+    audit::record(
+        &state.pool,
+        Event::new("me.token_create")
+            .user(&auth.0)
+            .target("api_token", record.id)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({
+                "name": name,
+                "scopes": &record.scopes,
+                "expires_at": record.expires_at,
+            })),
+    )
+    .await;
+    Ok((StatusCode::CREATED, Json(CreatedToken { token, record })))
+}
+
+/// DELETE /me/tokens/:id：撤銷自己的 API token。
+async fn revoke_token(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if !api_token::revoke(&state.pool, id, auth.0.id)
+        .await
+        .map_err(db_error)?
+    {
+        return Err(error(StatusCode::NOT_FOUND, "Token not found or already revoked"));
+    }
+    audit::record(
+        &state.pool,
+        Event::new("me.token_revoke")
+            .user(&auth.0)
+            .target("api_token", id)
+            .ip(ip.as_deref()),
+    )
+    .await;
+    Ok(Json(serde_json::json!({})))
+}
+
+/// 掛載 /me、/me/password、/me/tokens。
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/me", get(get_me).patch(update_me))
+        .route("/me/password", post(change_password))
+        .route("/me/tokens", get(list_tokens).post(create_token))
+        .route("/me/tokens/:id", delete(revoke_token))
+}
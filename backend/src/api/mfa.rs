@@ -0,0 +1,412 @@
+//! 兩步驟登入與 TOTP 管理。
+//! /auth/token 密碼正確後，已啟用 TOTP（或 staff 要求 MFA 但尚未設定）的使用者只會拿到短效的 `mfa_token`，
+//! 再以 /auth/mfa/verify（TOTP 驗證碼或復原碼）換取 access/refresh token；尚未設定者先呼叫 /auth/mfa/enroll。
+//! 第二步與密碼共用 per-username 限流與失敗鎖定。已登入的使用者以 /me/mfa/* 啟用、停用 TOTP 與重新產生復原碼。
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+use crate::api::auth::{issue_session, login_event, TokenResponse};
+use crate::audit::{self, Event};
+use crate::auth_extractor::{AuthUser, Require};
+use crate::db::login_lockout;
+use crate::db::mfa as store;
+use crate::db::setting;
+use crate::db::user::{self, UserRow};
+use crate::mfa;
+use crate::permissions::perm;
+use crate::rate_limit::{client_ip, ClientIp};
+use crate::AppState;
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(serde_json::json!({ "error": msg.into() })))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    tracing::warn!("mfa: db error: {}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// /auth/token 需要第二步時的回應（取代 access/refresh token）。
+#[derive(Serialize)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    /// 交給 /auth/mfa/verify（與 /auth/mfa/enroll）的短效 token。
+    pub mfa_token: String,
+    /// true 表示必須先設定 TOTP（staff 要求 MFA，但此帳號尚未啟用）。
+    pub enroll: bool,
+}
+
+/// 第一步（密碼或 OIDC，`method`）通過後決定是否需要第二步；不需要時回 None（直接簽發 token）。
+pub(crate) async fn login_challenge(
+    state: &AppState,
+    row: &UserRow,
+    method: &str,
+) -> Result<Option<MfaChallenge>, ApiError> {
+    let enabled = store::is_enabled(&state.pool, row.id).await.map_err(db_error)?;
+    let enroll = !enabled
+        && setting::get_flag(&state.pool, setting::REQUIRE_MFA)
+            .await
+            .map_err(db_error)?;
+    if !enabled && !enroll {
+        return Ok(None);
+    }
+    let mfa_token = state
+        .jwt
+        .issue_mfa_challenge(row.id, &row.username, enroll, method, state.config.mfa_challenge_ttl_secs)
+        .map_err(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "token issue failed"))?;
+    Ok(Some(MfaChallenge {
+        mfa_required: true,
+        mfa_token,
+        enroll,
+    }))
+}
+
+fn invalid_challenge() -> ApiError {
+    error(StatusCode::UNAUTHORIZED, "MFA token is invalid or expired")
+}
+
+/// 驗證 challenge 並取回使用者；停用或 session 已被撤銷時拒絕。
+/// 回傳的 enroll 在 TOTP 已啟用後一律為 false，同一個 challenge 不能再用來換掉已確認的 secret；
+/// 另回傳第一步的登入方式。
+async fn challenge_user(state: &AppState, mfa_token: &str) -> Result<(UserRow, bool, String), ApiError> {
+    let claims = state.jwt.verify_mfa(mfa_token).map_err(|_| invalid_challenge())?;
+    let row = user::get_row_by_id(&state.pool, claims.user_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(invalid_challenge)?;
+    if !row.token_still_valid(claims.iat) || !row.is_active() {
+        return Err(invalid_challenge());
+    }
+    let enroll = claims.enroll && !store::is_enabled(&state.pool, row.id).await.map_err(db_error)?;
+    Ok((row, enroll, claims.method))
+}
+
+/// 驗證 TOTP 驗證碼（防重放）或未使用的復原碼；`allow_recovery` 為 false 時只接受 TOTP。
+/// 通過時回傳使用的方式（`totp` / `recovery_code`），供稽核紀錄。
+async fn check_code(
+    state: &AppState,
+    user_id: i64,
+    code: &str,
+    allow_recovery: bool,
+) -> Result<Option<&'static str>, ApiError> {
+    let Some(totp) = store::get_totp(&state.pool, user_id).await.map_err(db_error)? else {
+        return Ok(None);
+    };
+    if let Some(step) = mfa::verify_code(&totp.secret, code, totp.last_used_step) {
+        let accepted = if totp.is_confirmed() {
+            store::use_step(&state.pool, user_id, step).await.map_err(db_error)?
+        } else {
+            store::confirm_totp(&state.pool, user_id, step).await.map_err(db_error)?;
+            true
+        };
+        return Ok(accepted.then_some("totp"));
+    }
+    if allow_recovery && totp.is_confirmed() {
+        let accepted = store::use_recovery_code(&state.pool, user_id, &mfa::hash_recovery_code(code))
+            .await
+            .map_err(db_error)?;
+        return Ok(accepted.then_some("recovery_code"));
+    }
+    Ok(None)
+}
+
+/// 產生並儲存新的一組復原碼，回傳明文。
+async fn new_recovery_codes(state: &AppState, user_id: i64) -> Result<Vec<String>, ApiError> {
+    let codes = mfa::generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|c| mfa::hash_recovery_code(c)).collect();
+    store::replace_recovery_codes(&state.pool, user_id, &hashes)
+        .await
+        .map_err(db_error)?;
+    Ok(codes)
+}
+
+#[derive(Serialize)]
+pub struct TotpSetup {
+    /// base32 secret（無法掃描 QR code 時手動輸入）。
+    pub secret: String,
+    /// `otpauth://totp/...`，前端轉成 QR code。
+    pub otpauth_url: String,
+}
+
+/// 產生未確認的 secret；以第一個正確驗證碼確認後才生效。已啟用（並行請求剛確認）時回 409。
+async fn start_enrollment(state: &AppState, user_id: i64, username: &str) -> Result<TotpSetup, ApiError> {
+    let secret = mfa::generate_secret();
+    let otpauth_url = mfa::provisioning_uri(&secret, &state.config.mfa_issuer, username)
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    if !store::start_totp(&state.pool, user_id, &secret)
+        .await
+        .map_err(db_error)?
+    {
+        return Err(error(StatusCode::CONFLICT, "TOTP is already enabled"));
+    }
+    Ok(TotpSetup { secret, otpauth_url })
+}
+
+#[derive(Deserialize)]
+pub struct ChallengeBody {
+    pub mfa_token: String,
+}
+
+/// POST /auth/mfa/enroll：challenge 為 `enroll` 時開始設定 TOTP。
+async fn enroll(
+    State(state): State<AppState>,
+    Json(body): Json<ChallengeBody>,
+) -> Result<Json<TotpSetup>, ApiError> {
+    let (row, enroll, _) = challenge_user(&state, &body.mfa_token).await?;
+    if !enroll {
+        return Err(error(StatusCode::BAD_REQUEST, "MFA is already set up for this account"));
+    }
+    Ok(Json(start_enrollment(&state, row.id, &row.username).await?))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyBody {
+    pub mfa_token: String,
+    /// 6 位數 TOTP 驗證碼或一組復原碼。
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct VerifyResponse {
+    #[serde(flatten)]
+    pub tokens: TokenResponse,
+    /// 剛完成設定時附上的復原碼（只顯示這一次）。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+}
+
+/// POST /auth/mfa/verify：第二步。與密碼登入共用限流與鎖定，錯誤的驗證碼計入失敗次數。
+async fn verify(
+    State(state): State<AppState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(body): Json<VerifyBody>,
+) -> Result<Json<VerifyResponse>, ApiError> {
+    let (row, enroll, method) = challenge_user(&state, &body.mfa_token).await?;
+    let ip = client_ip(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        state.config.forwarded_trust(),
+    );
+    state.limits.login_user.check(&row.username).await?;
+    if login_lockout::locked_until(&state.pool, &row.username)
+        .await
+        .map_err(db_error)?
+        .is_some()
+    {
+        audit::record(
+            &state.pool,
+            login_event(&method, ip.as_deref(), Some("locked_out")).actor(Some(row.id), &row.username),
+        )
+        .await;
+        return Err(error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed login attempts. Try again later.",
+        ));
+    }
+    let Some(factor) = check_code(&state, row.id, &body.code, !enroll).await? else {
+        tracing::info!("auth/mfa: invalid code username={:?}", row.username);
+        login_lockout::record_failure(&state.pool, &row.username, ip.as_deref())
+            .await
+            .map_err(db_error)?;
+        audit::record(
+            &state.pool,
+            login_event(&method, ip.as_deref(), Some("invalid_mfa_code")).actor(Some(row.id), &row.username),
+        )
+        .await;
+        return Err(error(StatusCode::UNAUTHORIZED, "Invalid code"));
+    };
+    login_lockout::record_success(&state.pool, &row.username)
+        .await
+        .map_err(db_error)?;
+    let recovery_codes = if enroll {
+        tracing::info!("auth/mfa: {} enrolled TOTP at login", row.username);
+        Some(new_recovery_codes(&state, row.id).await?)
+    } else {
+        None
+    };
+    let tokens = issue_session(&state, row.id, &row.username)
+        .await
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    audit::record(
+        &state.pool,
+        login_event(&method, ip.as_deref(), None)
+            .actor(Some(row.id), &row.username)
+            .details(serde_json::json!({ "method": method, "mfa": factor, "enrolled": enroll })),
+    )
+    .await;
+    Ok(Json(VerifyResponse { tokens, recovery_codes }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MfaPolicy {
+    /// 所有使用者都必須啟用 TOTP；尚未啟用者下次登入時須先設定。
+    pub require_mfa: bool,
+}
+
+/// GET /auth/mfa/policy（`auth.manage`）
+async fn get_policy(_auth: Require<perm::AuthManage>, State(state): State<AppState>) -> Result<Json<MfaPolicy>, ApiError> {
+    Ok(Json(MfaPolicy {
+        require_mfa: setting::get_flag(&state.pool, setting::REQUIRE_MFA)
+            .await
+            .map_err(db_error)?,
+    }))
+}
+
+/// PUT /auth/mfa/policy（`auth.manage`）
+async fn set_policy(
+    Require(auth, _): Require<perm::AuthManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<MfaPolicy>,
+) -> Result<Json<MfaPolicy>, ApiError> {
+    setting::set_flag(&state.pool, setting::REQUIRE_MFA, body.require_mfa)
+        .await
+        .map_err(db_error)?;
+    tracing::info!("auth/mfa: {} set require_mfa={}", auth.0.username, body.require_mfa);
+    audit::record(
+        &state.pool,
+        Event::new("auth.mfa_policy")
+            .user(&auth.0)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({ "require_mfa": body.require_mfa })),
+    )
+    .await;
+    Ok(Json(body))
+}
+
+#[derive(Serialize)]
+pub struct MfaStatus {
+    /// 已啟用（確認過）TOTP。
+    pub enabled: bool,
+    /// 已開始設定但尚未確認。
+    pub pending: bool,
+    pub recovery_codes_remaining: i64,
+    /// staff 是否要求所有人啟用。
+    pub required: bool,
+}
+
+/// GET /me/mfa
+async fn status(auth: AuthUser, State(state): State<AppState>) -> Result<Json<MfaStatus>, ApiError> {
+    let totp = store::get_totp(&state.pool, auth.0.id).await.map_err(db_error)?;
+    Ok(Json(MfaStatus {
+        enabled: totp.as_ref().is_some_and(|t| t.is_confirmed()),
+        pending: totp.as_ref().is_some_and(|t| !t.is_confirmed()),
+        recovery_codes_remaining: store::recovery_codes_remaining(&state.pool, auth.0.id)
+            .await
+            .map_err(db_error)?,
+        required: setting::get_flag(&state.pool, setting::REQUIRE_MFA)
+            .await
+            .map_err(db_error)?,
+    }))
+}
+
+/// POST /me/mfa/totp：開始設定（已啟用時回 409，須先停用）。
+async fn setup_totp(auth: AuthUser, State(state): State<AppState>) -> Result<Json<TotpSetup>, ApiError> {
+    if store::is_enabled(&state.pool, auth.0.id).await.map_err(db_error)? {
+        return Err(error(StatusCode::CONFLICT, "TOTP is already enabled"));
+    }
+    Ok(Json(start_enrollment(&state, auth.0.id, &auth.0.username).await?))
+}
+
+#[derive(Deserialize)]
+pub struct CodeBody {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    /// 明文復原碼，只顯示這一次。
+    pub recovery_codes: Vec<String>,
+}
+
+/// POST /me/mfa/totp/confirm：以驗證碼確認設定，回傳復原碼。
+async fn confirm_totp(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<CodeBody>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    state.limits.login_user.check(&auth.0.username).await?;
+    let totp = store::get_totp(&state.pool, auth.0.id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::BAD_REQUEST, "Start TOTP setup first"))?;
+    if totp.is_confirmed() {
+        return Err(error(StatusCode::CONFLICT, "TOTP is already enabled"));
+    }
+    if check_code(&state, auth.0.id, &body.code, false).await?.is_none() {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid code"));
+    }
+    tracing::info!("me/mfa: {} enabled TOTP", auth.0.username);
+    audit::record(&state.pool, Event::new("me.mfa_enable").user(&auth.0).ip(ip.as_deref())).await;
+    Ok(Json(RecoveryCodes {
+        recovery_codes: new_recovery_codes(&state, auth.0.id).await?,
+    }))
+}
+
+/// 需要已啟用 TOTP 並提供正確的驗證碼或復原碼。
+async fn require_current_code(state: &AppState, auth: &AuthUser, code: &str) -> Result<(), ApiError> {
+    state.limits.login_user.check(&auth.0.username).await?;
+    if !store::is_enabled(&state.pool, auth.0.id).await.map_err(db_error)? {
+        return Err(error(StatusCode::BAD_REQUEST, "TOTP is not enabled"));
+    }
+    if check_code(state, auth.0.id, code, true).await?.is_none() {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid code"));
+    }
+    Ok(())
+}
+
+/// DELETE /me/mfa/totp：以目前的驗證碼（或復原碼）停用；staff 要求 MFA 時不可停用。
+async fn disable_totp(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<CodeBody>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if setting::get_flag(&state.pool, setting::REQUIRE_MFA)
+        .await
+        .map_err(db_error)?
+    {
+        return Err(error(StatusCode::FORBIDDEN, "MFA is required for all users"));
+    }
+    require_current_code(&state, &auth, &body.code).await?;
+    store::disable(&state.pool, auth.0.id).await.map_err(db_error)?;
+    tracing::info!("me/mfa: {} disabled TOTP", auth.0.username);
+    audit::record(&state.pool, Event::new("me.mfa_disable").user(&auth.0).ip(ip.as_deref())).await;
+    Ok(Json(serde_json::json!({})))
+}
+
+/// POST /me/mfa/recovery-codes：以目前的驗證碼重新產生復原碼（舊的全部失效）。
+async fn regenerate_recovery_codes(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<CodeBody>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    require_current_code(&state, &auth, &body.code).await?;
+    let recovery_codes = new_recovery_codes(&state, auth.0.id).await?;
+    audit::record(&state.pool, Event::new("me.recovery_codes").user(&auth.0).ip(ip.as_deref())).await;
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// 掛載 /auth/mfa/*（第二步與 `auth.manage` 政策）與 /me/mfa/*（自助管理）。
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/auth/mfa/verify", post(verify))
+        .route("/auth/mfa/enroll", post(enroll))
+        .route("/auth/mfa/policy", get(get_policy).put(set_policy))
+        .route("/me/mfa", get(status))
+        .route("/me/mfa/totp", post(setup_totp).delete(disable_totp))
+        .route("/me/mfa/totp/confirm", post(confirm_totp))
+        .route("/me/mfa/recovery-codes", post(regenerate_recovery_codes))
+}
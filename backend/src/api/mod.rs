@@ -1,20 +1,37 @@
-//! REST API 路由彙總：auth（JWT）、containers、images、ports。
-//! 僅 JWT 登入，無 Google 等第三方登入路由。
+//! REST API 路由彙總：audit（稽核紀錄）、auth（JWT）、containers、gpus、images、me（自助帳號）、mfa（兩步驟登入與 TOTP）、oidc（OpenID Connect 登入）、ports、recordings（console 錄影）、transfer（匯出/匯入）、users（使用者管理）。
+//! 本機帳號以 JWT 登入；第三方登入只支援通用的 OIDC，無 Google 專用路由。
 
+mod audit;
 mod auth;
-mod containers;
+pub(crate) mod containers;
+mod gpus;
 mod images;
+mod me;
+mod mfa;
+mod oidc;
 mod ports;
+mod recordings;
+mod transfer;
+mod users;
 
 use axum::Router;
 
+use crate::config::Config;
 use crate::AppState;
 
-/// 合併所有 REST 子路由，掛在 /api 下。
-pub fn router() -> Router<AppState> {
+/// 合併所有 REST 子路由，掛在 /api 下；匯入上傳上限取自 `config`。
+pub fn router(config: &Config) -> Router<AppState> {
     Router::new()
+        .merge(audit::router())
         .merge(auth::router())
         .merge(containers::router())
+        .merge(gpus::router())
         .merge(images::router())
+        .merge(me::router())
+        .merge(mfa::router())
+        .merge(oidc::router())
         .merge(ports::router())
+        .merge(recordings::router())
+        .merge(transfer::router(config))
+        .merge(users::router())
 }
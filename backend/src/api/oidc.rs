@@ -0,0 +1,277 @@
+//! OIDC 登入端點：/auth/oidc/login 導向 IdP，/auth/oidc/callback 驗證後依 `sub` 自動建立或更新使用者，
+//! 簽發與 /auth/token 相同的 access/refresh token，並以 URL fragment 帶回前端（OIDC_POST_LOGIN_URL）。
+//! state 同時存在 SQLite 與 HttpOnly cookie，callback 兩者需一致，避免 login CSRF。
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+
+use crate::api::auth::{issue_session, login_event, LoginResponse};
+use crate::api::mfa::login_challenge;
+use crate::audit;
+use crate::db::oidc_state;
+use crate::db::user::{self, UserRow};
+use crate::oidc::{self, Identity, OidcClient};
+use crate::permissions::Role;
+use crate::rate_limit::{client_ip, ClientIp};
+use crate::AppState;
+
+const STATE_COOKIE: &str = "ddm_oidc_state";
+/// 從導向 IdP 到回呼之間允許的時間。
+const STATE_TTL_SECS: i64 = 600;
+
+fn state_cookie(value: &str, max_age: i64, secure: bool) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{}",
+        STATE_COOKIE,
+        value,
+        max_age,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+fn not_configured() -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "detail": "OIDC login is not configured." })),
+    )
+}
+
+/// GET /auth/oidc/login：產生 state、nonce 與 PKCE verifier，導向 IdP。
+async fn login(
+    State(state): State<AppState>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let client = state.oidc.as_ref().ok_or_else(not_configured)?;
+    let ip = client_ip(
+        &headers,
+        peer.map(|ConnectInfo(addr)| addr),
+        state.config.forwarded_trust(),
+    );
+    state
+        .limits
+        .login_ip
+        .check(ip.as_deref().unwrap_or("unknown"))
+        .await?;
+    let (login_state, nonce, verifier) = (oidc::random_token(), oidc::random_token(), oidc::random_token());
+    let url = client
+        .authorization_url(&login_state, &nonce, &verifier)
+        .await
+        .map_err(|e| {
+            tracing::warn!("oidc: {}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(serde_json::json!({ "detail": "Identity provider is unavailable." })),
+            )
+        })?;
+    oidc_state::insert(&state.pool, &login_state, &verifier, &nonce, STATE_TTL_SECS)
+        .await
+        .map_err(|e| {
+            tracing::warn!("oidc: db error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "detail": "database error" })),
+            )
+        })?;
+    let cookie = state_cookie(&login_state, STATE_TTL_SECS, client.is_https());
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&url)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    /// IdP 回報的錯誤（例如使用者拒絕授權）。
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// 依 `sub` 找到或建立使用者；有設定群組對應時同步角色（staff 群組對應 admin），並更新 email。
+/// username 已被其他帳號使用時改用 `<username>-<sub 雜湊前 8 碼>`，不會接管既有帳號。
+async fn provision(state: &AppState, client: &OidcClient, identity: &Identity) -> Result<UserRow, sqlx::Error> {
+    let pool = &state.pool;
+    let is_staff = client.staff_for(&identity.groups);
+    let role = client.role_for(&identity.groups);
+    if let Some(row) = user::get_by_external_id(pool, oidc::PROVIDER, &identity.sub).await? {
+        // 沒有角色對應時，staff 身分改變才改回該身分的預設角色
+        let role = role.or_else(|| {
+            is_staff
+                .filter(|&is_staff| is_staff != (row.is_staff != 0))
+                .map(Role::default_for)
+        });
+        if let Some(role) = role {
+            user::set_role(pool, row.id, role).await?;
+        }
+        if identity.email.is_some() && identity.email != row.email {
+            user::set_email(pool, row.id, identity.email.as_deref()).await?;
+        }
+        return Ok(user::get_row_by_id(pool, row.id).await?.unwrap_or(row));
+    }
+
+    let role = role.unwrap_or(if is_staff == Some(true) { Role::Admin } else { client.default_role() });
+    let suffix = &hex::encode(Sha256::digest(identity.sub.as_bytes()))[..8];
+    let candidates = [identity.username.clone(), format!("{}-{}", identity.username, suffix)];
+    let mut last_err = None;
+    for username in &candidates {
+        match user::create_external_user(
+            pool,
+            oidc::PROVIDER,
+            &identity.sub,
+            username,
+            identity.email.as_deref(),
+            role,
+        )
+        .await
+        {
+            Ok(id) => {
+                tracing::info!(
+                    "oidc: provisioned user {:?} (id={}, sub={}, role={})",
+                    username,
+                    id,
+                    identity.sub,
+                    role.as_str()
+                );
+                return user::get_row_by_id(pool, id).await?.ok_or(sqlx::Error::RowNotFound);
+            }
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => last_err = Some(sqlx::Error::Database(e)),
+            Err(e) => return Err(e),
+        }
+    }
+    // 同一個 sub 同時登入兩次時，另一個請求可能已先建立
+    match user::get_by_external_id(pool, oidc::PROVIDER, &identity.sub).await? {
+        Some(row) => Ok(row),
+        None => Err(last_err.unwrap_or(sqlx::Error::RowNotFound)),
+    }
+}
+
+/// callback 的驗證流程；已啟用 MFA（或 staff 要求 MFA）時回傳 challenge 而非 token。
+/// 失敗時回傳導向前端的錯誤代碼（已知使用者時一併回傳，供稽核紀錄）。
+async fn complete_login(
+    state: &AppState,
+    client: &OidcClient,
+    headers: &HeaderMap,
+    q: &CallbackQuery,
+) -> Result<(UserRow, LoginResponse), (&'static str, Option<UserRow>)> {
+    if let Some(error) = &q.error {
+        tracing::info!("oidc: IdP returned error {:?}", error);
+        return Err(("access_denied", None));
+    }
+    let (Some(code), Some(login_state)) = (q.code.as_deref(), q.state.as_deref()) else {
+        return Err(("invalid_request", None));
+    };
+    if cookie_value(headers, STATE_COOKIE) != Some(login_state) {
+        tracing::info!("oidc: state cookie missing or mismatched");
+        return Err(("invalid_state", None));
+    }
+    let (verifier, nonce) = match oidc_state::take(&state.pool, login_state).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => return Err(("invalid_state", None)),
+        Err(e) => {
+            tracing::warn!("oidc: db error: {}", e);
+            return Err(("server_error", None));
+        }
+    };
+    let identity = client.authenticate(code, &verifier, &nonce).await.map_err(|e| {
+        tracing::warn!("oidc: login failed: {}", e);
+        ("login_failed", None)
+    })?;
+    let row = provision(state, client, &identity).await.map_err(|e| {
+        tracing::warn!("oidc: provisioning sub={} failed: {}", identity.sub, e);
+        ("server_error", None)
+    })?;
+    if !row.is_active() {
+        tracing::info!("oidc: disabled user {:?} (sub={})", row.username, identity.sub);
+        return Err(("account_disabled", Some(row)));
+    }
+    match login_challenge(state, &row, "oidc").await {
+        Ok(Some(challenge)) => {
+            tracing::info!("oidc: {} passed the IdP, MFA required (sub={})", row.username, identity.sub);
+            return Ok((row, LoginResponse::Mfa(challenge)));
+        }
+        Ok(None) => {}
+        Err(_) => return Err(("server_error", Some(row))),
+    }
+    let tokens = match issue_session(state, row.id, &row.username).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            tracing::warn!("oidc: {}", e);
+            return Err(("server_error", Some(row)));
+        }
+    };
+    tracing::info!("oidc: {} signed in (sub={})", row.username, identity.sub);
+    Ok((row, LoginResponse::Tokens(tokens)))
+}
+
+/// GET /auth/oidc/callback：驗證 state（cookie 與 DB）、換 token、驗證 ID token、建立/更新使用者並簽發 JWT。
+/// 成功導向 `OIDC_POST_LOGIN_URL#access_token=…&refresh_token=…`；需要 MFA 時導向 `…#mfa_token=…&enroll=…`，
+/// 由前端以 /auth/mfa/verify 完成登入；失敗導向 `…#error=<code>`。
+async fn callback(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Query(q): Query<CallbackQuery>,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let client = state.oidc.as_ref().ok_or_else(not_configured)?;
+    let clear_cookie = state_cookie("", 0, client.is_https());
+    let target = &state.config.oidc_post_login_url;
+    match complete_login(&state, client, &headers, &q).await {
+        Ok((row, LoginResponse::Tokens(tokens))) => {
+            audit::record(
+                &state.pool,
+                login_event("oidc", ip.as_deref(), None).actor(Some(row.id), &row.username),
+            )
+            .await;
+            let url = format!(
+                "{}#access_token={}&refresh_token={}",
+                target, tokens.access_token, tokens.refresh_token
+            );
+            Ok(([(header::SET_COOKIE, clear_cookie)], Redirect::to(&url)).into_response())
+        }
+        // 與密碼登入相同：成功的 auth.login 由 /auth/mfa/verify 記錄
+        Ok((_, LoginResponse::Mfa(challenge))) => {
+            let url = format!("{}#mfa_token={}&enroll={}", target, challenge.mfa_token, challenge.enroll);
+            Ok(([(header::SET_COOKIE, clear_cookie)], Redirect::to(&url)).into_response())
+        }
+        Err((code, row)) => {
+            let event = login_event("oidc", ip.as_deref(), Some(code));
+            let event = match &row {
+                Some(row) => event.actor(Some(row.id), &row.username),
+                None => event,
+            };
+            audit::record(&state.pool, event).await;
+            Ok((
+                [(header::SET_COOKIE, clear_cookie)],
+                Redirect::to(&format!("{}#error={}", target, code)),
+            )
+                .into_response())
+        }
+    }
+}
+
+/// 掛載 /auth/oidc/login、/auth/oidc/callback。
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/auth/oidc/login", get(login))
+        .route("/auth/oidc/callback", get(callback))
+}
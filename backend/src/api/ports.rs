@@ -1,15 +1,20 @@
 //! 埠位與環境檢查 API：取得可用埠、檢查埠是否被佔用、NVIDIA Docker 是否可用、是否為 Linux。
-//! 對應 Django 的 free_ports、port_check、nvdocker、linux 等。
+//! 對應 Django 的 free_ports、port_check、nvdocker、linux 等。皆需登入；
+//! 掃描 host port 與觸發 NVIDIA 探測的端點另有 per-user 限流（見 `rate_limit::Limits`）。
 
 use axum::{
     extract::{Query, State},
-    routing::get,
+    routing::{get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::auth_extractor::{AuthUser, Require};
+use crate::db::port_reservation;
 use crate::docker;
+use crate::docker::nvidia::NvidiaStatus;
 use crate::docker::ports;
+use crate::permissions::{perm, Permission};
 use crate::AppState;
 
 #[derive(Serialize)]
@@ -21,11 +26,8 @@ pub struct FreePortsResponse {
 pub struct PortCheckResponse {
     pub port: u16,
     pub is_used: bool,
-}
-
-#[derive(Serialize)]
-pub struct NvidiaDockerResponse {
-    pub nvidia_docker_available: bool,
+    /// 已被待處理的建立/還原請求保留（也計入 is_used）。
+    pub reserved: bool,
 }
 
 #[derive(Serialize)]
@@ -49,9 +51,11 @@ pub struct PortCheckQuery {
 }
 
 async fn free_ports(
+    auth: AuthUser,
     State(state): State<AppState>,
     Query(q): Query<FreePortsQuery>,
 ) -> Result<Json<FreePortsResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    state.limits.free_ports.check(&auth.0.id.to_string()).await?;
     let count = q.count.unwrap_or(30);
     if count == 0 {
         return Err((
@@ -59,9 +63,21 @@ async fn free_ports(
             Json(serde_json::json!({ "error": "Count must be a positive integer" })),
         ));
     }
+    let count = count.min(state.config.free_ports_max);
+    let reserved = port_reservation::active_ports(&state.pool)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?;
     let free_ports = docker::find_multiple_free_ports(
         &state.docker,
-        &state.config.host_for_port_check,
+        &state.containers,
+        &ports::PortPolicy::from_config(&state.config),
+        &reserved,
+        &ports::check_hosts(&state.config),
         count,
     )
     .await
@@ -75,17 +91,27 @@ async fn free_ports(
 }
 
 async fn check_port(
+    auth: AuthUser,
     State(state): State<AppState>,
     Query(q): Query<PortCheckQuery>,
 ) -> Result<Json<PortCheckResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    state.limits.port_check.check(&auth.0.id.to_string()).await?;
     let port = q.port.ok_or_else(|| {
         (
             axum::http::StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": "Port parameter is missing" })),
         )
     })?;
-    let in_use_host = ports::check_port_in_use(&state.config.host_for_port_check, port);
-    let in_use_container = docker::is_port_used_by_container(&state.docker, port)
+    let in_use_host = ports::check_port_in_use(&ports::check_hosts(&state.config), port).await;
+    let in_use_container = docker::port_used_by_container(&state.docker, &state.containers, port)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?;
+    let reserved = port_reservation::is_reserved(&state.pool, port)
         .await
         .map_err(|e| {
             (
@@ -95,29 +121,53 @@ async fn check_port(
         })?;
     Ok(Json(PortCheckResponse {
         port,
-        is_used: in_use_host || in_use_container,
+        is_used: in_use_host || in_use_container || reserved,
+        reserved,
     }))
 }
 
+/// 回傳探測結果；不可用時維持 503 以相容既有前端。
+/// 有 `system.manage` 在快取逾期時會重新探測，其他使用者只拿背景刷新的最近結果，不會觸發建立探測容器。
 async fn nvdocker_check(
+    auth: AuthUser,
     State(state): State<AppState>,
-) -> Result<Json<NvidiaDockerResponse>, (axum::http::StatusCode, Json<NvidiaDockerResponse>)> {
-    let available = docker::nvidia::can_use_nvidia_docker(&state.docker).await;
-    if available {
-        Ok(Json(NvidiaDockerResponse {
-            nvidia_docker_available: true,
-        }))
+) -> Result<Json<NvidiaStatus>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let status = if auth.0.can(Permission::SystemManage) {
+        state.nvidia.get().await
+    } else {
+        state.nvidia.latest().await.ok_or_else(|| {
+            (
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "error": "NVIDIA probe has not completed yet" })),
+            )
+        })?
+    };
+    nvidia_response(status)
+}
+
+fn nvidia_response(
+    status: NvidiaStatus,
+) -> Result<Json<NvidiaStatus>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    if status.nvidia_docker_available {
+        Ok(Json(status))
     } else {
         Err((
             axum::http::StatusCode::SERVICE_UNAVAILABLE,
-            Json(NvidiaDockerResponse {
-                nvidia_docker_available: false,
-            }),
+            Json(serde_json::to_value(status).unwrap_or_default()),
         ))
     }
 }
 
-async fn linux_check() -> Json<LinuxCheckResponse> {
+/// POST /nvdocker/refresh：強制重新探測（`system.manage`）。
+async fn nvdocker_refresh(
+    Require(auth, _): Require<perm::SystemManage>,
+    State(state): State<AppState>,
+) -> Result<Json<NvidiaStatus>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    state.limits.nvdocker_refresh.check(&auth.0.id.to_string()).await?;
+    Ok(Json(state.nvidia.refresh().await))
+}
+
+async fn linux_check(_auth: AuthUser) -> Json<LinuxCheckResponse> {
     Json(LinuxCheckResponse {
         is_linux: docker::is_linux(),
     })
@@ -128,5 +178,6 @@ pub fn router() -> Router<AppState> {
         .route("/ports", get(free_ports))
         .route("/ports/check", get(check_port))
         .route("/nvdocker/check", get(nvdocker_check))
+        .route("/nvdocker/refresh", post(nvdocker_refresh))
         .route("/linux/check", get(linux_check))
 }
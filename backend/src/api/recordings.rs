@@ -0,0 +1,158 @@
+//! Console 錄影：列出某容器的錄影、查看 metadata、下載或播放 `.cast`（asciicast v2），`audit.manage` 可刪除。
+//! 有 `audit.read` 看得到所有錄影，其他使用者只看得到自己的 session。
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use tokio::io::AsyncReadExt;
+
+use crate::audit::{self, Event};
+use crate::auth_extractor::{AuthUser, Require};
+use crate::db::recording::{self, Recording};
+use crate::permissions::{perm, Permission};
+use crate::rate_limit::ClientIp;
+use crate::AppState;
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(serde_json::json!({ "error": msg.into() })))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    tracing::warn!("recordings: db error: {}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+/// 下載時每次讀取的大小。
+const READ_CHUNK: usize = 64 * 1024;
+
+/// 取得看得到的錄影；別人的錄影與不存在一樣回 404。
+async fn visible(state: &AppState, auth: &AuthUser, id: &str) -> Result<Recording, ApiError> {
+    match recording::get(&state.pool, id).await.map_err(db_error)? {
+        Some(rec) if auth.0.can(Permission::AuditRead) || rec.user_id == Some(auth.0.id) => Ok(rec),
+        _ => Err(error(StatusCode::NOT_FOUND, "recording not found")),
+    }
+}
+
+/// GET /containers/:id/recordings（:id 可為完整 ID、前綴或名稱；容器刪除後仍可查）
+async fn list_recordings(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(container): Path<String>,
+) -> Result<Json<Vec<Recording>>, ApiError> {
+    let user_id = (!auth.0.can(Permission::AuditRead)).then_some(auth.0.id);
+    let recordings = recording::list_for_container(&state.pool, &container, user_id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(recordings))
+}
+
+/// GET /recordings/:id
+async fn get_recording(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Recording>, ApiError> {
+    Ok(Json(visible(&state, &auth, &id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct CastQuery {
+    /// 有值時以附件下載；否則 inline，供 asciinema player 直接載入。
+    #[serde(default)]
+    pub download: Option<String>,
+}
+
+/// GET /recordings/:id/cast[?download=1]：以串流回傳錄影檔；進行中的 session 回傳目前已寫入的部分。
+async fn get_cast(
+    auth: AuthUser,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<String>,
+    Query(q): Query<CastQuery>,
+) -> Result<Response, ApiError> {
+    let rec = visible(&state, &auth, &id).await?;
+    let path = crate::recording::file_path(&state.config, &rec.file);
+    let file = tokio::fs::File::open(&path).await.map_err(|e| {
+        tracing::warn!("recordings: cannot open {}: {}", path.display(), e);
+        error(StatusCode::NOT_FOUND, "recording file missing")
+    })?;
+    audit::record(
+        &state.pool,
+        Event::new("recording.download")
+            .user(&auth.0)
+            .target("recording", &rec.id)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({ "container_id": rec.container_id })),
+    )
+    .await;
+    let stream = futures_util::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = Vec::with_capacity(READ_CHUNK);
+        match (&mut file).take(READ_CHUNK as u64).read_to_end(&mut buf).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(Bytes::from(buf)), Some(file))),
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    let disposition = if q.download.is_some() { "attachment" } else { "inline" };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-asciicast".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("{}; filename=\"{}.cast\"", disposition, rec.id),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+/// DELETE /recordings/:id（`audit.manage`）
+async fn delete_recording(
+    Require(auth, _): Require<perm::AuditManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let rec = visible(&state, &auth, &id).await?;
+    if rec.ended_at.is_none() {
+        return Err(error(StatusCode::CONFLICT, "session is still being recorded"));
+    }
+    let path = crate::recording::file_path(&state.config, &rec.file);
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            tracing::warn!("recordings: cannot delete {}: {}", path.display(), e);
+            return Err(error(StatusCode::INTERNAL_SERVER_ERROR, "failed to delete recording file"));
+        }
+    }
+    recording::delete(&state.pool, &rec.id).await.map_err(db_error)?;
+    audit::record(
+        &state.pool,
+        Event::new("recording.delete")
+            .user(&auth.0)
+            .target("recording", &rec.id)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({ "container_id": rec.container_id, "username": rec.username })),
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// 掛載 /containers/:id/recordings 與 /recordings/:id。
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/containers/:id/recordings", get(list_recordings))
+        .route("/recordings/:id", get(get_recording).delete(delete_recording))
+        .route("/recordings/:id/cast", get(get_cast))
+}
@@ -0,0 +1,393 @@
+//! 容器匯出/匯入 API：將容器 commit 後以 tarball 串流下載，或上傳 tarball 還原成容器。
+//! 匯出經佇列 worker commit（見 `Job::ExportContainer`），下載與上傳皆以串流處理，不整份載入記憶體。
+//! 上傳的 tarball 先寫到暫存檔，移除其中宣告的 tag（見 `docker::archive`）後才載入，並以 `docker::import_image_ref` 重新 tag。
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, RequestExt, Router,
+};
+use bollard::query_parameters::{
+    ImportImageOptions, RemoveImageOptions, RemoveImageOptionsBuilder, TagImageOptionsBuilder,
+};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+
+use crate::api::containers::{
+    authorize_container, check_quota, container_event, precheck_gpus, rejected, require_container_options,
+    reserve_port, validate_new_container,
+};
+use crate::audit;
+use crate::config::Config;
+use crate::auth_extractor::{AuthUser, Require};
+use crate::db::port_reservation;
+use crate::docker;
+use crate::docker::gpu::GpuRequest;
+use crate::permissions::{perm, Permission};
+use crate::rate_limit::ClientIp;
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct ExportBody {
+    pub id: String,
+}
+
+#[derive(Serialize)]
+pub struct ExportResponse {
+    pub task_id: String,
+    pub image: String,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    pub container_name: String,
+    pub ssh: String,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub nvdocker: bool,
+    /// nvdocker 時配置的 GPU 數量（預設 1）。
+    #[serde(default)]
+    pub gpu_count: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    pub container_name: String,
+    pub image: String,
+    pub task_id: String,
+}
+
+/// task_id 由 `queue::new_task_id` 產生（十六進位），拒絕其他字元以免被拼進任意映像名稱。
+fn export_image_for(task_id: &str) -> Result<String, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    if task_id.is_empty() || !task_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err((
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "invalid task id" })),
+        ));
+    }
+    Ok(docker::export_image_ref(task_id))
+}
+
+/// 檢查匯出映像存在且屬於目前使用者；回傳映像上記錄的容器名稱（供下載檔名）。
+async fn authorize_export(
+    state: &AppState,
+    auth: &AuthUser,
+    image: &str,
+) -> Result<Option<String>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let inspect = state.docker.inspect_image(image).await.map_err(|_| {
+        (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Export not found or not ready yet" })),
+        )
+    })?;
+    let labels = inspect.config.as_ref().and_then(|c| c.labels.as_ref());
+    if !docker::user_can_access(&auth.0, docker::owner_from_labels(labels)) {
+        return Err((
+            axum::http::StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "You do not have access to this export" })),
+        ));
+    }
+    Ok(labels.and_then(|l| l.get(docker::LABEL_NAME)).cloned())
+}
+
+/// POST /containers/export：將 commit 任務丟進佇列，完成後會推送 EXPORTED 通知。匯出三個端點都需要 `images.manage`。
+async fn export_container(
+    Require(auth, _): Require<perm::ImagesManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<ExportBody>,
+) -> Result<Json<ExportResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let event = container_event("export", &auth, &body.id, ip.as_deref());
+    if let Err(e) = authorize_container(&state, &auth, &body.id).await {
+        audit::record(&state.pool, rejected(event, e.0)).await;
+        return Err(e);
+    }
+    let task_id = match crate::queue::enqueue_export_container(&state.config.redis_url, &body.id, auth.0.id).await {
+        Ok(task_id) => task_id,
+        Err(e) => {
+            audit::record(&state.pool, event.failed()).await;
+            return Err((
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e })),
+            ));
+        }
+    };
+    audit::record(
+        &state.pool,
+        event.details(serde_json::json!({ "task_id": &task_id })),
+    )
+    .await;
+    Ok(Json(ExportResponse {
+        image: docker::export_image_ref(&task_id),
+        task_id,
+    }))
+}
+
+/// GET /containers/export/:task_id：以 export_image 串流回傳 tarball（docker save 格式）。
+async fn download_export(
+    Require(auth, _): Require<perm::ImagesManage>,
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+) -> Result<Response, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let image = export_image_for(&task_id)?;
+    let name = authorize_export(&state, &auth, &image).await?;
+    let filename = format!("{}-{}.tar", name.as_deref().unwrap_or("container"), task_id);
+    let stream = state.docker.export_image(&image);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+/// DELETE /containers/export/:task_id：下載完成後移除匯出映像。
+async fn delete_export(
+    Require(auth, _): Require<perm::ImagesManage>,
+    State(state): State<AppState>,
+    Path(task_id): Path<String>,
+) -> Result<Json<serde_json::Value>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let image = export_image_for(&task_id)?;
+    authorize_export(&state, &auth, &image).await?;
+    state
+        .docker
+        .remove_image(&image, None::<RemoveImageOptions>, None)
+        .await
+        .map_err(|e| {
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+        })?;
+    Ok(Json(serde_json::json!({})))
+}
+
+fn upload_error(status: axum::http::StatusCode, msg: impl Into<String>) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+    (status, Json(serde_json::json!({ "error": msg.into() })))
+}
+
+fn io_error(e: std::io::Error) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+    tracing::warn!("import: temp file error: {}", e);
+    upload_error(axum::http::StatusCode::INTERNAL_SERVER_ERROR, "failed to store upload")
+}
+
+/// 上傳中斷回 400；超過 IMPORT_MAX_MB（`DefaultBodyLimit`）回 413。
+fn body_error(e: axum::Error) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+    let inner = e.into_inner();
+    if inner.is::<http_body_util::LengthLimitError>() {
+        return upload_error(axum::http::StatusCode::PAYLOAD_TOO_LARGE, "Upload exceeds IMPORT_MAX_MB");
+    }
+    upload_error(axum::http::StatusCode::BAD_REQUEST, inner.to_string())
+}
+
+/// 上傳內容寫入暫存檔（manifest.json 可能在 tarball 結尾，須整份收完才能檢查）。
+async fn spool_upload(body: Body) -> Result<tempfile::NamedTempFile, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let tmp = tempfile::NamedTempFile::new().map_err(io_error)?;
+    let mut file = tokio::fs::File::from_std(tmp.reopen().map_err(io_error)?);
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(body_error)?;
+        file.write_all(&chunk).await.map_err(io_error)?;
+    }
+    file.flush().await.map_err(io_error)?;
+    Ok(tmp)
+}
+
+/// 移除 tarball 內的 tag，寫到另一個暫存檔；多個映像或不是 docker save 格式時回 400。
+async fn strip_tags(
+    upload: tempfile::NamedTempFile,
+) -> Result<tempfile::NamedTempFile, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    tokio::task::spawn_blocking(move || {
+        let clean = tempfile::NamedTempFile::new().map_err(io_error)?;
+        let src = std::io::BufReader::new(upload.reopen().map_err(io_error)?);
+        let dst = std::io::BufWriter::new(clean.reopen().map_err(io_error)?);
+        docker::archive::strip_image_tags(src, dst)
+            .map_err(|e| upload_error(axum::http::StatusCode::BAD_REQUEST, e))?;
+        Ok(clean)
+    })
+    .await
+    .map_err(|_| upload_error(axum::http::StatusCode::INTERNAL_SERVER_ERROR, "import failed"))?
+}
+
+/// 以 64 KiB 為單位讀取暫存檔，供 import_image_stream 使用。
+fn file_chunks(file: tokio::fs::File) -> impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> {
+    futures_util::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = Vec::with_capacity(64 * 1024);
+        match (&mut file).take(64 * 1024).read_to_end(&mut buf).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(Bytes::from(buf)), Some(file))),
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+/// 載入上傳的 tarball（不帶任何 tag），再 tag 成 `image`。
+async fn load_image(
+    state: &AppState,
+    body: Body,
+    image: &str,
+) -> Result<(), (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let clean = strip_tags(spool_upload(body).await?).await?;
+    let file = tokio::fs::File::from_std(clean.reopen().map_err(io_error)?);
+    let mut progress = state
+        .docker
+        .import_image_stream(ImportImageOptions::default(), file_chunks(file), None);
+    let mut loaded: Vec<String> = Vec::new();
+    while let Some(item) = progress.next().await {
+        let info = item.map_err(|e| upload_error(axum::http::StatusCode::BAD_REQUEST, e.to_string()))?;
+        if let Some(detail) = info.error_detail.and_then(|d| d.message) {
+            return Err(upload_error(axum::http::StatusCode::BAD_REQUEST, detail));
+        }
+        // 沒有 tag 時 docker load 回傳 "Loaded image ID: sha256:..."
+        if let Some(line) = info.stream.as_deref().map(str::trim) {
+            if let Some(id) = line
+                .strip_prefix("Loaded image ID: ")
+                .or_else(|| line.strip_prefix("Loaded image: "))
+            {
+                if !loaded.iter().any(|l| l == id) {
+                    loaded.push(id.to_string());
+                }
+            }
+        }
+    }
+    let loaded = match loaded.as_slice() {
+        [one] => one,
+        [] => return Err(upload_error(axum::http::StatusCode::BAD_REQUEST, "Tarball did not contain an image")),
+        _ => {
+            return Err(upload_error(
+                axum::http::StatusCode::BAD_REQUEST,
+                "Tarball must contain exactly one image",
+            ))
+        }
+    };
+    let (repo, tag) = image.rsplit_once(':').unwrap_or((image, "latest"));
+    let opts = TagImageOptionsBuilder::default().repo(repo).tag(tag).build();
+    state.docker.tag_image(loaded, Some(opts)).await.map_err(|e| {
+        tracing::warn!("import: failed to tag {} as {}: {}", loaded, image, e);
+        upload_error(axum::http::StatusCode::INTERNAL_SERVER_ERROR, "failed to tag imported image")
+    })
+}
+
+/// 匯入失敗時移除已 tag 的映像（忽略錯誤）。
+async fn remove_import_image(state: &AppState, image: &str) {
+    let opts = RemoveImageOptionsBuilder::default().force(true).build();
+    if let Err(e) = state.docker.remove_image(image, Some(opts), None).await {
+        tracing::warn!("import: failed to remove {}: {}", image, e);
+    }
+}
+
+/// POST /containers/import：request body 為匯出的 tarball（上限 IMPORT_MAX_MB），串流給 import_image 載入，
+/// 再將「由映像建立容器」任務丟進佇列（重新套用管理 label，擁有者為目前使用者）。
+/// 會載入任意映像，需要 `images.manage` 與 `containers.create`。載入或排入佇列失敗時移除映像並記錄 failure。
+async fn import_container(
+    Require(auth, _): Require<perm::ImagesManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Query(q): Query<ImportQuery>,
+    request: Request,
+) -> Result<Json<ImportResponse>, (axum::http::StatusCode, Json<serde_json::Value>)> {
+    let allowed = auth
+        .require(Permission::ContainersCreate)
+        .and_then(|_| require_container_options(&auth, q.privileged, q.nvdocker));
+    if let Err(e) = allowed {
+        let event = container_event("import", &auth, &q.container_name, ip.as_deref())
+            .details(serde_json::json!({ "privileged": q.privileged, "nvdocker": q.nvdocker }));
+        audit::record(&state.pool, rejected(event, e.0)).await;
+        return Err(e);
+    }
+    let (name, ssh_port) = validate_new_container(&state, &q.container_name, &q.ssh).await?;
+    check_quota(&state, &auth).await?;
+    let gpus = GpuRequest {
+        count: q.gpu_count,
+        device_ids: None,
+    };
+    if q.nvdocker {
+        precheck_gpus(&state, &gpus).await?;
+    }
+    // 上傳大型 tarball 期間先保留 ssh port，避免載入完成後才發現被其他請求佔走
+    let task_id = crate::queue::new_task_id();
+    reserve_port(&state, ssh_port, &task_id, auth.0.id).await?;
+    let image = docker::import_image_ref(auth.0.id, &task_id);
+    let details = serde_json::json!({
+        "task_id": &task_id,
+        "image": &image,
+        "ssh_port": ssh_port,
+        "privileged": q.privileged,
+        "nvdocker": q.nvdocker,
+    });
+    let event = container_event("import", &auth, &name, ip.as_deref());
+    if let Err(e) = load_image(&state, request.into_limited_body(), &image).await {
+        let _ = port_reservation::release(&state.pool, ssh_port, &task_id).await;
+        audit::record(&state.pool, event.details(details).failed()).await;
+        return Err(e);
+    }
+    if let Err(e) = crate::queue::enqueue_restore_image(
+        &state.config.redis_url,
+        &task_id,
+        &state.config.docker_network,
+        &image,
+        ssh_port,
+        &name,
+        q.privileged,
+        q.nvdocker,
+        gpus,
+        auth.0.id,
+    )
+    .await
+    {
+        let _ = port_reservation::release(&state.pool, ssh_port, &task_id).await;
+        remove_import_image(&state, &image).await;
+        audit::record(&state.pool, event.details(details).failed()).await;
+        return Err((
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e })),
+        ));
+    }
+    audit::record(&state.pool, event.details(details)).await;
+    Ok(Json(ImportResponse {
+        container_name: name,
+        image,
+        task_id,
+    }))
+}
+
+/// 掛載 /containers/export（POST 建立匯出、GET 下載、DELETE 清除）與 /containers/import（上傳上限 IMPORT_MAX_MB）。
+pub fn router(config: &Config) -> Router<AppState> {
+    Router::new()
+        .route("/containers/export", post(export_container))
+        .route(
+            "/containers/export/:task_id",
+            get(download_export).delete(delete_export),
+        )
+        .route(
+            "/containers/import",
+            post(import_container).layer(DefaultBodyLimit::max(config.import_max_bytes)),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spool_upload_enforces_body_limit() {
+        // 與 `RequestExt::into_limited_body` 相同的包法
+        let limited = |len: usize| Body::new(http_body_util::Limited::new(Body::from(vec![0u8; len]), 8));
+        let err = spool_upload(limited(16)).await.unwrap_err();
+        assert_eq!(err.0, axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+        let tmp = spool_upload(limited(8)).await.unwrap();
+        assert_eq!(std::fs::metadata(tmp.path()).unwrap().len(), 8);
+    }
+}
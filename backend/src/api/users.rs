@@ -0,0 +1,348 @@
+//! 使用者管理 API（需要 `users.manage` 權限）：列出、建立、查詢、更新（email、role（舊欄位 is_staff 對應 admin）、is_active、重設密碼）、刪除。
+//! 停用或重設密碼會撤銷該使用者所有 session；刪除時可選擇停止或移轉其容器；遺失裝置時可重設 MFA。
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::api::auth::revoke_user_sessions;
+use crate::api::containers::containers_snapshot;
+use crate::audit::{self, Event};
+use crate::auth_extractor::Require;
+use crate::db::mfa;
+use crate::db::user::{self, User};
+use crate::docker;
+use crate::permissions::{perm, Permission, Role};
+use crate::rate_limit::ClientIp;
+use crate::AppState;
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn error(status: StatusCode, msg: impl Into<String>) -> ApiError {
+    (status, Json(serde_json::json!({ "error": msg.into() })))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    tracing::warn!("users: db error: {}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
+
+async fn hash(password: String) -> Result<String, ApiError> {
+    tokio::task::spawn_blocking(move || user::hash_password(&password))
+        .await
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn find_user(state: &AppState, id: i64) -> Result<User, ApiError> {
+    user::get_by_id(&state.pool, id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "User not found"))
+}
+
+/// GET /users
+async fn list_users(
+    _auth: Require<perm::UsersManage>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<User>>, ApiError> {
+    Ok(Json(user::list_users(&state.pool).await.map_err(db_error)?))
+}
+
+#[derive(Deserialize)]
+pub struct CreateUserBody {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    /// 舊欄位：未指定 role 時 true 等同 admin。
+    #[serde(default)]
+    pub is_staff: bool,
+    /// 未指定時 staff 為 admin，其餘為 user。
+    #[serde(default)]
+    pub role: Option<Role>,
+}
+
+/// POST /users：帳號重複回 409。
+async fn create_user(
+    Require(auth, _): Require<perm::UsersManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(body): Json<CreateUserBody>,
+) -> Result<(StatusCode, Json<User>), ApiError> {
+    let username = body.username.trim();
+    if username.is_empty() || body.password.is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST, "username and password are required"));
+    }
+    let password_hash = hash(body.password).await?;
+    let email = body.email.as_deref().map(str::trim).filter(|e| !e.is_empty());
+    let role = body.role.unwrap_or(Role::default_for(body.is_staff));
+    let id = match user::create_user(&state.pool, username, &password_hash, email, role).await {
+        Ok(id) => id,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return Err(error(StatusCode::CONFLICT, format!("User [{}] already exists", username)));
+        }
+        Err(e) => return Err(db_error(e)),
+    };
+    tracing::info!("users: {} created user {:?} (id={})", auth.0.username, username, id);
+    audit::record(
+        &state.pool,
+        Event::new("user.create")
+            .user(&auth.0)
+            .target("user", id)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({
+                "username": username,
+                "role": role.as_str(),
+            })),
+    )
+    .await;
+    Ok((StatusCode::CREATED, Json(find_user(&state, id).await?)))
+}
+
+/// GET /users/:id
+async fn get_user(
+    _auth: Require<perm::UsersManage>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<User>, ApiError> {
+    Ok(Json(find_user(&state, id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserBody {
+    /// 空字串清除 email。
+    #[serde(default)]
+    pub email: Option<String>,
+    /// 舊欄位：未指定 role 且與目前不同時，true 設為 admin、false 設為 user。
+    #[serde(default)]
+    pub is_staff: Option<bool>,
+    #[serde(default)]
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub is_active: Option<bool>,
+    /// 重設密碼。
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// PATCH /users/:id：只更新有給的欄位；不能停用自己或移除自己的 `users.manage`（含以 is_staff=false 降為 user）。
+async fn update_user(
+    Require(auth, _): Require<perm::UsersManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+    Json(body): Json<UpdateUserBody>,
+) -> Result<Json<User>, ApiError> {
+    let target = find_user(&state, id).await?;
+    let demotes_self = body.is_staff == Some(false)
+        || body.is_active == Some(false)
+        || body.role.is_some_and(|r| !r.has(Permission::UsersManage));
+    if id == auth.0.id && demotes_self {
+        return Err(error(StatusCode::BAD_REQUEST, "You cannot disable or demote yourself"));
+    }
+    if let Some(email) = &body.email {
+        let email = Some(email.trim()).filter(|e| !e.is_empty());
+        user::set_email(&state.pool, id, email).await.map_err(db_error)?;
+    }
+    let role = body.role.or_else(|| {
+        body.is_staff
+            .filter(|&is_staff| is_staff != target.is_staff)
+            .map(Role::default_for)
+    });
+    if let Some(role) = role {
+        user::set_role(&state.pool, id, role).await.map_err(db_error)?;
+    }
+    let mut revoke = false;
+    if let Some(is_active) = body.is_active {
+        user::set_active(&state.pool, id, is_active).await.map_err(db_error)?;
+        revoke |= !is_active;
+    }
+    if let Some(password) = &body.password {
+        if password.is_empty() {
+            return Err(error(StatusCode::BAD_REQUEST, "password must not be empty"));
+        }
+        let password_hash = hash(password.clone()).await?;
+        user::set_password_hash(&state.pool, id, &password_hash)
+            .await
+            .map_err(db_error)?;
+        revoke = true;
+    }
+    if revoke {
+        revoke_user_sessions(&state.pool, id).await.map_err(db_error)?;
+    }
+    tracing::info!("users: {} updated user {:?} (id={})", auth.0.username, target.username, id);
+    audit::record(
+        &state.pool,
+        Event::new("user.update")
+            .user(&auth.0)
+            .target("user", id)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({
+                "username": target.username,
+                "email": body.email,
+                "role": role.map(Role::as_str),
+                "is_active": body.is_active,
+                "password_reset": body.password.is_some(),
+            })),
+    )
+    .await;
+    Ok(Json(find_user(&state, id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct DeleteUserQuery {
+    /// 對該使用者容器的處理：`keep`（預設，僅 staff 可見）、`stop`、`transfer`。
+    #[serde(default)]
+    pub containers: Option<String>,
+    /// `containers=transfer` 時的新擁有者 user id。
+    #[serde(default)]
+    pub transfer_to: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ContainerTask {
+    pub id: String,
+    pub name: String,
+    pub task_id: String,
+}
+
+#[derive(Serialize)]
+pub struct DeleteUserResponse {
+    pub id: i64,
+    /// 為其容器排入的 stop / transfer 任務。
+    pub containers: Vec<ContainerTask>,
+}
+
+/// DELETE /users/:id?containers=keep|stop|transfer&transfer_to=<id>：先排入容器任務，成功後才刪除帳號。
+async fn delete_user(
+    Require(auth, _): Require<perm::UsersManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+    Query(q): Query<DeleteUserQuery>,
+) -> Result<Json<DeleteUserResponse>, ApiError> {
+    if id == auth.0.id {
+        return Err(error(StatusCode::BAD_REQUEST, "You cannot delete yourself"));
+    }
+    let target = find_user(&state, id).await?;
+    let mode = q.containers.as_deref().unwrap_or("keep");
+    let new_owner = match mode {
+        "keep" | "stop" => None,
+        "transfer" => {
+            let to = q
+                .transfer_to
+                .ok_or_else(|| error(StatusCode::BAD_REQUEST, "transfer_to is required"))?;
+            if to == id {
+                return Err(error(StatusCode::BAD_REQUEST, "Cannot transfer containers to the deleted user"));
+            }
+            let new_owner = find_user(&state, to).await?;
+            if !new_owner.is_active {
+                return Err(error(StatusCode::BAD_REQUEST, "Cannot transfer containers to a disabled user"));
+            }
+            Some(new_owner)
+        }
+        _ => return Err(error(StatusCode::BAD_REQUEST, "containers must be keep, stop or transfer")),
+    };
+
+    let owned: Vec<docker::ContainerInfo> = containers_snapshot(&state)
+        .await
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .into_iter()
+        .filter(|c| c.owner_id == Some(id))
+        .collect();
+
+    let mut tasks = Vec::new();
+    for c in owned {
+        let task_id = match (&new_owner, mode) {
+            (Some(new_owner), _) => crate::queue::enqueue_transfer_container(
+                &state.config.redis_url,
+                &state.config.docker_network,
+                &c.id,
+                new_owner.id,
+            )
+            .await
+            .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e))?,
+            (None, "stop") if c.status == "running" => {
+                crate::queue::enqueue_containers_control(&state.config.redis_url, "stop", &c.id)
+                    .await
+                    .ok_or_else(|| error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to enqueue task"))?
+            }
+            _ => continue,
+        };
+        tasks.push(ContainerTask {
+            id: c.id,
+            name: c.name,
+            task_id,
+        });
+    }
+
+    if !user::delete_user(&state.pool, id).await.map_err(db_error)? {
+        return Err(error(StatusCode::NOT_FOUND, "User not found"));
+    }
+    tracing::info!(
+        "users: {} deleted user {:?} (id={}), containers={} ({} tasks)",
+        auth.0.username,
+        target.username,
+        id,
+        mode,
+        tasks.len()
+    );
+    audit::record(
+        &state.pool,
+        Event::new("user.delete")
+            .user(&auth.0)
+            .target("user", id)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({
+                "username": target.username,
+                "containers": mode,
+                "transfer_to": new_owner.as_ref().map(|u| u.id),
+                "tasks": tasks.iter().map(|t| &t.task_id).collect::<Vec<_>>(),
+            })),
+    )
+    .await;
+    Ok(Json(DeleteUserResponse {
+        id,
+        containers: tasks,
+    }))
+}
+
+/// DELETE /users/:id/mfa：移除使用者的 TOTP 與復原碼（遺失裝置時）；若 staff 要求 MFA，下次登入須重新設定。
+async fn reset_mfa(
+    Require(auth, _): Require<perm::UsersManage>,
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let target = find_user(&state, id).await?;
+    if !mfa::disable(&state.pool, id).await.map_err(db_error)? {
+        return Err(error(StatusCode::NOT_FOUND, "MFA is not set up for this user"));
+    }
+    tracing::info!("users: {} reset MFA of {:?} (id={})", auth.0.username, target.username, id);
+    audit::record(
+        &state.pool,
+        Event::new("user.mfa_reset")
+            .user(&auth.0)
+            .target("user", id)
+            .ip(ip.as_deref())
+            .details(serde_json::json!({ "username": target.username })),
+    )
+    .await;
+    Ok(Json(serde_json::json!({})))
+}
+
+/// 掛載 /users、/users/:id、/users/:id/mfa（需要 `users.manage`）。
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route(
+            "/users/:id",
+            get(get_user).patch(update_user).delete(delete_user),
+        )
+        .route("/users/:id/mfa", delete(reset_mfa))
+}
@@ -0,0 +1,98 @@
+//! 稽核事件的建立與寫入。handler 在動作完成（或被拒絕）後呼叫 `record`：
+//! `audit::record(&state.pool, Event::new("container.stop").user(&auth.0).target("container", id).ip(ip)).await`。
+//! 寫入失敗只記 warning，不影響原本的操作。儲存與查詢見 `db::audit`。
+
+use sqlx::SqlitePool;
+
+use crate::db::audit::{self, NewAuditEvent, Outcome};
+use crate::db::User;
+
+pub struct Event {
+    action: String,
+    outcome: Outcome,
+    user_id: Option<i64>,
+    username: Option<String>,
+    target_type: Option<&'static str>,
+    target_id: Option<String>,
+    ip: Option<String>,
+    details: serde_json::Value,
+}
+
+impl Event {
+    /// 預設為成功、沒有操作者與目標。
+    pub fn new(action: impl Into<String>) -> Self {
+        Self {
+            action: action.into(),
+            outcome: Outcome::Success,
+            user_id: None,
+            username: None,
+            target_type: None,
+            target_id: None,
+            ip: None,
+            details: serde_json::json!({}),
+        }
+    }
+
+    pub fn user(self, user: &User) -> Self {
+        self.actor(Some(user.id), &user.username)
+    }
+
+    /// 操作者；登入失敗時只有嘗試的 username。
+    pub fn actor(mut self, user_id: Option<i64>, username: &str) -> Self {
+        self.user_id = user_id;
+        self.username = Some(username.to_string());
+        self
+    }
+
+    pub fn target(mut self, kind: &'static str, id: impl ToString) -> Self {
+        self.target_type = Some(kind);
+        self.target_id = Some(id.to_string());
+        self
+    }
+
+    pub fn outcome(mut self, outcome: Outcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    pub fn failed(self) -> Self {
+        self.outcome(Outcome::Failure)
+    }
+
+    pub fn denied(self) -> Self {
+        self.outcome(Outcome::Denied)
+    }
+
+    pub fn ip(mut self, ip: Option<&str>) -> Self {
+        self.ip = ip.map(str::to_string);
+        self
+    }
+
+    /// 附加資訊（JSON object）；不可放密碼、token 等機密。
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// 寫入一筆事件；失敗時只記 warning。
+pub async fn record(pool: &SqlitePool, event: Event) {
+    let new = NewAuditEvent {
+        user_id: event.user_id,
+        username: event.username.as_deref(),
+        action: &event.action,
+        target_type: event.target_type,
+        target_id: event.target_id.as_deref(),
+        outcome: event.outcome,
+        ip: event.ip.as_deref(),
+        details: &event.details,
+    };
+    if let Err(e) = audit::insert(pool, &new).await {
+        tracing::warn!(
+            "audit: failed to record {} by {:?}: {}",
+            event.action,
+            event.username,
+            e
+        );
+    }
+}
@@ -2,15 +2,23 @@
 //!
 //! 從請求的 `Authorization: Bearer <token>` 解析 JWT，驗證後查詢 DB 取得使用者，
 //! 供需要登入的路由使用（例如 `AuthUser` 作為 handler 參數即可取得當前使用者）。
+//! `Require<P>` 另外要求角色具備權限 P（見 `permissions`），不足時回 403。
+//! Bearer 值以 `ddm_` 開頭時視為個人 API token（見 `db::api_token`），依請求決定需要的 scope。
 
 use async_trait::async_trait;
 use axum::{
     extract::FromRequestParts,
     http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
 };
+use sqlx::SqlitePool;
+use std::marker::PhantomData;
 
-use crate::db::{get_by_id, User};
-use crate::jwt;
+use crate::db::api_token;
+use crate::db::user::get_row_by_id;
+use crate::db::User;
+use crate::permissions::{Permission, PermissionMarker, Scope};
 use crate::AppState;
 
 /// 已通過 JWT 驗證的使用者；若缺少或無效的 Bearer token 則回傳 401。
@@ -34,12 +42,159 @@ impl FromRequestParts<AppState> for AuthUser {
             .strip_prefix("Bearer ")
             .or_else(|| auth.strip_prefix("bearer "))
             .ok_or((StatusCode::UNAUTHORIZED, "missing Bearer"))?;
-        let claims = jwt::verify_access(token, app.config.jwt_secret.as_bytes())
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "invalid token"))?;
-        let user = get_by_id(&app.pool, claims.user_id)
-            .await
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "db error"))?
-            .ok_or((StatusCode::UNAUTHORIZED, "user not found"))?;
+        let user = if api_token::is_api_token(token) {
+            authenticate_api_token(&app.pool, token, request_scope(&parts.method, parts.uri.path())).await?
+        } else {
+            authenticate_access_token(app, token).await?
+        };
         Ok(AuthUser(user))
     }
 }
+
+impl AuthUser {
+    /// 要求目前使用者的角色具備權限；用於依請求內容才決定的檢查（例如 privileged）。
+    pub fn require(&self, permission: Permission) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+        if self.0.can(permission) {
+            return Ok(());
+        }
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": format!("Permission denied: {}", permission.as_str()),
+                "permission": permission.as_str(),
+            })),
+        ))
+    }
+}
+
+/// 已登入且角色具備權限 P 的使用者，例如 `Require<perm::ContainersCreate>`。
+pub struct Require<P: PermissionMarker>(pub AuthUser, pub PhantomData<P>);
+
+#[async_trait]
+impl<P: PermissionMarker> FromRequestParts<AppState> for Require<P> {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let auth = AuthUser::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        auth.require(P::PERMISSION).map_err(IntoResponse::into_response)?;
+        Ok(Require(auth, PhantomData))
+    }
+}
+
+/// API token 呼叫此請求需要的 scope：去掉 /api 或 /dashboard/api 前綴後查 `Scope::for_request`。
+fn request_scope(method: &axum::http::Method, path: &str) -> Option<Scope> {
+    let path = path
+        .strip_prefix("/dashboard/api")
+        .or_else(|| path.strip_prefix("/api"))
+        .unwrap_or(path);
+    Scope::for_request(method, path)
+}
+
+/// 驗證 access token 並取得使用者；拒絕在使用者 tokens_valid_after 之前簽發的 token 與已停用的帳號。
+/// HTTP extractor 與 WebSocket 共用。
+pub async fn authenticate_access_token(
+    state: &AppState,
+    token: &str,
+) -> Result<User, (StatusCode, &'static str)> {
+    let claims = state
+        .jwt
+        .verify_access(token)
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "invalid token"))?;
+    let row = get_row_by_id(&state.pool, claims.user_id)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "db error"))?
+        .ok_or((StatusCode::UNAUTHORIZED, "user not found"))?;
+    if !row.token_still_valid(claims.iat) {
+        return Err((StatusCode::UNAUTHORIZED, "token revoked"));
+    }
+    if !row.is_active() {
+        return Err((StatusCode::UNAUTHORIZED, "user disabled"));
+    }
+    Ok(row.into())
+}
+
+/// 驗證個人 API token：須未撤銷、未過期、具備 `scope`（None 表示此端點不接受 API token），且使用者仍啟用。
+pub async fn authenticate_api_token(
+    pool: &SqlitePool,
+    token: &str,
+    scope: Option<Scope>,
+) -> Result<User, (StatusCode, &'static str)> {
+    let record = api_token::authenticate(pool, token)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "db error"))?
+        .ok_or((StatusCode::UNAUTHORIZED, "invalid token"))?;
+    let scope = scope.ok_or((StatusCode::FORBIDDEN, "not allowed with an API token"))?;
+    if !record.has_scope(scope) {
+        return Err((StatusCode::FORBIDDEN, "API token scope does not allow this"));
+    }
+    let row = get_row_by_id(pool, record.user_id)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "db error"))?
+        .ok_or((StatusCode::UNAUTHORIZED, "user not found"))?;
+    if !row.is_active() {
+        return Err((StatusCode::UNAUTHORIZED, "user disabled"));
+    }
+    Ok(row.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permissions::Role;
+    use axum::http::Method;
+
+    #[test]
+    fn request_scope_strips_api_prefixes() {
+        for prefix in ["/api", "/dashboard/api"] {
+            let scope = |method: Method, path: &str| request_scope(&method, &format!("{}{}", prefix, path));
+            assert_eq!(scope(Method::GET, "/containers"), Some(Scope::Read));
+            assert_eq!(scope(Method::POST, "/containers/control"), Some(Scope::ContainersControl));
+            assert_eq!(scope(Method::GET, "/console/shell/abc"), Some(Scope::Console));
+            assert_eq!(scope(Method::GET, "/me/tokens"), None);
+            assert_eq!(scope(Method::GET, "/auth/lockouts"), None);
+            assert_eq!(scope(Method::POST, "/users"), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn api_token_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let id = crate::db::user::create_user(&pool, "alice", "x", None, Role::User).await.unwrap();
+        let (token, _) = api_token::create(&pool, id, "ci", &[Scope::Read], None).await.unwrap();
+        let check = |method: Method, path: &'static str| {
+            let (pool, token) = (pool.clone(), token.clone());
+            async move {
+                authenticate_api_token(&pool, &token, request_scope(&method, path))
+                    .await
+                    .map(|u| u.id)
+                    .map_err(|e| e.0)
+            }
+        };
+
+        assert_eq!(check(Method::GET, "/api/containers").await, Ok(id));
+        // scope 不足
+        assert_eq!(check(Method::POST, "/api/containers/control").await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(check(Method::GET, "/api/console/shell/abc").await, Err(StatusCode::FORBIDDEN));
+        // /me/、/auth/ 只接受 JWT，即使是 GET
+        assert_eq!(check(Method::GET, "/api/me/tokens").await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(check(Method::POST, "/api/me/password").await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(check(Method::GET, "/api/auth/lockouts").await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(check(Method::POST, "/dashboard/api/auth/logout-all").await, Err(StatusCode::FORBIDDEN));
+
+        // 停用的使用者
+        crate::db::user::set_active(&pool, id, false).await.unwrap();
+        assert_eq!(check(Method::GET, "/api/containers").await, Err(StatusCode::UNAUTHORIZED));
+        crate::db::user::set_active(&pool, id, true).await.unwrap();
+
+        // 撤銷與過期
+        let record = api_token::list_for_user(&pool, id).await.unwrap().remove(0);
+        api_token::revoke(&pool, record.id, id).await.unwrap();
+        assert_eq!(check(Method::GET, "/api/containers").await, Err(StatusCode::UNAUTHORIZED));
+        let (expired, _) = api_token::create(&pool, id, "old", &[Scope::Read], Some(1)).await.unwrap();
+        let res = authenticate_api_token(&pool, &expired, Some(Scope::Read)).await;
+        assert_eq!(res.map(|u| u.id).map_err(|e| e.0), Err(StatusCode::UNAUTHORIZED));
+    }
+}
@@ -0,0 +1,354 @@
+//! LDAP 驗證：以服務帳號（LDAP_BIND_DN，未設定則匿名）搜尋使用者，再以找到的 DN 與密碼 simple bind，最後讀取群組。
+//! 第一次登入時建立 `auth_provider = 'ldap'` 的本機帳號（external_id 為 LDAP_ID_ATTR，沒有該屬性時為 DN），
+//! 之後每次登入同步 email；設定 LDAP_STAFF_GROUPS 時同步 staff（admin 角色）。
+//! 同名的本機（local）帳號仍以本機密碼驗證，不會被目錄中的同名帳號接管（例如 create-user 建立的管理員）。
+
+use async_trait::async_trait;
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+use super::{Authenticator, LocalAuthenticator};
+use crate::config::Config;
+use crate::db::user::{self, UserRow};
+use crate::permissions::Role;
+
+/// `users.auth_provider` 的值。
+pub const PROVIDER: &str = "ldap";
+
+/// LDAP 結果碼 invalidCredentials。
+const INVALID_CREDENTIALS: u32 = 49;
+/// 整個查詢（連線、搜尋、bind）的時間上限。
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// 目錄中找到且密碼正確的使用者。
+struct DirectoryUser {
+    dn: String,
+    id: String,
+    email: Option<String>,
+    groups: Vec<String>,
+}
+
+pub struct LdapAuthenticator {
+    url: String,
+    starttls: bool,
+    bind_dn: Option<String>,
+    bind_password: String,
+    user_base: String,
+    user_filter: String,
+    id_attr: String,
+    email_attr: String,
+    group_attr: String,
+    group_base: Option<String>,
+    group_filter: String,
+    staff_groups: Vec<String>,
+}
+
+/// 屬性名稱不分大小寫（伺服器回傳的大小寫不一定與請求相同）。
+fn attr_values<'a>(entry: &'a SearchEntry, name: &str) -> &'a [String] {
+    entry
+        .attrs
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_slice())
+        .unwrap_or(&[])
+}
+
+impl LdapAuthenticator {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            url: config.ldap_url.clone(),
+            starttls: config.ldap_starttls,
+            bind_dn: config.ldap_bind_dn.clone(),
+            bind_password: config.ldap_bind_password.clone(),
+            user_base: config.ldap_user_base.clone(),
+            user_filter: config.ldap_user_filter.clone(),
+            id_attr: config.ldap_id_attr.clone(),
+            email_attr: config.ldap_email_attr.clone(),
+            group_attr: config.ldap_group_attr.clone(),
+            group_base: config.ldap_group_base.clone(),
+            group_filter: config.ldap_group_filter.clone(),
+            staff_groups: config.ldap_staff_groups.clone(),
+        }
+    }
+
+    async fn connect(&self) -> Result<Ldap, ldap3::LdapError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(10))
+            .set_starttls(self.starttls);
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.url).await?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    async fn service_bind(&self, ldap: &mut Ldap) -> Result<(), ldap3::LdapError> {
+        if let Some(dn) = &self.bind_dn {
+            ldap.simple_bind(dn, &self.bind_password).await?.success()?;
+        }
+        Ok(())
+    }
+
+    /// 搜尋使用者並以其密碼 bind；帳號不存在、不唯一或密碼錯誤回 Ok(None)。
+    async fn lookup(&self, username: &str, password: &str) -> Result<Option<DirectoryUser>, ldap3::LdapError> {
+        let mut ldap = self.connect().await?;
+        self.service_bind(&mut ldap).await?;
+        let filter = self.user_filter.replace("{username}", &ldap_escape(username));
+        let attrs = [self.id_attr.as_str(), self.email_attr.as_str(), self.group_attr.as_str()];
+        let (entries, _) = ldap
+            .search(&self.user_base, Scope::Subtree, &filter, attrs.to_vec())
+            .await?
+            .success()?;
+        if entries.len() != 1 {
+            if entries.len() > 1 {
+                tracing::warn!("ldap: filter {:?} matched {} entries; refusing login", filter, entries.len());
+            }
+            let _ = ldap.unbind().await;
+            return Ok(None);
+        }
+        let Some(entry) = entries.into_iter().next().map(SearchEntry::construct) else {
+            return Ok(None);
+        };
+
+        let bind = ldap.simple_bind(&entry.dn, password).await?;
+        if bind.rc == INVALID_CREDENTIALS {
+            let _ = ldap.unbind().await;
+            return Ok(None);
+        }
+        bind.success()?;
+
+        let groups = match &self.group_base {
+            Some(base) => {
+                // 使用者本身不一定能搜尋群組，改回服務帳號
+                self.service_bind(&mut ldap).await?;
+                let filter = self
+                    .group_filter
+                    .replace("{dn}", &ldap_escape(entry.dn.as_str()))
+                    .replace("{username}", &ldap_escape(username));
+                let (groups, _) = ldap
+                    .search(base, Scope::Subtree, &filter, vec!["1.1"])
+                    .await?
+                    .success()?;
+                groups.into_iter().map(|g| SearchEntry::construct(g).dn).collect()
+            }
+            None => attr_values(&entry, &self.group_attr).to_vec(),
+        };
+        let _ = ldap.unbind().await;
+
+        let id = attr_values(&entry, &self.id_attr)
+            .first()
+            .cloned()
+            .unwrap_or_else(|| entry.dn.clone());
+        let email = attr_values(&entry, &self.email_attr)
+            .first()
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty());
+        Ok(Some(DirectoryUser {
+            dn: entry.dn,
+            id,
+            email,
+            groups,
+        }))
+    }
+
+    /// 有設定 LDAP_STAFF_GROUPS 時依群組 DN（不分大小寫）決定是否為 staff；否則 None（不同步）。
+    fn staff_for(&self, groups: &[String]) -> Option<bool> {
+        (!self.staff_groups.is_empty()).then(|| {
+            groups
+                .iter()
+                .any(|g| self.staff_groups.iter().any(|s| s.eq_ignore_ascii_case(g)))
+        })
+    }
+
+    /// 依 external_id 找到或建立本機帳號並同步 email / staff 身分。
+    /// staff 身分改變時角色改回該身分的預設（staff 為 admin，否則 user），避免離開群組後仍保有 admin。
+    /// username 已被其他帳號使用時拒絕登入。
+    async fn provision(
+        &self,
+        pool: &SqlitePool,
+        username: &str,
+        found: &DirectoryUser,
+    ) -> Result<Option<UserRow>, sqlx::Error> {
+        let is_staff = self.staff_for(&found.groups);
+        if let Some(row) = user::get_by_external_id(pool, PROVIDER, &found.id).await? {
+            if let Some(is_staff) = is_staff {
+                if is_staff != (row.is_staff != 0) {
+                    user::set_role(pool, row.id, Role::default_for(is_staff)).await?;
+                    tracing::info!("ldap: {} staff={} (group sync)", row.username, is_staff);
+                }
+            }
+            if found.email.is_some() && found.email != row.email {
+                user::set_email(pool, row.id, found.email.as_deref()).await?;
+            }
+            return user::get_row_by_id(pool, row.id).await;
+        }
+        let role = Role::default_for(is_staff.unwrap_or(false));
+        match user::create_external_user(
+            pool,
+            PROVIDER,
+            &found.id,
+            username,
+            found.email.as_deref(),
+            role,
+        )
+        .await
+        {
+            Ok(id) => {
+                tracing::info!(
+                    "ldap: provisioned user {:?} (id={}, dn={}, role={})",
+                    username,
+                    id,
+                    found.dn,
+                    role.as_str()
+                );
+                user::get_row_by_id(pool, id).await
+            }
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                tracing::warn!(
+                    "ldap: username {:?} is already used by another account; not linking {}",
+                    username,
+                    found.dn
+                );
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for LdapAuthenticator {
+    async fn authenticate(
+        &self,
+        pool: &SqlitePool,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<UserRow>, String> {
+        let username = username.trim();
+        // 空密碼在 LDAP 是 unauthenticated bind，伺服器會回成功，必須先擋掉
+        if password.is_empty() || username.is_empty() {
+            return Ok(None);
+        }
+        let local = user::get_by_username(pool, username)
+            .await
+            .map_err(|e| format!("database error: {}", e))?;
+        if local.is_some_and(|row| row.auth_provider == "local") {
+            return LocalAuthenticator.authenticate(pool, username, password).await;
+        }
+        let found = tokio::time::timeout(LOOKUP_TIMEOUT, self.lookup(username, password))
+            .await
+            .map_err(|_| format!("LDAP {} timed out", self.url))?
+            .map_err(|e| format!("LDAP {}: {}", self.url, e))?;
+        let Some(found) = found else {
+            return Ok(None);
+        };
+        self.provision(pool, username, &found)
+            .await
+            .map_err(|e| format!("database error: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAFF_GROUP: &str = "cn=dock-admins,ou=groups,dc=example,dc=com";
+
+    /// 指向沒有服務的位址：測試的路徑若真的連到 LDAP 會回 Err。
+    fn authenticator() -> LdapAuthenticator {
+        LdapAuthenticator {
+            url: "ldap://127.0.0.1:1".into(),
+            starttls: false,
+            bind_dn: None,
+            bind_password: String::new(),
+            user_base: "ou=people,dc=example,dc=com".into(),
+            user_filter: "(uid={username})".into(),
+            id_attr: "entryUUID".into(),
+            email_attr: "mail".into(),
+            group_attr: "memberOf".into(),
+            group_base: None,
+            group_filter: String::new(),
+            staff_groups: vec![STAFF_GROUP.into()],
+        }
+    }
+
+    fn directory_user(id: &str, groups: &[&str]) -> DirectoryUser {
+        DirectoryUser {
+            dn: format!("uid={},ou=people,dc=example,dc=com", id),
+            id: id.into(),
+            email: Some(format!("{}@example.com", id)),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn provision_syncs_staff_and_resets_role() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let ldap = authenticator();
+
+        // 群組 DN 不分大小寫
+        let row = ldap
+            .provision(&pool, "alice", &directory_user("a-1", &[&STAFF_GROUP.to_uppercase()]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((row.is_staff, row.role.as_str(), row.auth_provider.as_str()), (1, "admin", PROVIDER));
+        assert_eq!(row.email.as_deref(), Some("a-1@example.com"));
+
+        // 離開 staff 群組：is_staff 與 admin 角色一併收回
+        let row = ldap
+            .provision(&pool, "alice", &directory_user("a-1", &[]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((row.is_staff, row.role.as_str()), (0, "user"));
+
+        // staff 身分沒變時保留另外指派的角色
+        user::set_role(&pool, row.id, Role::Operator).await.unwrap();
+        let row = ldap
+            .provision(&pool, "alice", &directory_user("a-1", &[]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.role, "operator");
+    }
+
+    #[tokio::test]
+    async fn provision_refuses_taken_username() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        user::create_user(&pool, "alice", "x", None, Role::Admin).await.unwrap();
+
+        let found = authenticator()
+            .provision(&pool, "alice", &directory_user("a-1", &[]))
+            .await
+            .unwrap();
+        assert!(found.is_none());
+        let local = user::get_by_username(&pool, "alice").await.unwrap().unwrap();
+        assert_eq!(local.auth_provider, "local");
+    }
+
+    #[tokio::test]
+    async fn empty_credentials_never_reach_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let ldap = authenticator();
+        assert!(ldap.authenticate(&pool, "alice", "").await.unwrap().is_none());
+        assert!(ldap.authenticate(&pool, "  ", "secret").await.unwrap().is_none());
+        // 其他帳號確實會連 LDAP（並因無法連線而失敗）
+        assert!(ldap.authenticate(&pool, "alice", "secret").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn local_accounts_use_local_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let hash = user::hash_password("local-pass").unwrap();
+        user::create_user(&pool, "admin", &hash, None, Role::Admin).await.unwrap();
+        let ldap: &dyn Authenticator = &authenticator();
+
+        let row = ldap.authenticate(&pool, "admin", "local-pass").await.unwrap();
+        assert_eq!(row.map(|r| r.username).as_deref(), Some("admin"));
+        assert!(ldap.authenticate(&pool, "admin", "wrong").await.unwrap().is_none());
+    }
+}
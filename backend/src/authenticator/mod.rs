@@ -0,0 +1,64 @@
+//! /auth/token 背後的帳密驗證：`Authenticator` trait，本機實作（users 表的 Argon2 雜湊）與 LDAP 實作。
+//! 限流、失敗鎖定與停用檢查仍在 handler 做，與驗證後端無關；依 AUTH_BACKEND 選擇實作。
+
+mod ldap;
+
+use async_trait::async_trait;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::db::user::{self, UserRow};
+
+pub use ldap::LdapAuthenticator;
+
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// 帳密正確回 `Ok(Some(row))`（外部來源的帳號會先建立或更新本機資料列），
+    /// 帳號不存在或密碼錯誤回 `Ok(None)`；後端無法使用（DB、LDAP 連線失敗）回 `Err`。
+    async fn authenticate(
+        &self,
+        pool: &SqlitePool,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<UserRow>, String>;
+}
+
+/// users 表的帳號與 Argon2 密碼。
+pub struct LocalAuthenticator;
+
+#[async_trait]
+impl Authenticator for LocalAuthenticator {
+    /// 不存在的帳號對假雜湊驗證，耗時與存在的帳號相同。
+    async fn authenticate(
+        &self,
+        pool: &SqlitePool,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<UserRow>, String> {
+        let row = user::get_by_username(pool, username)
+            .await
+            .map_err(|e| format!("database error: {}", e))?;
+        // Argon2 驗證為 CPU 密集，移到 blocking pool
+        let password = password.to_string();
+        let hash = row.as_ref().map(|r| r.password_hash.clone());
+        let valid = tokio::task::spawn_blocking(move || match hash {
+            Some(hash) => user::verify_password(&hash, &password),
+            None => {
+                user::verify_dummy_password(&password);
+                false
+            }
+        })
+        .await
+        .unwrap_or(false);
+        Ok(row.filter(|_| valid))
+    }
+}
+
+/// 依 AUTH_BACKEND 建立驗證後端（`Config::validate` 已檢查設定）。
+pub fn from_config(config: &Config) -> Arc<dyn Authenticator> {
+    match config.auth_backend.as_str() {
+        "ldap" => Arc::new(LdapAuthenticator::new(config)),
+        _ => Arc::new(LocalAuthenticator),
+    }
+}
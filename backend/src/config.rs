@@ -1,31 +1,394 @@
-//! 從環境變數讀取設定（綁定位址、DB、Redis、JWT、Docker 網路等）。
-//! 未使用 Google 登入，無 client id 等欄位。
+//! 從環境變數讀取設定（綁定位址、DB、Redis、JWT、Docker 網路、OIDC、LDAP 等）。
+//! 未使用 Google 登入；第三方登入改用通用的 OpenID Connect（見 `oidc`）。
 
 #[derive(Clone)]
 pub struct Config {
     pub bind_addr: String,
     pub database_url: String,
     pub redis_url: String,
+    /// HS256 secret: JWT_SECRET, or the contents of JWT_SECRET_FILE (Docker secrets) when that is set.
     pub jwt_secret: String,
+    /// File the secret was read from (JWT_SECRET_FILE); surrounding whitespace is trimmed.
+    pub jwt_secret_file: Option<String>,
+    /// Development mode (DEV_MODE): allows a missing, default or short JWT secret.
+    pub dev_mode: bool,
+    /// Access token lifetime in seconds (ACCESS_TOKEN_TTL).
+    pub access_token_ttl_secs: i64,
+    /// Refresh token lifetime in seconds (REFRESH_TOKEN_TTL).
+    pub refresh_token_ttl_secs: i64,
+    /// Signing keys for HS256 as `kid:secret,kid:secret` (JWT_SECRETS); when unset, JWT_SECRET is used as kid `default`.
+    pub jwt_secrets_list: String,
+    /// kid used to sign new tokens (JWT_ACTIVE_KID); defaults to the first configured key.
+    pub jwt_active_kid: Option<String>,
+    /// HS256 (default), RS256 or EdDSA (JWT_ALGORITHM).
+    pub jwt_algorithm: String,
+    /// PEM private key for RS256/EdDSA (JWT_PRIVATE_KEY_FILE).
+    pub jwt_private_key_file: Option<String>,
+    /// PEM public keys for RS256/EdDSA as `kid:path,kid:path` (JWT_PUBLIC_KEY_FILES).
+    pub jwt_public_key_files: String,
+    /// `iss` claim issued and required (JWT_ISSUER).
+    pub jwt_issuer: String,
+    /// `aud` claim issued and required (JWT_AUDIENCE).
+    pub jwt_audience: String,
     pub docker_network: String,
     /// Host used for port-in-use check (e.g. host.docker.internal when running in Docker).
     pub host_for_port_check: String,
     /// Image name prefix for GUI containers (e.g. gui-vnc), same as Django DOCKER_IMAGE_NAME.
     pub docker_image_name: String,
+    /// Fixed GPU list (comma-separated indexes or UUIDs); when unset the inventory is read via nvidia-smi.
+    pub gpu_devices: Option<String>,
+    /// Image used for the NVIDIA capability probe and nvidia-smi inventory.
+    pub nvidia_probe_image: String,
+    /// How long a cached NVIDIA probe result stays valid (seconds); also the background refresh interval.
+    pub nvidia_probe_ttl_secs: u64,
+    /// Host ports that may be handed out / accepted for containers, inclusive (PORT_RANGE, e.g. 1024-65535).
+    pub port_range: (u16, u16),
+    /// Host ports never handed out (PORT_EXCLUDE, e.g. 8000,8080,9000-9100).
+    pub port_exclude: Vec<u16>,
+    /// Upper bound for the `count` parameter of GET /api/ports.
+    pub free_ports_max: u32,
+    /// How long a port stays reserved for a queued create/restore before it expires (PORT_RESERVATION_TTL, seconds).
+    pub port_reservation_ttl_secs: i64,
+    /// Interfaces container host ports are bound to (PORT_BIND_ADDRS, e.g. `127.0.0.1,::1`, `loopback`, `0.0.0.0,::`).
+    /// Empty means Docker's default (all interfaces).
+    pub port_bind_addrs: Vec<std::net::IpAddr>,
+    /// Containers a user without `containers.unlimited` may own, pending creations included (MAX_CONTAINERS_PER_USER); unset means unlimited.
+    pub max_containers_per_user: Option<u32>,
+    /// Largest tarball accepted by `POST /containers/import` (IMPORT_MAX_MB, default 20480).
+    pub import_max_bytes: usize,
+    /// Take the client IP from X-Forwarded-For (set when running behind Traefik/another reverse proxy).
+    /// Only honoured for connections from `trusted_proxies`.
+    pub trust_proxy_headers: bool,
+    /// Reverse proxies allowed to set X-Forwarded-For (TRUSTED_PROXIES, CIDRs or addresses, e.g. `172.18.0.5/32`).
+    pub trusted_proxies: Vec<ipnet::IpNet>,
+    /// Reject username/password login on /auth/token (DISABLE_PASSWORD_LOGIN), e.g. when everyone signs in via OIDC.
+    pub disable_password_login: bool,
+    /// OpenID Connect issuer URL (OIDC_ISSUER, e.g. a Keycloak realm); OIDC login is enabled when set.
+    pub oidc_issuer: Option<String>,
+    /// Client id registered at the IdP (OIDC_CLIENT_ID).
+    pub oidc_client_id: String,
+    /// Client secret (OIDC_CLIENT_SECRET); unset for a public client (PKCE only).
+    pub oidc_client_secret: Option<String>,
+    /// Callback URL registered at the IdP (OIDC_REDIRECT_URL, e.g. https://host/api/auth/oidc/callback).
+    pub oidc_redirect_url: String,
+    /// Requested scopes, space-separated (OIDC_SCOPES).
+    pub oidc_scopes: String,
+    /// Claim holding the user's groups (OIDC_GROUPS_CLAIM); dots address nested claims, e.g. `realm_access.roles`.
+    pub oidc_groups_claim: String,
+    /// Groups that make a user staff (OIDC_STAFF_GROUPS, comma-separated); when set, staff (the admin role) is synced on every login.
+    pub oidc_staff_groups: Vec<String>,
+    /// Group to role mapping as `group:role,group:role` (OIDC_ROLE_GROUPS); when set, the role is synced on every login.
+    pub oidc_role_groups: Vec<(String, String)>,
+    /// Role for users matching no OIDC_ROLE_GROUPS entry, and for new users (OIDC_DEFAULT_ROLE).
+    pub oidc_default_role: String,
+    /// Frontend page the callback redirects to with the tokens in the URL fragment (OIDC_POST_LOGIN_URL).
+    pub oidc_post_login_url: String,
+    /// Issuer shown in authenticator apps for TOTP (MFA_ISSUER).
+    pub mfa_issuer: String,
+    /// Lifetime of the MFA challenge token between the password and the code step (MFA_CHALLENGE_TTL, seconds).
+    pub mfa_challenge_ttl_secs: i64,
+    /// Password check behind /auth/token (AUTH_BACKEND): `local` (default) or `ldap`.
+    pub auth_backend: String,
+    /// LDAP server, `ldap://host:389` or `ldaps://host:636` (LDAP_URL).
+    pub ldap_url: String,
+    /// Upgrade `ldap://` connections with StartTLS (LDAP_STARTTLS).
+    pub ldap_starttls: bool,
+    /// Service account used for the user search (LDAP_BIND_DN); unset means an anonymous search.
+    pub ldap_bind_dn: Option<String>,
+    /// Password of the service account (LDAP_BIND_PASSWORD).
+    pub ldap_bind_password: String,
+    /// Base DN searched for users (LDAP_USER_BASE, e.g. ou=people,dc=example,dc=com).
+    pub ldap_user_base: String,
+    /// User search filter (LDAP_USER_FILTER); `{username}` is replaced with the escaped login name.
+    pub ldap_user_filter: String,
+    /// Attribute with a stable user id (LDAP_ID_ATTR); the entry DN is used when the attribute is missing.
+    pub ldap_id_attr: String,
+    /// Attribute holding the email address (LDAP_EMAIL_ATTR).
+    pub ldap_email_attr: String,
+    /// User attribute listing group DNs (LDAP_GROUP_ATTR), used when LDAP_GROUP_BASE is unset.
+    pub ldap_group_attr: String,
+    /// Base DN searched for groups (LDAP_GROUP_BASE); when set, groups come from a search instead of LDAP_GROUP_ATTR.
+    pub ldap_group_base: Option<String>,
+    /// Group search filter (LDAP_GROUP_FILTER); `{dn}` and `{username}` are replaced with escaped values.
+    pub ldap_group_filter: String,
+    /// Group DNs that make a user staff (LDAP_STAFF_GROUPS, `;`-separated); when set, staff (the admin role) is synced on every login.
+    pub ldap_staff_groups: Vec<String>,
+    /// Record console sessions as asciicast v2 files (CONSOLE_RECORDING).
+    pub console_recording: bool,
+    /// Directory for the `.cast` files (CONSOLE_RECORDING_DIR).
+    pub console_recording_dir: String,
+    /// Also record keystrokes sent to the terminal (CONSOLE_RECORDING_INPUT, default true).
+    pub console_recording_input: bool,
+    /// Size limit of one recording (CONSOLE_RECORDING_MAX_MB); later events are dropped and the recording is marked truncated.
+    pub console_recording_max_bytes: u64,
+    /// Delete recordings this many days after they end (CONSOLE_RECORDING_RETENTION_DAYS); 0 keeps them forever.
+    pub console_recording_retention_days: u64,
+}
+
+/// 開發用預設 secret；非 dev 模式下拒絕使用。
+pub const DEV_JWT_SECRET: &str = "change-me-in-production";
+/// 非 dev 模式下 HS* secret 的最短長度（bytes）。
+pub const MIN_JWT_SECRET_LEN: usize = 32;
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|s| matches!(s.trim(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// 解析 TRUSTED_PROXIES：逗號分隔的 CIDR 或單一位址（視為 /32、/128）；無法解析的項目略過並記錄警告。
+pub fn parse_proxy_list(s: &str) -> Vec<ipnet::IpNet> {
+    split_list(s)
+        .into_iter()
+        .filter_map(|item| {
+            let net = item
+                .parse::<ipnet::IpNet>()
+                .or_else(|_| item.parse::<std::net::IpAddr>().map(ipnet::IpNet::from));
+            if net.is_err() {
+                tracing::warn!("TRUSTED_PROXIES: ignoring invalid entry {:?}", item);
+            }
+            net.ok()
+        })
+        .collect()
 }
 
 impl Config {
+    /// 可信任 X-Forwarded-For 的來源；TRUST_PROXY_HEADERS 關閉時為空（一律使用連線位址）。
+    pub fn forwarded_trust(&self) -> &[ipnet::IpNet] {
+        if self.trust_proxy_headers {
+            &self.trusted_proxies
+        } else {
+            &[]
+        }
+    }
+
     /// 從環境變數建構；未設定時使用預設值（如 8000、sqlite、redis://127.0.0.1 等）。
+    /// JWT secret 的安全性檢查見 `validate`。
     pub fn from_env() -> Self {
+        let dev_mode = env_flag("DEV_MODE");
+        let jwt_secret_file = std::env::var("JWT_SECRET_FILE").ok().filter(|s| !s.trim().is_empty());
+        let jwt_secret = match &jwt_secret_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default(),
+            None => std::env::var("JWT_SECRET")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| if dev_mode { DEV_JWT_SECRET.into() } else { String::new() }),
+        };
         Self {
             bind_addr: std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:8000".into()),
             database_url: std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:db.sqlite3".into()),
             redis_url: std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".into()),
-            jwt_secret: std::env::var("JWT_SECRET").unwrap_or_else(|_| "change-me-in-production".into()),
+            jwt_secret,
+            jwt_secret_file,
+            dev_mode,
+            access_token_ttl_secs: std::env::var("ACCESS_TOKEN_TTL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(3600),
+            refresh_token_ttl_secs: std::env::var("REFRESH_TOKEN_TTL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(86400),
+            jwt_secrets_list: std::env::var("JWT_SECRETS").unwrap_or_default(),
+            jwt_active_kid: std::env::var("JWT_ACTIVE_KID").ok().filter(|s| !s.trim().is_empty()),
+            jwt_algorithm: std::env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".into()),
+            jwt_private_key_file: std::env::var("JWT_PRIVATE_KEY_FILE").ok().filter(|s| !s.trim().is_empty()),
+            jwt_public_key_files: std::env::var("JWT_PUBLIC_KEY_FILES").unwrap_or_default(),
+            jwt_issuer: std::env::var("JWT_ISSUER").unwrap_or_else(|_| "dev-dock-manager".into()),
+            jwt_audience: std::env::var("JWT_AUDIENCE").unwrap_or_else(|_| "dev-dock-manager".into()),
             docker_network: std::env::var("DOCKER_NETWORK").unwrap_or_else(|_| "d-gui-network".into()),
             host_for_port_check: std::env::var("HOST_FOR_PORT_CHECK")
                 .unwrap_or_else(|_| "host.docker.internal".into()),
             docker_image_name: std::env::var("DOCKER_IMAGE_NAME").unwrap_or_else(|_| "gui-vnc".into()),
+            gpu_devices: std::env::var("GPU_DEVICES").ok().filter(|s| !s.trim().is_empty()),
+            nvidia_probe_image: std::env::var("NVIDIA_PROBE_IMAGE")
+                .unwrap_or_else(|_| crate::docker::nvidia::DEFAULT_PROBE_IMAGE.into()),
+            nvidia_probe_ttl_secs: std::env::var("NVIDIA_PROBE_TTL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(3600),
+            port_range: std::env::var("PORT_RANGE")
+                .ok()
+                .and_then(|s| crate::docker::ports::parse_port_range(&s))
+                .unwrap_or((1024, 65535)),
+            port_exclude: std::env::var("PORT_EXCLUDE")
+                .map(|s| crate::docker::ports::parse_port_list(&s))
+                .unwrap_or_default(),
+            free_ports_max: std::env::var("FREE_PORTS_MAX")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(100),
+            port_reservation_ttl_secs: std::env::var("PORT_RESERVATION_TTL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(300),
+            port_bind_addrs: std::env::var("PORT_BIND_ADDRS")
+                .map(|s| crate::docker::ports::parse_bind_addrs(&s))
+                .unwrap_or_default(),
+            max_containers_per_user: std::env::var("MAX_CONTAINERS_PER_USER")
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            import_max_bytes: std::env::var("IMPORT_MAX_MB")
+                .ok()
+                .and_then(|s| s.parse::<usize>().ok())
+                .unwrap_or(20 * 1024)
+                .saturating_mul(1024 * 1024),
+            trust_proxy_headers: env_flag("TRUST_PROXY_HEADERS"),
+            trusted_proxies: std::env::var("TRUSTED_PROXIES")
+                .map(|s| parse_proxy_list(&s))
+                .unwrap_or_default(),
+            disable_password_login: env_flag("DISABLE_PASSWORD_LOGIN"),
+            oidc_issuer: std::env::var("OIDC_ISSUER")
+                .ok()
+                .map(|s| s.trim().trim_end_matches('/').to_string())
+                .filter(|s| !s.is_empty()),
+            oidc_client_id: std::env::var("OIDC_CLIENT_ID").unwrap_or_default(),
+            oidc_client_secret: std::env::var("OIDC_CLIENT_SECRET").ok().filter(|s| !s.is_empty()),
+            oidc_redirect_url: std::env::var("OIDC_REDIRECT_URL").unwrap_or_default(),
+            oidc_scopes: std::env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid profile email".into()),
+            oidc_groups_claim: std::env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| "groups".into()),
+            oidc_staff_groups: std::env::var("OIDC_STAFF_GROUPS")
+                .map(|s| split_list(&s))
+                .unwrap_or_default(),
+            oidc_role_groups: std::env::var("OIDC_ROLE_GROUPS")
+                .map(|s| {
+                    split_list(&s)
+                        .into_iter()
+                        .filter_map(|item| {
+                            // 群組名稱可能含 `:`，以最後一個 `:` 分隔角色
+                            let (group, role) = item.rsplit_once(':')?;
+                            Some((group.trim().to_string(), role.trim().to_string()))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            oidc_default_role: std::env::var("OIDC_DEFAULT_ROLE").unwrap_or_else(|_| "user".into()),
+            oidc_post_login_url: std::env::var("OIDC_POST_LOGIN_URL").unwrap_or_else(|_| "/login".into()),
+            mfa_issuer: std::env::var("MFA_ISSUER").unwrap_or_else(|_| "Dev Dock Manager".into()),
+            mfa_challenge_ttl_secs: std::env::var("MFA_CHALLENGE_TTL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(300),
+            auth_backend: std::env::var("AUTH_BACKEND")
+                .map(|s| s.trim().to_ascii_lowercase())
+                .unwrap_or_else(|_| "local".into()),
+            ldap_url: std::env::var("LDAP_URL").unwrap_or_default(),
+            ldap_starttls: env_flag("LDAP_STARTTLS"),
+            ldap_bind_dn: std::env::var("LDAP_BIND_DN").ok().filter(|s| !s.trim().is_empty()),
+            ldap_bind_password: std::env::var("LDAP_BIND_PASSWORD").unwrap_or_default(),
+            ldap_user_base: std::env::var("LDAP_USER_BASE").unwrap_or_default(),
+            ldap_user_filter: std::env::var("LDAP_USER_FILTER").unwrap_or_else(|_| "(uid={username})".into()),
+            ldap_id_attr: std::env::var("LDAP_ID_ATTR").unwrap_or_else(|_| "entryUUID".into()),
+            ldap_email_attr: std::env::var("LDAP_EMAIL_ATTR").unwrap_or_else(|_| "mail".into()),
+            ldap_group_attr: std::env::var("LDAP_GROUP_ATTR").unwrap_or_else(|_| "memberOf".into()),
+            ldap_group_base: std::env::var("LDAP_GROUP_BASE").ok().filter(|s| !s.trim().is_empty()),
+            ldap_group_filter: std::env::var("LDAP_GROUP_FILTER")
+                .unwrap_or_else(|_| "(|(member={dn})(uniqueMember={dn})(memberUid={username}))".into()),
+            // group DN 本身含 `,`，以 `;` 分隔
+            ldap_staff_groups: std::env::var("LDAP_STAFF_GROUPS")
+                .map(|s| {
+                    s.split(';')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            console_recording: env_flag("CONSOLE_RECORDING"),
+            console_recording_dir: std::env::var("CONSOLE_RECORDING_DIR")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .unwrap_or_else(|| "recordings".into()),
+            console_recording_input: std::env::var("CONSOLE_RECORDING_INPUT")
+                .map(|s| matches!(s.trim(), "1" | "true" | "yes"))
+                .unwrap_or(true),
+            console_recording_max_bytes: std::env::var("CONSOLE_RECORDING_MAX_MB")
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+                .unwrap_or(100)
+                .saturating_mul(1024 * 1024),
+            console_recording_retention_days: std::env::var("CONSOLE_RECORDING_RETENTION_DAYS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(30),
+        }
+    }
+
+    /// HS256 簽章金鑰（kid, secret）：JWT_SECRETS，未設定時為 `default` 的 JWT_SECRET。
+    pub fn jwt_secrets(&self) -> Vec<(String, String)> {
+        let list = crate::jwt::parse_kid_list(&self.jwt_secrets_list);
+        if list.is_empty() {
+            vec![("default".to_string(), self.jwt_secret.clone())]
+        } else {
+            list
+        }
+    }
+
+    /// 啟動前檢查：token TTL 必須為正；TRUST_PROXY_HEADERS 需搭配 TRUSTED_PROXIES；啟用 OIDC 時 client id、redirect URL 與角色對應必須有效；
+    /// AUTH_BACKEND=ldap 時需有 LDAP_URL、LDAP_USER_BASE；非 dev 模式且使用 HS* 時，
+    /// 拒絕未設定、預設值或短於 `MIN_JWT_SECRET_LEN` 的 secret（含 JWT_SECRETS 每一把）。
+    pub fn validate(&self) -> Result<(), String> {
+        if self.access_token_ttl_secs <= 0 || self.refresh_token_ttl_secs <= 0 || self.mfa_challenge_ttl_secs <= 0 {
+            return Err("ACCESS_TOKEN_TTL, REFRESH_TOKEN_TTL and MFA_CHALLENGE_TTL must be positive".into());
+        }
+        if let Some(path) = &self.jwt_secret_file {
+            if self.jwt_secret.is_empty() && self.jwt_secrets_list.trim().is_empty() {
+                return Err(format!("JWT_SECRET_FILE {} is missing, unreadable or empty", path));
+            }
+        }
+        if self.oidc_issuer.is_some() {
+            if self.oidc_client_id.trim().is_empty() || self.oidc_redirect_url.trim().is_empty() {
+                return Err("OIDC_CLIENT_ID and OIDC_REDIRECT_URL are required when OIDC_ISSUER is set".into());
+            }
+            let roles = std::iter::once(&self.oidc_default_role).chain(self.oidc_role_groups.iter().map(|(_, r)| r));
+            for role in roles {
+                if crate::permissions::Role::parse(role).is_none() {
+                    return Err(format!("unknown role {:?} in OIDC settings", role));
+                }
+            }
+        }
+        if self.trust_proxy_headers && self.trusted_proxies.is_empty() {
+            return Err("TRUST_PROXY_HEADERS=true requires TRUSTED_PROXIES (the reverse proxy's address or CIDR)".into());
+        }
+        match self.auth_backend.as_str() {
+            "local" => {}
+            "ldap" => {
+                if self.ldap_url.trim().is_empty() || self.ldap_user_base.trim().is_empty() {
+                    return Err("LDAP_URL and LDAP_USER_BASE are required when AUTH_BACKEND=ldap".into());
+                }
+                if !self.ldap_user_filter.contains("{username}") {
+                    return Err("LDAP_USER_FILTER must contain {username}".into());
+                }
+            }
+            other => return Err(format!("unknown AUTH_BACKEND {:?} (expected local or ldap)", other)),
+        }
+        if self.dev_mode || !self.jwt_algorithm.to_ascii_uppercase().starts_with("HS") {
+            return Ok(());
+        }
+        for (kid, secret) in self.jwt_secrets() {
+            if secret.is_empty() {
+                return Err("JWT_SECRET is not set (use JWT_SECRET, JWT_SECRET_FILE or DEV_MODE=true)".into());
+            }
+            if secret == DEV_JWT_SECRET {
+                return Err(format!("JWT secret {:?} is the default value; set a real secret", kid));
+            }
+            if secret.len() < MIN_JWT_SECRET_LEN {
+                return Err(format!(
+                    "JWT secret {:?} is shorter than {} bytes",
+                    kid, MIN_JWT_SECRET_LEN
+                ));
+            }
         }
+        Ok(())
     }
 }
@@ -0,0 +1,212 @@
+//! 個人 API token（CI / CLI 用）：明文只在建立時回傳一次，資料庫只存 SHA-256；
+//! 每個 token 有 scope（見 `permissions::Scope`）與可選的到期時間，撤銷後立即失效。
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::Engine;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+use crate::permissions::Scope;
+
+/// 明文 token 的前綴，用來和 JWT 區分。
+pub const TOKEN_PREFIX: &str = "ddm_";
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// GET /me/tokens 的單筆紀錄（不含 hash）。
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub name: String,
+    /// 明文開頭幾個字元，供辨識。
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+impl ApiToken {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+impl<'r> sqlx::FromRow<'r, sqlx::sqlite::SqliteRow> for ApiToken {
+    fn from_row(row: &'r sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+        let scopes: String = row.try_get("scopes")?;
+        Ok(ApiToken {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            name: row.try_get("name")?,
+            prefix: row.try_get("prefix")?,
+            scopes: scopes.split_whitespace().filter_map(Scope::parse).collect(),
+            expires_at: row.try_get("expires_at")?,
+            created_at: row.try_get("created_at")?,
+            last_used_at: row.try_get("last_used_at")?,
+            revoked_at: row.try_get("revoked_at")?,
+        })
+    }
+}
+
+const COLUMNS: &str =
+    "id, user_id, name, prefix, scopes, expires_at, created_at, last_used_at, revoked_at";
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// 是否為 API token 格式（而非 JWT）。
+pub fn is_api_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// 建立 token；回傳明文（只此一次）與紀錄。
+pub async fn create(
+    pool: &SqlitePool,
+    user_id: i64,
+    name: &str,
+    scopes: &[Scope],
+    expires_at: Option<i64>,
+) -> Result<(String, ApiToken), sqlx::Error> {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let token = format!(
+        "{}{}",
+        TOKEN_PREFIX,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(secret)
+    );
+    let prefix: String = token.chars().take(TOKEN_PREFIX.len() + 6).collect();
+    let scopes_str = scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" ");
+    let res = sqlx::query(
+        "INSERT INTO api_tokens (user_id, name, token_hash, prefix, scopes, expires_at, created_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(user_id)
+    .bind(name)
+    .bind(hash_token(&token))
+    .bind(&prefix)
+    .bind(scopes_str)
+    .bind(expires_at)
+    .bind(now())
+    .execute(pool)
+    .await?;
+    let record = sqlx::query_as::<_, ApiToken>(&format!("SELECT {} FROM api_tokens WHERE id = ?", COLUMNS))
+        .bind(res.last_insert_rowid())
+        .fetch_one(pool)
+        .await?;
+    Ok((token, record))
+}
+
+pub async fn list_for_user(pool: &SqlitePool, user_id: i64) -> Result<Vec<ApiToken>, sqlx::Error> {
+    sqlx::query_as::<_, ApiToken>(&format!(
+        "SELECT {} FROM api_tokens WHERE user_id = ? ORDER BY id",
+        COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// 撤銷自己的 token；不存在或已撤銷時回 false。
+pub async fn revoke(pool: &SqlitePool, id: i64, user_id: i64) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE api_tokens SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(now())
+    .bind(id)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 以明文查詢有效（未撤銷、未過期）的 token，並更新 last_used_at。
+pub async fn authenticate(pool: &SqlitePool, token: &str) -> Result<Option<ApiToken>, sqlx::Error> {
+    let now = now();
+    let found = sqlx::query_as::<_, ApiToken>(&format!(
+        "SELECT {} FROM api_tokens WHERE token_hash = ? AND revoked_at IS NULL \
+         AND (expires_at IS NULL OR expires_at > ?)",
+        COLUMNS
+    ))
+    .bind(hash_token(token))
+    .bind(now)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(t) = &found {
+        sqlx::query("UPDATE api_tokens SET last_used_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(t.id)
+            .execute(pool)
+            .await?;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permissions::Role;
+
+    async fn setup() -> (tempfile::TempDir, SqlitePool, i64) {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let id = crate::db::user::create_user(&pool, "alice", "x", None, Role::User).await.unwrap();
+        (dir, pool, id)
+    }
+
+    #[tokio::test]
+    async fn create_stores_only_the_hash() {
+        let (_dir, pool, id) = setup().await;
+        let (token, record) = create(&pool, id, "ci", &[Scope::Read, Scope::Console], None).await.unwrap();
+        assert!(is_api_token(&token) && !is_api_token("eyJhbGciOi"));
+        assert!(token.starts_with(&record.prefix));
+        assert_eq!(record.scopes, [Scope::Read, Scope::Console]);
+        assert!(record.has_scope(Scope::Console) && !record.has_scope(Scope::ContainersControl));
+        let stored: String = sqlx::query_scalar("SELECT token_hash FROM api_tokens WHERE id = ?")
+            .bind(record.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, hash_token(&token));
+
+        let found = authenticate(&pool, &token).await.unwrap().unwrap();
+        assert_eq!((found.id, found.user_id), (record.id, id));
+        assert!(found.last_used_at.is_none());
+        let listed = list_for_user(&pool, id).await.unwrap();
+        assert!(listed[0].last_used_at.is_some());
+        assert!(authenticate(&pool, &format!("{}x", token)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_are_rejected() {
+        let (_dir, pool, id) = setup().await;
+        let (expired, _) = create(&pool, id, "old", &[Scope::Read], Some(now() - 1)).await.unwrap();
+        let (valid, _) = create(&pool, id, "new", &[Scope::Read], Some(now() + 3600)).await.unwrap();
+        assert!(authenticate(&pool, &expired).await.unwrap().is_none());
+        assert!(authenticate(&pool, &valid).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn revoked_tokens_are_rejected() {
+        let (_dir, pool, id) = setup().await;
+        let (token, record) = create(&pool, id, "ci", &[Scope::Read], None).await.unwrap();
+        // 只能撤銷自己的 token
+        assert!(!revoke(&pool, record.id, id + 1).await.unwrap());
+        assert!(authenticate(&pool, &token).await.unwrap().is_some());
+        assert!(revoke(&pool, record.id, id).await.unwrap());
+        assert!(!revoke(&pool, record.id, id).await.unwrap());
+        assert!(authenticate(&pool, &token).await.unwrap().is_none());
+        assert!(list_for_user(&pool, id).await.unwrap()[0].revoked_at.is_some());
+    }
+}
@@ -0,0 +1,154 @@
+//! 稽核紀錄：誰在何時對什麼做了什麼（登入、容器操作、console、使用者管理）。只新增不修改；
+//! 寫入見 `audit::record`，查詢與匯出見 `api::audit`。
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    /// 驗證失敗、輸入錯誤或執行失敗。
+    Failure,
+    /// 權限不足（已登入但不能操作該資源）。
+    Denied,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Denied => "denied",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Outcome> {
+        match s {
+            "success" => Some(Outcome::Success),
+            "failure" => Some(Outcome::Failure),
+            "denied" => Some(Outcome::Denied),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub created_at: i64,
+    /// 操作者；登入失敗時可能只有嘗試的 username。
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    /// 例如 `auth.login`、`container.stop`、`console.open`、`user.update`。
+    pub action: String,
+    /// 例如 `container`、`user`。
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub outcome: String,
+    pub ip: Option<String>,
+    pub details: serde_json::Value,
+}
+
+impl<'r> sqlx::FromRow<'r, sqlx::sqlite::SqliteRow> for AuditEvent {
+    fn from_row(row: &'r sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+        let details: String = row.try_get("details")?;
+        Ok(AuditEvent {
+            id: row.try_get("id")?,
+            created_at: row.try_get("created_at")?,
+            user_id: row.try_get("user_id")?,
+            username: row.try_get("username")?,
+            action: row.try_get("action")?,
+            target_type: row.try_get("target_type")?,
+            target_id: row.try_get("target_id")?,
+            outcome: row.try_get("outcome")?,
+            ip: row.try_get("ip")?,
+            details: serde_json::from_str(&details).unwrap_or_default(),
+        })
+    }
+}
+
+const COLUMNS: &str = "id, created_at, user_id, username, action, target_type, target_id, outcome, ip, details";
+
+/// 尚未寫入的事件（見 `audit::Event`）。
+pub struct NewAuditEvent<'a> {
+    pub user_id: Option<i64>,
+    pub username: Option<&'a str>,
+    pub action: &'a str,
+    pub target_type: Option<&'a str>,
+    pub target_id: Option<&'a str>,
+    pub outcome: Outcome,
+    pub ip: Option<&'a str>,
+    pub details: &'a serde_json::Value,
+}
+
+pub async fn insert(pool: &SqlitePool, event: &NewAuditEvent<'_>) -> Result<i64, sqlx::Error> {
+    let res = sqlx::query(
+        "INSERT INTO audit_events (created_at, user_id, username, action, target_type, target_id, outcome, ip, details) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(now())
+    .bind(event.user_id)
+    .bind(event.username)
+    .bind(event.action)
+    .bind(event.target_type)
+    .bind(event.target_id)
+    .bind(event.outcome.as_str())
+    .bind(event.ip)
+    .bind(event.details.to_string())
+    .execute(pool)
+    .await?;
+    Ok(res.last_insert_rowid())
+}
+
+/// 查詢條件；None 表示不篩選。
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    /// 完整名稱（`container.stop`）或前綴（`container` 符合所有 `container.*`）。
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub outcome: Option<Outcome>,
+    /// created_at >= since。
+    pub since: Option<i64>,
+    /// created_at < until。
+    pub until: Option<i64>,
+    /// id < before（分頁：傳上一頁最後一筆的 id）。
+    pub before: Option<i64>,
+}
+
+/// 依條件由新到舊列出最多 `limit` 筆。
+pub async fn list(pool: &SqlitePool, filter: &AuditFilter, limit: i64) -> Result<Vec<AuditEvent>, sqlx::Error> {
+    sqlx::query_as::<_, AuditEvent>(&format!(
+        "SELECT {} FROM audit_events WHERE \
+         (?1 IS NULL OR user_id = ?1) AND (?2 IS NULL OR username = ?2) \
+         AND (?3 IS NULL OR action = ?3 OR substr(action, 1, length(?3) + 1) = ?3 || '.') \
+         AND (?4 IS NULL OR target_type = ?4) AND (?5 IS NULL OR target_id = ?5) \
+         AND (?6 IS NULL OR outcome = ?6) AND (?7 IS NULL OR created_at >= ?7) \
+         AND (?8 IS NULL OR created_at < ?8) AND (?9 IS NULL OR id < ?9) \
+         ORDER BY id DESC LIMIT ?10",
+        COLUMNS
+    ))
+    .bind(filter.user_id)
+    .bind(filter.username.as_deref())
+    .bind(filter.action.as_deref())
+    .bind(filter.target_type.as_deref())
+    .bind(filter.target_id.as_deref())
+    .bind(filter.outcome.map(Outcome::as_str))
+    .bind(filter.since)
+    .bind(filter.until)
+    .bind(filter.before)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,218 @@
+//! 登入失敗紀錄與漸進式鎖定：同一 username 連續失敗 `MAX_FAILURES` 次即鎖定，
+//! 每次鎖定時間加倍（`BASE_LOCKOUT_SECS` 起、上限 `MAX_LOCKOUT_SECS`），登入成功或 staff 重設後歸零。
+//! 不存在的 username 也照常記錄，行為與存在的帳號一致。
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// 連續失敗幾次後鎖定。
+pub const MAX_FAILURES: i64 = 5;
+/// 第一次鎖定的秒數，之後每次加倍。
+pub const BASE_LOCKOUT_SECS: i64 = 60;
+/// 鎖定秒數上限。
+pub const MAX_LOCKOUT_SECS: i64 = 3600;
+/// 未鎖定且超過此秒數沒有失敗的紀錄會被清除。
+pub const STALE_AFTER_SECS: i64 = 86400;
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// GET /auth/lockouts 的單筆紀錄。
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginLockout {
+    pub username: String,
+    pub failed_count: i64,
+    pub lockout_level: i64,
+    /// 鎖定到期時間（UNIX 秒）；None 或已過期表示目前未鎖定。
+    pub locked_until: Option<i64>,
+    pub last_failure_at: i64,
+    pub last_ip: Option<String>,
+}
+
+impl<'r> sqlx::FromRow<'r, sqlx::sqlite::SqliteRow> for LoginLockout {
+    fn from_row(row: &'r sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        use sqlx::Row;
+        Ok(LoginLockout {
+            username: row.try_get("username")?,
+            failed_count: row.try_get("failed_count")?,
+            lockout_level: row.try_get("lockout_level")?,
+            locked_until: row.try_get("locked_until")?,
+            last_failure_at: row.try_get("last_failure_at")?,
+            last_ip: row.try_get("last_ip")?,
+        })
+    }
+}
+
+/// 目前鎖定中則回傳到期時間（UNIX 秒）。
+pub async fn locked_until(pool: &SqlitePool, username: &str) -> Result<Option<i64>, sqlx::Error> {
+    let until: Option<Option<i64>> =
+        sqlx::query_scalar("SELECT locked_until FROM login_lockouts WHERE username = ?")
+            .bind(username)
+            .fetch_optional(pool)
+            .await?;
+    Ok(until.flatten().filter(|t| *t > now()))
+}
+
+/// 記錄一次失敗；達到上限時鎖定並回傳鎖定到期時間。
+pub async fn record_failure(
+    pool: &SqlitePool,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let now = now();
+    let mut tx = pool.begin().await?;
+    let (failed_count, level): (i64, i64) = sqlx::query_as(
+        "INSERT INTO login_lockouts (username, failed_count, lockout_level, last_failure_at, last_ip) \
+         VALUES (?, 1, 0, ?, ?) \
+         ON CONFLICT(username) DO UPDATE SET failed_count = failed_count + 1, \
+         last_failure_at = excluded.last_failure_at, last_ip = excluded.last_ip \
+         RETURNING failed_count, lockout_level",
+    )
+    .bind(username)
+    .bind(now)
+    .bind(ip)
+    .fetch_one(&mut *tx)
+    .await?;
+    let mut locked = None;
+    if failed_count >= MAX_FAILURES {
+        let secs = BASE_LOCKOUT_SECS
+            .saturating_mul(1i64 << level.clamp(0, 20))
+            .min(MAX_LOCKOUT_SECS);
+        let until = now + secs;
+        sqlx::query(
+            "UPDATE login_lockouts SET failed_count = 0, lockout_level = lockout_level + 1, locked_until = ? \
+             WHERE username = ?",
+        )
+        .bind(until)
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+        locked = Some(until);
+    }
+    tx.commit().await?;
+    Ok(locked)
+}
+
+/// 登入成功：清除失敗紀錄。
+pub async fn record_success(pool: &SqlitePool, username: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_lockouts WHERE username = ?")
+        .bind(username)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 所有失敗/鎖定紀錄（鎖定中的優先，其次最近失敗）。
+pub async fn list(pool: &SqlitePool) -> Result<Vec<LoginLockout>, sqlx::Error> {
+    sqlx::query_as::<_, LoginLockout>(
+        "SELECT username, failed_count, lockout_level, locked_until, last_failure_at, last_ip \
+         FROM login_lockouts ORDER BY (locked_until > ?) DESC, last_failure_at DESC",
+    )
+    .bind(now())
+    .fetch_all(pool)
+    .await
+}
+
+/// staff 重設：移除該 username 的紀錄；不存在回 false。
+pub async fn reset(pool: &SqlitePool, username: &str) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM login_lockouts WHERE username = ?")
+        .bind(username)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 清除未鎖定且久未失敗的紀錄（避免以隨機 username 灌爆資料表）。
+pub async fn prune(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let now = now();
+    let res = sqlx::query(
+        "DELETE FROM login_lockouts WHERE (locked_until IS NULL OR locked_until <= ?) AND last_failure_at <= ?",
+    )
+    .bind(now)
+    .bind(now - STALE_AFTER_SECS)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// 常駐迴圈：定期清除過期紀錄。
+pub async fn run_prune(pool: SqlitePool, interval: std::time::Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = prune(&pool).await {
+            tracing::warn!("Login lockouts: prune failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 連續失敗直到鎖定，回傳鎖定的秒數。
+    async fn fail_until_locked(pool: &SqlitePool, username: &str) -> i64 {
+        for _ in 1..MAX_FAILURES {
+            assert_eq!(record_failure(pool, username, Some("10.0.0.1")).await.unwrap(), None);
+        }
+        let start = now();
+        let until = record_failure(pool, username, Some("10.0.0.1")).await.unwrap().unwrap();
+        until - start
+    }
+
+    #[tokio::test]
+    async fn locks_after_threshold_with_growing_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        assert_eq!(locked_until(&pool, "alice").await.unwrap(), None);
+
+        let secs = fail_until_locked(&pool, "alice").await;
+        assert!((BASE_LOCKOUT_SECS..=BASE_LOCKOUT_SECS + 1).contains(&secs), "{}", secs);
+        assert!(locked_until(&pool, "alice").await.unwrap().is_some());
+
+        // 每次再鎖定時間加倍，直到上限
+        let mut expected = BASE_LOCKOUT_SECS;
+        for _ in 0..8 {
+            expected = (expected * 2).min(MAX_LOCKOUT_SECS);
+            let secs = fail_until_locked(&pool, "alice").await;
+            assert!((expected..=expected + 1).contains(&secs), "expected {}, got {}", expected, secs);
+        }
+        assert_eq!(expected, MAX_LOCKOUT_SECS);
+
+        let rows = list(&pool).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].failed_count, rows[0].lockout_level), (0, 9));
+        assert_eq!(rows[0].last_ip.as_deref(), Some("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn success_and_reset_clear_the_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        fail_until_locked(&pool, "alice").await;
+        record_success(&pool, "alice").await.unwrap();
+        assert_eq!(locked_until(&pool, "alice").await.unwrap(), None);
+        assert!(list(&pool).await.unwrap().is_empty());
+
+        // 歸零後重新從第一級鎖定開始
+        let secs = fail_until_locked(&pool, "alice").await;
+        assert!(secs <= BASE_LOCKOUT_SECS + 1, "{}", secs);
+
+        assert!(reset(&pool, "alice").await.unwrap());
+        assert!(!reset(&pool, "alice").await.unwrap());
+        assert_eq!(locked_until(&pool, "alice").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn usernames_are_tracked_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        fail_until_locked(&pool, "alice").await;
+        record_failure(&pool, "nobody", None).await.unwrap();
+        assert_eq!(locked_until(&pool, "nobody").await.unwrap(), None);
+        assert_eq!(list(&pool).await.unwrap()[0].username, "alice");
+    }
+}
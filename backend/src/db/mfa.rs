@@ -0,0 +1,199 @@
+//! MFA 儲存：每位使用者一組 TOTP secret（確認前為待啟用）與雜湊過的單次復原碼。
+//! TOTP secret 必須可還原才能計算驗證碼，因此以明文存放；復原碼只存 SHA-256。
+
+use sqlx::SqlitePool;
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[derive(Debug, Clone)]
+pub struct UserTotp {
+    /// base32。
+    pub secret: String,
+    /// None 表示已產生 secret 但尚未以驗證碼確認。
+    pub confirmed_at: Option<i64>,
+    /// 最後一次使用的 TOTP 時間步；同一步（或更早）的驗證碼不再接受。
+    pub last_used_step: i64,
+}
+
+impl UserTotp {
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed_at.is_some()
+    }
+}
+
+pub async fn get_totp(pool: &SqlitePool, user_id: i64) -> Result<Option<UserTotp>, sqlx::Error> {
+    let row: Option<(String, Option<i64>, i64)> =
+        sqlx::query_as("SELECT secret, confirmed_at, last_used_step FROM user_totp WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(row.map(|(secret, confirmed_at, last_used_step)| UserTotp {
+        secret,
+        confirmed_at,
+        last_used_step,
+    }))
+}
+
+/// 使用者是否已啟用（確認過）TOTP。
+pub async fn is_enabled(pool: &SqlitePool, user_id: i64) -> Result<bool, sqlx::Error> {
+    Ok(get_totp(pool, user_id).await?.is_some_and(|t| t.is_confirmed()))
+}
+
+/// 開始（或重新開始）設定：寫入未確認的 secret，取代先前未確認的。
+/// 已確認的 secret 不會被取代（須先 `disable`），此時回 false。
+pub async fn start_totp(pool: &SqlitePool, user_id: i64, secret: &str) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "INSERT INTO user_totp (user_id, secret, confirmed_at, last_used_step, created_at) VALUES (?, ?, NULL, 0, ?) \
+         ON CONFLICT(user_id) DO UPDATE SET secret = excluded.secret, confirmed_at = NULL, \
+         last_used_step = 0, created_at = excluded.created_at WHERE user_totp.confirmed_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(secret)
+    .bind(now())
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 以第一個正確的驗證碼確認設定。
+pub async fn confirm_totp(pool: &SqlitePool, user_id: i64, step: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE user_totp SET confirmed_at = ?, last_used_step = ? WHERE user_id = ?")
+        .bind(now())
+        .bind(step)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 記錄已使用的時間步；該步已用過（重放）時回 false。以單一 UPDATE 完成，並行請求只有一個會成功。
+pub async fn use_step(pool: &SqlitePool, user_id: i64, step: i64) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query("UPDATE user_totp SET last_used_step = ? WHERE user_id = ? AND last_used_step < ?")
+        .bind(step)
+        .bind(user_id)
+        .bind(step)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 停用 MFA：刪除 TOTP 與所有復原碼。
+pub async fn disable(pool: &SqlitePool, user_id: i64) -> Result<bool, sqlx::Error> {
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    let res = sqlx::query("DELETE FROM user_totp WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 以新的一組復原碼（SHA-256）取代舊的。
+pub async fn replace_recovery_codes(pool: &SqlitePool, user_id: i64, hashes: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for hash in hashes {
+        sqlx::query("INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// 使用一組復原碼；不存在或已用過時回 false。
+pub async fn use_recovery_code(pool: &SqlitePool, user_id: i64, hash: &str) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE mfa_recovery_codes SET used_at = ? WHERE user_id = ? AND code_hash = ? AND used_at IS NULL",
+    )
+    .bind(now())
+    .bind(user_id)
+    .bind(hash)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+pub async fn recovery_codes_remaining(pool: &SqlitePool, user_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM mfa_recovery_codes WHERE user_id = ? AND used_at IS NULL")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permissions::Role;
+
+    async fn setup() -> (tempfile::TempDir, SqlitePool, i64) {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let id = crate::db::user::create_user(&pool, "alice", "x", None, Role::User).await.unwrap();
+        (dir, pool, id)
+    }
+
+    #[tokio::test]
+    async fn use_step_rejects_repeated_and_older_steps() {
+        let (_dir, pool, id) = setup().await;
+        assert!(start_totp(&pool, id, "SECRET").await.unwrap());
+        confirm_totp(&pool, id, 100).await.unwrap();
+        // 確認時用的那一步已算使用過
+        assert!(!use_step(&pool, id, 100).await.unwrap());
+        assert!(use_step(&pool, id, 101).await.unwrap());
+        assert!(!use_step(&pool, id, 101).await.unwrap());
+        assert!(!use_step(&pool, id, 99).await.unwrap());
+        assert!(use_step(&pool, id, 105).await.unwrap());
+        assert_eq!(get_totp(&pool, id).await.unwrap().unwrap().last_used_step, 105);
+        // 沒有 TOTP 的使用者
+        assert!(!use_step(&pool, id + 1, 200).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn recovery_code_works_once() {
+        let (_dir, pool, id) = setup().await;
+        replace_recovery_codes(&pool, id, &["h1".into(), "h2".into()]).await.unwrap();
+        assert_eq!(recovery_codes_remaining(&pool, id).await.unwrap(), 2);
+        assert!(use_recovery_code(&pool, id, "h1").await.unwrap());
+        assert!(!use_recovery_code(&pool, id, "h1").await.unwrap());
+        assert!(!use_recovery_code(&pool, id, "unknown").await.unwrap());
+        assert!(!use_recovery_code(&pool, id + 1, "h2").await.unwrap());
+        assert_eq!(recovery_codes_remaining(&pool, id).await.unwrap(), 1);
+        // 重新產生後舊碼全部失效
+        replace_recovery_codes(&pool, id, &["h3".into()]).await.unwrap();
+        assert!(!use_recovery_code(&pool, id, "h2").await.unwrap());
+        assert!(use_recovery_code(&pool, id, "h3").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn enrollment_cannot_replace_confirmed_secret() {
+        let (_dir, pool, id) = setup().await;
+        assert!(start_totp(&pool, id, "FIRST").await.unwrap());
+        // 未確認前可以重新開始
+        assert!(start_totp(&pool, id, "SECOND").await.unwrap());
+        assert!(!is_enabled(&pool, id).await.unwrap());
+        confirm_totp(&pool, id, 7).await.unwrap();
+        assert!(is_enabled(&pool, id).await.unwrap());
+
+        assert!(!start_totp(&pool, id, "ATTACKER").await.unwrap());
+        let totp = get_totp(&pool, id).await.unwrap().unwrap();
+        assert_eq!((totp.secret.as_str(), totp.last_used_step), ("SECOND", 7));
+        assert!(totp.is_confirmed());
+
+        // 停用後才能重新設定
+        assert!(disable(&pool, id).await.unwrap());
+        assert!(start_totp(&pool, id, "THIRD").await.unwrap());
+        assert!(!is_enabled(&pool, id).await.unwrap());
+    }
+}
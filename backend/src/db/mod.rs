@@ -1,8 +1,38 @@
-//! 資料庫層：使用者查詢與密碼驗證（僅 JWT 登入，無 SocialAccount/Google）。
+//! 資料庫層：使用者查詢與密碼驗證（僅 JWT 登入，無 SocialAccount/Google）、refresh token、個人 API token、MFA（TOTP 與復原碼）、OIDC 登入狀態、全域設定、登入失敗鎖定、host port 保留表、稽核紀錄、console 錄影。
 
+pub mod api_token;
+pub mod audit;
+pub mod login_lockout;
+pub mod mfa;
+pub mod oidc_state;
+pub mod port_reservation;
+pub mod recording;
+pub mod refresh_token;
+pub mod setting;
 pub mod user;
 
 pub use user::User;
 pub use user::{get_by_id, get_by_username, verify_password};
 pub use user::create_user;
 pub use user::hash_password;
+
+/// 測試用：暫存目錄中的 SQLite，已執行 migrations。
+#[cfg(test)]
+pub(crate) async fn test_pool(dir: &tempfile::TempDir) -> sqlx::SqlitePool {
+    let opts = sqlx::sqlite::SqliteConnectOptions::new()
+        .filename(dir.path().join("db.sqlite3"))
+        .create_if_missing(true);
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(4)
+        .connect_with(opts)
+        .await
+        .unwrap();
+    let migrations = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+    sqlx::migrate::Migrator::new(migrations)
+        .await
+        .unwrap()
+        .run(&pool)
+        .await
+        .unwrap();
+    pool
+}
@@ -0,0 +1,47 @@
+//! 進行中的 OIDC 登入：`state` 對應 PKCE code verifier 與 nonce，單次使用，逾時作廢。
+//! 存在 SQLite 而非記憶體，重啟後仍可完成登入。
+
+use sqlx::SqlitePool;
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// 新增一筆登入狀態，順便清除已過期的。
+pub async fn insert(
+    pool: &SqlitePool,
+    state: &str,
+    code_verifier: &str,
+    nonce: &str,
+    ttl_secs: i64,
+) -> Result<(), sqlx::Error> {
+    let now = now();
+    sqlx::query("DELETE FROM oidc_login_states WHERE expires_at <= ?")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    sqlx::query("INSERT INTO oidc_login_states (state, code_verifier, nonce, expires_at) VALUES (?, ?, ?, ?)")
+        .bind(state)
+        .bind(code_verifier)
+        .bind(nonce)
+        .bind(now + ttl_secs)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 取出並刪除（單次使用）；不存在或已過期回 None。回傳 (code_verifier, nonce)。
+pub async fn take(pool: &SqlitePool, state: &str) -> Result<Option<(String, String)>, sqlx::Error> {
+    let row: Option<(String, String, i64)> = sqlx::query_as(
+        "DELETE FROM oidc_login_states WHERE state = ? RETURNING code_verifier, nonce, expires_at",
+    )
+    .bind(state)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row
+        .filter(|(_, _, expires_at)| *expires_at > now())
+        .map(|(verifier, nonce, _)| (verifier, nonce)))
+}
@@ -0,0 +1,117 @@
+//! host port 保留表：API 驗證通過後以 task_id 持有 ssh port（有 TTL），直到 worker 建立容器後確認或失敗時釋放，
+//! 避免兩個請求在入列與 create_container 之間搶到同一個 port。逾期的保留視同不存在，並由背景工作清除。
+
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+
+/// worker 確認後保留的寬限時間（秒），讓容器索引有時間收到 create 事件。
+pub const CONFIRM_GRACE_SECS: i64 = 30;
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// 嘗試保留 port；已被未逾期的保留佔用時回 false。逾期的保留直接被覆蓋（單一 upsert，無競態）。
+pub async fn acquire(
+    pool: &SqlitePool,
+    port: u16,
+    task_id: &str,
+    owner_id: i64,
+    ttl_secs: i64,
+) -> Result<bool, sqlx::Error> {
+    let now = now();
+    let res = sqlx::query(
+        "INSERT INTO port_reservations (port, task_id, owner_id, status, expires_at) VALUES (?, ?, ?, 'held', ?) \
+         ON CONFLICT(port) DO UPDATE SET task_id = excluded.task_id, owner_id = excluded.owner_id, \
+         status = 'held', expires_at = excluded.expires_at, created_at = datetime('now') \
+         WHERE port_reservations.expires_at <= ?",
+    )
+    .bind(port as i64)
+    .bind(task_id)
+    .bind(owner_id)
+    .bind(now + ttl_secs)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 釋放 task 持有的保留（入列失敗或 worker 建立容器失敗）。
+pub async fn release(pool: &SqlitePool, port: u16, task_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM port_reservations WHERE port = ? AND task_id = ?")
+        .bind(port as i64)
+        .bind(task_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 容器已建立：port 之後由 Docker/容器索引負責，保留只再維持一小段寬限時間。
+pub async fn confirm(pool: &SqlitePool, port: u16, task_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE port_reservations SET status = 'confirmed', expires_at = ? WHERE port = ? AND task_id = ?",
+    )
+    .bind(now() + CONFIRM_GRACE_SECS)
+    .bind(port as i64)
+    .bind(task_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// 目前有效（未逾期）的保留 port。
+pub async fn active_ports(pool: &SqlitePool) -> Result<HashSet<u16>, sqlx::Error> {
+    let ports: Vec<i64> =
+        sqlx::query_scalar("SELECT port FROM port_reservations WHERE expires_at > ?")
+            .bind(now())
+            .fetch_all(pool)
+            .await?;
+    Ok(ports.into_iter().filter_map(|p| u16::try_from(p).ok()).collect())
+}
+
+/// 使用者尚在等待 worker 建立的容器數（未逾期且未確認的保留），供容器配額計算。
+pub async fn pending_for_owner(pool: &SqlitePool, owner_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM port_reservations WHERE owner_id = ? AND status = 'held' AND expires_at > ?",
+    )
+    .bind(owner_id)
+    .bind(now())
+    .fetch_one(pool)
+    .await
+}
+
+/// 該 port 是否有有效保留。
+pub async fn is_reserved(pool: &SqlitePool, port: u16) -> Result<bool, sqlx::Error> {
+    let found: Option<i64> = sqlx::query_scalar(
+        "SELECT 1 FROM port_reservations WHERE port = ? AND expires_at > ?",
+    )
+    .bind(port as i64)
+    .bind(now())
+    .fetch_optional(pool)
+    .await?;
+    Ok(found.is_some())
+}
+
+/// 刪除逾期的保留；回傳刪除筆數。
+pub async fn gc_expired(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM port_reservations WHERE expires_at <= ?")
+        .bind(now())
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected())
+}
+
+/// 常駐迴圈：定期清除逾期保留。
+pub async fn run_gc(pool: SqlitePool, interval: std::time::Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match gc_expired(&pool).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Port reservations: removed {} expired holds", n),
+            Err(e) => tracing::warn!("Port reservations: gc failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Console 錄影的 metadata：檔案本身（asciicast v2）放在 CONSOLE_RECORDING_DIR，寫入見 `recording::Recorder`，
+//! 查詢與下載見 `api::recordings`。
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Recording {
+    pub id: String,
+    /// 完整容器 ID。
+    pub container_id: String,
+    /// 錄影開始時的容器名稱（不含開頭的 `/`）。
+    pub container_name: Option<String>,
+    pub user_id: Option<i64>,
+    pub username: String,
+    /// `shell` 或 `attach`。
+    pub mode: String,
+    /// 錄影目錄下的檔名。
+    #[serde(skip)]
+    pub file: String,
+    pub started_at: i64,
+    /// 進行中的 session 為 None。
+    pub ended_at: Option<i64>,
+    pub size_bytes: i64,
+    /// 超過 CONSOLE_RECORDING_MAX_MB 後停止寫入。
+    pub truncated: bool,
+}
+
+const COLUMNS: &str =
+    "id, container_id, container_name, user_id, username, mode, file, started_at, ended_at, size_bytes, truncated";
+
+/// 尚未寫入的錄影（session 開始時建立）。
+pub struct NewRecording<'a> {
+    pub id: &'a str,
+    pub container_id: &'a str,
+    pub container_name: Option<&'a str>,
+    pub user_id: i64,
+    pub username: &'a str,
+    pub mode: &'a str,
+    pub file: &'a str,
+}
+
+pub async fn insert(pool: &SqlitePool, rec: &NewRecording<'_>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO console_recordings (id, container_id, container_name, user_id, username, mode, file, started_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(rec.id)
+    .bind(rec.container_id)
+    .bind(rec.container_name)
+    .bind(rec.user_id)
+    .bind(rec.username)
+    .bind(rec.mode)
+    .bind(rec.file)
+    .bind(now())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// session 結束：記下結束時間、檔案大小與是否被截斷。
+pub async fn finish(pool: &SqlitePool, id: &str, size_bytes: u64, truncated: bool) -> Result<(), sqlx::Error> {
+    finish_at(pool, id, now(), size_bytes, truncated).await
+}
+
+pub async fn finish_at(
+    pool: &SqlitePool,
+    id: &str,
+    ended_at: i64,
+    size_bytes: u64,
+    truncated: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE console_recordings SET ended_at = ?, size_bytes = ?, truncated = ? WHERE id = ?")
+        .bind(ended_at)
+        .bind(size_bytes as i64)
+        .bind(truncated)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get(pool: &SqlitePool, id: &str) -> Result<Option<Recording>, sqlx::Error> {
+    sqlx::query_as::<_, Recording>(&format!("SELECT {} FROM console_recordings WHERE id = ?", COLUMNS))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// 某容器的錄影，由新到舊。`container` 可為完整 ID、ID 前綴或名稱；`user_id` 有值時只列該使用者的。
+pub async fn list_for_container(
+    pool: &SqlitePool,
+    container: &str,
+    user_id: Option<i64>,
+) -> Result<Vec<Recording>, sqlx::Error> {
+    sqlx::query_as::<_, Recording>(&format!(
+        "SELECT {} FROM console_recordings WHERE \
+         (container_id = ?1 OR container_name = ?1 OR substr(container_id, 1, length(?1)) = ?1) \
+         AND (?2 IS NULL OR user_id = ?2) \
+         ORDER BY started_at DESC, id",
+        COLUMNS
+    ))
+    .bind(container.trim_start_matches('/'))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn delete(pool: &SqlitePool, id: &str) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM console_recordings WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 在 `before`（Unix 秒）之前結束的錄影，供保留期限清除。
+pub async fn ended_before(pool: &SqlitePool, before: i64) -> Result<Vec<Recording>, sqlx::Error> {
+    sqlx::query_as::<_, Recording>(&format!(
+        "SELECT {} FROM console_recordings WHERE ended_at IS NOT NULL AND ended_at < ?",
+        COLUMNS
+    ))
+    .bind(before)
+    .fetch_all(pool)
+    .await
+}
+
+/// 尚未結束的錄影（服務中斷時留下的）。
+pub async fn unfinished(pool: &SqlitePool) -> Result<Vec<Recording>, sqlx::Error> {
+    sqlx::query_as::<_, Recording>(&format!(
+        "SELECT {} FROM console_recordings WHERE ended_at IS NULL",
+        COLUMNS
+    ))
+    .fetch_all(pool)
+    .await
+}
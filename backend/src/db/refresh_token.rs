@@ -0,0 +1,222 @@
+//! Refresh token 紀錄：每個 refresh token 以 jti 存一筆，使用一次即輪替成新的（同一 family）。
+//! 已用過的 token 再被拿來 refresh 視為外洩（reuse），整個 family 一併撤銷。
+
+use sqlx::SqlitePool;
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// 輪替結果。
+pub enum RotateOutcome {
+    /// 舊 token 已標記使用，新 token 已寫入。
+    Rotated,
+    /// 舊 token 曾被使用過：整個 family 已撤銷。
+    Reused,
+    /// 查無此 jti、已過期、已撤銷（登出），或不屬於該使用者。
+    Invalid,
+}
+
+/// (family_id, user_id, expires_at, used_at, revoked_at)
+type TokenState = (String, i64, i64, Option<i64>, Option<i64>);
+
+/// 登入或輪替時寫入新的 refresh token。
+pub async fn insert(
+    pool: &SqlitePool,
+    jti: &str,
+    user_id: i64,
+    family_id: &str,
+    expires_at: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO refresh_tokens (jti, user_id, family_id, expires_at, created_at) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(jti)
+    .bind(user_id)
+    .bind(family_id)
+    .bind(expires_at)
+    .bind(now())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// 以新 jti 取代舊 jti（同一 transaction）；舊 token 已用過時撤銷整個 family。
+/// 以條件式 UPDATE（`used_at IS NULL`）標記使用，同一 token 併發 refresh 時只有一個會成功，另一個視為 reuse。
+pub async fn rotate(
+    pool: &SqlitePool,
+    old_jti: &str,
+    new_jti: &str,
+    user_id: i64,
+    expires_at: i64,
+) -> Result<RotateOutcome, sqlx::Error> {
+    let now = now();
+    let mut tx = pool.begin().await?;
+    let claimed: Option<(String,)> = sqlx::query_as(
+        "UPDATE refresh_tokens SET used_at = ?, replaced_by = ? \
+         WHERE jti = ? AND user_id = ? AND expires_at > ? AND used_at IS NULL AND revoked_at IS NULL \
+         RETURNING family_id",
+    )
+    .bind(now)
+    .bind(new_jti)
+    .bind(old_jti)
+    .bind(user_id)
+    .bind(now)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some((family_id,)) = claimed {
+        sqlx::query(
+            "INSERT INTO refresh_tokens (jti, user_id, family_id, expires_at, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(new_jti)
+        .bind(user_id)
+        .bind(&family_id)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        return Ok(RotateOutcome::Rotated);
+    }
+
+    // 沒有更新到：判斷是無效 token 還是 reuse
+    let row: Option<TokenState> = sqlx::query_as(
+        "SELECT family_id, user_id, expires_at, used_at, revoked_at FROM refresh_tokens WHERE jti = ?",
+    )
+    .bind(old_jti)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let (family_id, owner, old_expires_at, used_at, revoked_at) = match row {
+        Some(r) => r,
+        None => return Ok(RotateOutcome::Invalid),
+    };
+    if owner != user_id || old_expires_at <= now || (revoked_at.is_some() && used_at.is_none()) {
+        return Ok(RotateOutcome::Invalid);
+    }
+    sqlx::query("UPDATE refresh_tokens SET revoked_at = COALESCE(revoked_at, ?) WHERE family_id = ?")
+        .bind(now)
+        .bind(&family_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(RotateOutcome::Reused)
+}
+
+/// 撤銷 jti 所屬的整個 family（登出單一 session）；回傳是否找到該 token。
+pub async fn revoke_family_of(pool: &SqlitePool, jti: &str, user_id: i64) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = COALESCE(revoked_at, ?) \
+         WHERE family_id = (SELECT family_id FROM refresh_tokens WHERE jti = ? AND user_id = ?)",
+    )
+    .bind(now())
+    .bind(jti)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// 撤銷使用者所有 refresh token（登出所有裝置）。
+pub async fn revoke_all_for_user(pool: &SqlitePool, user_id: i64) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(now())
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// 刪除已過期的紀錄（過期後無法再用於 refresh，也不再需要 reuse 偵測）。
+pub async fn prune_expired(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM refresh_tokens WHERE expires_at <= ?")
+        .bind(now())
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected())
+}
+
+/// 常駐迴圈：定期清除過期紀錄。
+pub async fn run_prune(pool: SqlitePool, interval: std::time::Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = prune_expired(&pool).await {
+            tracing::warn!("Refresh tokens: prune failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_pool(dir: &tempfile::TempDir) -> SqlitePool {
+        let opts = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(dir.path().join("db.sqlite3"))
+            .create_if_missing(true);
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(opts)
+            .await
+            .unwrap();
+        sqlx::migrate::Migrator::new(std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations")))
+            .await
+            .unwrap()
+            .run(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users (id, username, password_hash) VALUES (1, 'u', 'x')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    async fn is_revoked(pool: &SqlitePool, jti: &str) -> bool {
+        let (revoked_at,): (Option<i64>,) = sqlx::query_as("SELECT revoked_at FROM refresh_tokens WHERE jti = ?")
+            .bind(jti)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        revoked_at.is_some()
+    }
+
+    #[tokio::test]
+    async fn second_use_is_reuse_and_revokes_family() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = test_pool(&dir).await;
+        let exp = now() + 3600;
+        insert(&pool, "a", 1, "fam", exp).await.unwrap();
+
+        assert!(matches!(rotate(&pool, "a", "b", 1, exp).await.unwrap(), RotateOutcome::Rotated));
+        assert!(matches!(rotate(&pool, "a", "c", 1, exp).await.unwrap(), RotateOutcome::Reused));
+        assert!(is_revoked(&pool, "b").await);
+        assert!(matches!(rotate(&pool, "b", "d", 1, exp).await.unwrap(), RotateOutcome::Invalid));
+        assert!(matches!(rotate(&pool, "missing", "e", 1, exp).await.unwrap(), RotateOutcome::Invalid));
+    }
+
+    #[tokio::test]
+    async fn concurrent_refresh_rotates_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = test_pool(&dir).await;
+        let exp = now() + 3600;
+        insert(&pool, "a", 1, "fam", exp).await.unwrap();
+
+        let (first, second) = tokio::join!(rotate(&pool, "a", "b1", 1, exp), rotate(&pool, "a", "b2", 1, exp));
+        let outcomes = [first.unwrap(), second.unwrap()];
+        let rotated = outcomes.iter().filter(|o| matches!(o, RotateOutcome::Rotated)).count();
+        let reused = outcomes.iter().filter(|o| matches!(o, RotateOutcome::Reused)).count();
+        assert_eq!((rotated, reused), (1, 1));
+        // reuse 撤銷整個 family，勝出的那個新 token 也不能再用
+        let (live,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM refresh_tokens WHERE family_id = 'fam' AND revoked_at IS NULL")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(live, 0);
+    }
+}
@@ -0,0 +1,34 @@
+//! 執行期可由 API 修改的全域設定（key/value），例如 staff 設定的「所有人必須啟用 MFA」。
+
+use sqlx::SqlitePool;
+
+/// 是否要求所有使用者啟用 MFA（"true" / "false"）。
+pub const REQUIRE_MFA: &str = "require_mfa";
+
+pub async fn get(pool: &SqlitePool, key: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT value FROM app_settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn set(pool: &SqlitePool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO app_settings (key, value) VALUES (?, ?) \
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(key)
+    .bind(value)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// 布林設定；未設定時為 false。
+pub async fn get_flag(pool: &SqlitePool, key: &str) -> Result<bool, sqlx::Error> {
+    Ok(get(pool, key).await?.as_deref() == Some("true"))
+}
+
+pub async fn set_flag(pool: &SqlitePool, key: &str, value: bool) -> Result<(), sqlx::Error> {
+    set(pool, key, if value { "true" } else { "false" }).await
+}
@@ -1,5 +1,8 @@
 //! 使用者模型與查詢：Argon2 密碼雜湊/驗證、依 username/id 查詢、建立使用者。
 //! User 為對外型別（不含密碼）；UserRow 含 password_hash 供登入驗證。
+//! 另提供使用者管理用的列出、更新（email、role、is_active、密碼）與刪除，以及使用者自訂的容器預設值。
+//! `is_staff` 不是獨立的權限：由角色推導（admin 即 staff），資料表欄位隨 `set_role` 同步。
+//! 由外部身分來源（OIDC）自動建立的帳號以 (auth_provider, external_id) 對應，沒有本機密碼。
 
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
@@ -8,14 +11,46 @@ use argon2::{
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 
+use crate::permissions::{Permission, Role};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: i64,
     pub username: String,
     pub email: Option<String>,
+    /// `role == admin`；僅供顯示與相容，授權一律看 `can`。
     pub is_staff: bool,
+    /// 停用的帳號無法登入，既有 token 也會被拒絕。
+    pub is_active: bool,
+    /// 決定權限（見 `permissions::Role`）。
+    pub role: Role,
+    /// `local`（本機密碼）或外部身分來源（如 `oidc`）。
+    pub auth_provider: String,
 }
 
+impl User {
+    pub fn can(&self, permission: Permission) -> bool {
+        self.role.has(permission)
+    }
+}
+
+/// 建立容器時的個人預設值（PATCH /me）；請求未指定的欄位套用此設定。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContainerDefaults {
+    #[serde(default)]
+    pub privileged: Option<bool>,
+    #[serde(default)]
+    pub nvdocker: Option<bool>,
+    #[serde(default)]
+    pub gpu_count: Option<u32>,
+}
+
+const USER_COLUMNS: &str =
+    "id, username, password_hash, email, is_staff, is_active, role, auth_provider, tokens_valid_after";
+
+/// 外部身分來源建立的帳號沒有本機密碼；此值不是合法的 PHC 字串，密碼驗證永遠失敗。
+pub const UNUSABLE_PASSWORD: &str = "!";
+
 /// Hash a password with Argon2 (for storage).
 pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
     let salt = SaltString::generate(&mut OsRng);
@@ -36,10 +71,15 @@ pub fn verify_password(hash: &str, password: &str) -> bool {
         .is_ok()
 }
 
+/// 不存在的帳號也跑一次同成本的 Argon2 驗證（對固定的假雜湊），讓回應時間不洩漏帳號是否存在。
+pub fn verify_dummy_password(password: &str) {
+    static DUMMY_HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+    let hash = DUMMY_HASH.get_or_init(|| hash_password("dummy-password").unwrap_or_default());
+    let _ = verify_password(hash, password);
+}
+
 pub async fn get_by_username(pool: &SqlitePool, username: &str) -> Result<Option<UserRow>, sqlx::Error> {
-    let row = sqlx::query_as::<_, UserRow>(
-        "SELECT id, username, password_hash, email, is_staff FROM users WHERE username = ?",
-    )
+    let row = sqlx::query_as::<_, UserRow>(&format!("SELECT {} FROM users WHERE username = ?", USER_COLUMNS))
     .bind(username)
     .fetch_optional(pool)
     .await?;
@@ -47,11 +87,149 @@ pub async fn get_by_username(pool: &SqlitePool, username: &str) -> Result<Option
 }
 
 pub async fn get_by_id(pool: &SqlitePool, id: i64) -> Result<Option<User>, sqlx::Error> {
-    let row = sqlx::query_as::<_, UserRow>("SELECT id, username, password_hash, email, is_staff FROM users WHERE id = ?")
+    Ok(get_row_by_id(pool, id).await?.map(|r| r.into()))
+}
+
+/// 含 password_hash / tokens_valid_after 的完整列（token 驗證用）。
+pub async fn get_row_by_id(pool: &SqlitePool, id: i64) -> Result<Option<UserRow>, sqlx::Error> {
+    sqlx::query_as::<_, UserRow>(&format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// 讓此時間（UNIX 秒）之前簽發的 token 全部失效（登出所有裝置、改密碼）。
+pub async fn set_tokens_valid_after(pool: &SqlitePool, id: i64, ts: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET tokens_valid_after = ? WHERE id = ?")
+        .bind(ts)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 依外部身分（provider + 該來源的唯一 id，如 OIDC `sub`）查詢。
+pub async fn get_by_external_id(
+    pool: &SqlitePool,
+    provider: &str,
+    external_id: &str,
+) -> Result<Option<UserRow>, sqlx::Error> {
+    sqlx::query_as::<_, UserRow>(&format!(
+        "SELECT {} FROM users WHERE auth_provider = ? AND external_id = ?",
+        USER_COLUMNS
+    ))
+    .bind(provider)
+    .bind(external_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// 建立外部身分來源的使用者（無本機密碼）；回傳新使用者的 id。
+pub async fn create_external_user(
+    pool: &SqlitePool,
+    provider: &str,
+    external_id: &str,
+    username: &str,
+    email: Option<&str>,
+    role: Role,
+) -> Result<i64, sqlx::Error> {
+    let res = sqlx::query(
+        "INSERT INTO users (username, password_hash, email, is_staff, role, auth_provider, external_id) \
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(username)
+    .bind(UNUSABLE_PASSWORD)
+    .bind(email)
+    .bind((role == Role::Admin) as i64)
+    .bind(role.as_str())
+    .bind(provider)
+    .bind(external_id)
+    .execute(pool)
+    .await?;
+    Ok(res.last_insert_rowid())
+}
+
+/// 列出所有使用者（依 id 排序）。
+pub async fn list_users(pool: &SqlitePool) -> Result<Vec<User>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, UserRow>(&format!("SELECT {} FROM users ORDER BY id", USER_COLUMNS))
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(User::from).collect())
+}
+
+pub async fn set_email(pool: &SqlitePool, id: i64, email: Option<&str>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET email = ? WHERE id = ?")
+        .bind(email)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 更新角色並同步 is_staff 欄位。
+pub async fn set_role(pool: &SqlitePool, id: i64, role: Role) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET role = ?, is_staff = ? WHERE id = ?")
+        .bind(role.as_str())
+        .bind((role == Role::Admin) as i64)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_active(pool: &SqlitePool, id: i64, is_active: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET is_active = ? WHERE id = ?")
+        .bind(is_active as i64)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 更新密碼雜湊（呼叫端負責撤銷既有 session）。
+pub async fn set_password_hash(pool: &SqlitePool, id: i64, password_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+        .bind(password_hash)
         .bind(id)
-        .fetch_optional(pool)
+        .execute(pool)
         .await?;
-    Ok(row.map(|r| r.into()))
+    Ok(())
+}
+
+/// 讀取個人容器預設值；未設定或內容無法解析時回預設（全部未指定）。
+pub async fn get_container_defaults(pool: &SqlitePool, id: i64) -> Result<ContainerDefaults, sqlx::Error> {
+    let raw: Option<Option<String>> =
+        sqlx::query_scalar("SELECT container_defaults FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(raw
+        .flatten()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default())
+}
+
+pub async fn set_container_defaults(
+    pool: &SqlitePool,
+    id: i64,
+    defaults: &ContainerDefaults,
+) -> Result<(), sqlx::Error> {
+    let json = serde_json::to_string(defaults).unwrap_or_else(|_| "{}".into());
+    sqlx::query("UPDATE users SET container_defaults = ? WHERE id = ?")
+        .bind(json)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// 刪除使用者（refresh_tokens 隨 FK 一併刪除）；不存在時回 false。
+pub async fn delete_user(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected() > 0)
 }
 
 /// Internal row with password_hash; use get_by_id for public User.
@@ -62,15 +240,36 @@ pub struct UserRow {
     pub password_hash: String,
     pub email: Option<String>,
     pub is_staff: i64,
+    pub is_active: i64,
+    pub role: String,
+    pub auth_provider: String,
+    /// iat 早於此 UNIX 秒數的 token 視為已撤銷。
+    pub tokens_valid_after: i64,
+}
+
+impl UserRow {
+    /// token 的 iat 是否仍有效（未被 logout-all 等撤銷）。
+    pub fn token_still_valid(&self, iat: i64) -> bool {
+        iat >= self.tokens_valid_after
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active != 0
+    }
 }
 
 impl From<UserRow> for User {
     fn from(r: UserRow) -> Self {
+        // 無法辨識的角色以最低權限處理
+        let role = Role::parse(&r.role).unwrap_or(Role::Viewer);
         User {
             id: r.id,
             username: r.username,
             email: r.email,
-            is_staff: r.is_staff != 0,
+            is_staff: role == Role::Admin,
+            is_active: r.is_active != 0,
+            role,
+            auth_provider: r.auth_provider,
         }
     }
 }
@@ -84,6 +283,10 @@ impl<'r> sqlx::FromRow<'r, sqlx::sqlite::SqliteRow> for UserRow {
             password_hash: row.try_get("password_hash")?,
             email: row.try_get("email")?,
             is_staff: row.try_get("is_staff")?,
+            is_active: row.try_get("is_active")?,
+            role: row.try_get("role")?,
+            auth_provider: row.try_get("auth_provider")?,
+            tokens_valid_after: row.try_get("tokens_valid_after")?,
         })
     }
 }
@@ -94,15 +297,16 @@ pub async fn create_user(
     username: &str,
     password_hash: &str,
     email: Option<&str>,
-    is_staff: bool,
+    role: Role,
 ) -> Result<i64, sqlx::Error> {
     let res = sqlx::query(
-        "INSERT INTO users (username, password_hash, email, is_staff) VALUES (?, ?, ?, ?)",
+        "INSERT INTO users (username, password_hash, email, is_staff, role) VALUES (?, ?, ?, ?, ?)",
     )
     .bind(username)
     .bind(password_hash)
     .bind(email)
-    .bind(if is_staff { 1i64 } else { 0 })
+    .bind(if role == Role::Admin { 1i64 } else { 0 })
+    .bind(role.as_str())
     .execute(pool)
     .await?;
     Ok(res.last_insert_rowid())
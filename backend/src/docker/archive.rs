@@ -0,0 +1,161 @@
+//! 匯入用的 `docker save` tarball 檢查與改寫：`docker load` 會重建 tarball 宣告的任何 tag（可覆蓋 `gui-vnc`
+//! 等共用映像），因此載入前移除所有 tag，並只接受單一映像；載入後由 `import_image_ref` 重新 tag。
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+/// OCI index.json 中會被 `docker load` 當成映像名稱的 annotation。
+const NAME_ANNOTATIONS: [&str; 2] = ["io.containerd.image.name", "org.opencontainers.image.ref.name"];
+
+fn io_err(e: std::io::Error) -> String {
+    format!("invalid tarball: {}", e)
+}
+
+/// manifest.json（舊格式）：只允許一個映像，RepoTags 清空。
+fn strip_manifest(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut manifest: Vec<serde_json::Value> =
+        serde_json::from_slice(data).map_err(|_| "invalid manifest.json".to_string())?;
+    if manifest.len() != 1 {
+        return Err("Tarball must contain exactly one image".into());
+    }
+    if let Some(entry) = manifest[0].as_object_mut() {
+        entry.insert("RepoTags".into(), serde_json::json!([]));
+    }
+    Ok(serde_json::to_vec(&manifest).unwrap_or_default())
+}
+
+/// index.json（OCI）：同一映像有多個 tag 時會重複出現，依 digest 去重後只允許一個，並移除名稱 annotation。
+fn strip_index(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut index: serde_json::Value = serde_json::from_slice(data).map_err(|_| "invalid index.json".to_string())?;
+    let manifests = index
+        .get_mut("manifests")
+        .and_then(|m| m.as_array_mut())
+        .ok_or("invalid index.json")?;
+    let mut seen = HashSet::new();
+    manifests.retain(|m| seen.insert(m.get("digest").and_then(|d| d.as_str()).unwrap_or_default().to_string()));
+    if manifests.len() != 1 {
+        return Err("Tarball must contain exactly one image".into());
+    }
+    if let Some(annotations) = manifests[0].get_mut("annotations").and_then(|a| a.as_object_mut()) {
+        for key in NAME_ANNOTATIONS {
+            annotations.remove(key);
+        }
+    }
+    Ok(serde_json::to_vec(&index).unwrap_or_default())
+}
+
+/// 將 `src` 的 tarball 改寫到 `dst`：移除 tag（manifest.json、index.json 的名稱 annotation、舊格式的
+/// `repositories`），其餘 entry 原樣複製。沒有 manifest.json 或包含多個映像時回傳錯誤。
+pub fn strip_image_tags<R: Read, W: Write>(src: R, dst: W) -> Result<(), String> {
+    let mut archive = tar::Archive::new(src);
+    let mut builder = tar::Builder::new(dst);
+    let mut saw_manifest = false;
+    for entry in archive.entries().map_err(io_err)? {
+        let mut entry = entry.map_err(io_err)?;
+        let path = entry.path().map_err(io_err)?.into_owned();
+        let name = path.to_string_lossy().trim_start_matches("./").to_string();
+        let mut header = entry.header().clone();
+        let rewritten = match name.as_str() {
+            "repositories" => continue,
+            "manifest.json" => {
+                saw_manifest = true;
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).map_err(io_err)?;
+                Some(strip_manifest(&data)?)
+            }
+            "index.json" => {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).map_err(io_err)?;
+                Some(strip_index(&data)?)
+            }
+            _ => None,
+        };
+        match rewritten {
+            Some(data) => {
+                header.set_size(data.len() as u64);
+                builder.append_data(&mut header, &path, data.as_slice())
+            }
+            None if header.entry_type().is_symlink() || header.entry_type().is_hard_link() => {
+                let target = entry.link_name().map_err(io_err)?.unwrap_or_default().into_owned();
+                builder.append_link(&mut header, &path, target)
+            }
+            None => builder.append_data(&mut header, &path, &mut entry),
+        }
+        .map_err(io_err)?;
+    }
+    if !saw_manifest {
+        return Err("Tarball is not a docker save archive (missing manifest.json)".into());
+    }
+    builder.into_inner().and_then(|mut w| w.flush()).map_err(io_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tarball(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn read_entry(tar_bytes: &[u8], name: &str) -> Option<Vec<u8>> {
+        let mut archive = tar::Archive::new(tar_bytes);
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.path().unwrap().to_string_lossy() == name {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                return Some(data);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn strips_tags_and_keeps_layers() {
+        let manifest = br#"[{"Config":"c.json","RepoTags":["gui-vnc:latest"],"Layers":["l/layer.tar"]}]"#;
+        let index = br#"{"schemaVersion":2,"manifests":[
+            {"digest":"sha256:aa","annotations":{"io.containerd.image.name":"docker.io/library/gui-vnc:latest","org.opencontainers.image.ref.name":"latest","keep":"x"}},
+            {"digest":"sha256:aa","annotations":{"io.containerd.image.name":"docker.io/library/gui-vnc:v2"}}]}"#;
+        let src = tarball(&[
+            ("l/layer.tar", b"layer-bytes"),
+            ("repositories", br#"{"gui-vnc":{"latest":"aa"}}"#),
+            ("index.json", index),
+            ("manifest.json", manifest),
+        ]);
+        let mut out = Vec::new();
+        strip_image_tags(src.as_slice(), &mut out).unwrap();
+
+        assert_eq!(read_entry(&out, "l/layer.tar").unwrap(), b"layer-bytes");
+        assert!(read_entry(&out, "repositories").is_none());
+        let manifest: serde_json::Value = serde_json::from_slice(&read_entry(&out, "manifest.json").unwrap()).unwrap();
+        assert_eq!(manifest[0]["RepoTags"], serde_json::json!([]));
+        assert_eq!(manifest[0]["Layers"][0], "l/layer.tar");
+        let index: serde_json::Value = serde_json::from_slice(&read_entry(&out, "index.json").unwrap()).unwrap();
+        assert_eq!(index["manifests"].as_array().unwrap().len(), 1);
+        assert_eq!(index["manifests"][0]["annotations"], serde_json::json!({ "keep": "x" }));
+    }
+
+    #[test]
+    fn rejects_multiple_images() {
+        let manifest = br#"[{"Config":"a.json","RepoTags":[]},{"Config":"b.json","RepoTags":[]}]"#;
+        let src = tarball(&[("manifest.json", manifest)]);
+        assert!(strip_image_tags(src.as_slice(), Vec::new()).is_err());
+
+        let index = br#"{"manifests":[{"digest":"sha256:aa"},{"digest":"sha256:bb"}]}"#;
+        let src = tarball(&[("index.json", index), ("manifest.json", br#"[{"Config":"a.json"}]"#)]);
+        assert!(strip_image_tags(src.as_slice(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_archive_without_manifest() {
+        let src = tarball(&[("repositories", b"{}"), ("x/layer.tar", b"x")]);
+        assert!(strip_image_tags(src.as_slice(), Vec::new()).is_err());
+    }
+}
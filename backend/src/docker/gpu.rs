@@ -0,0 +1,332 @@
+//! GPU 清單與配置：取代 `count: -1`（每個 GPU 容器拿到所有 GPU），改為指定裝置。
+//! 清單來源為設定（GPU_DEVICES）或在 NVIDIA 探測容器內執行 nvidia-smi；
+//! 佔用狀態直接由管理中容器的 device_requests 推得，由佇列 worker 依序配置以避免超賣。
+
+use async_trait::async_trait;
+use bollard::query_parameters::{InspectContainerOptions, ListContainersOptionsBuilder};
+use bollard::Docker;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::nvidia;
+use super::LABEL_OWNER;
+
+/// 主機上的一張 GPU。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuDevice {
+    /// nvidia-smi 的 index；GPU_DEVICES 只給 UUID 時為 None（清單中的位置不一定是實際 index）。
+    pub index: Option<u32>,
+    /// nvidia-smi 的 GPU UUID；設定清單若只給 index 則為 None。
+    pub uuid: Option<String>,
+    pub name: Option<String>,
+    pub memory_total_mib: Option<u64>,
+}
+
+impl GpuDevice {
+    /// 傳給 Docker device_ids 的識別字：有 UUID 用 UUID，否則用 index。
+    pub fn device_id(&self) -> String {
+        self.uuid
+            .clone()
+            .or_else(|| self.index.map(|i| i.to_string()))
+            .unwrap_or_default()
+    }
+
+    /// Docker device_ids 可為 index 或 UUID，已知的兩者皆視為同一張卡。
+    pub fn matches(&self, id: &str) -> bool {
+        self.uuid.as_deref() == Some(id) || self.index.is_some_and(|i| i.to_string() == id)
+    }
+}
+
+/// 建立容器時要求的 GPU：指定 device_ids，或只給數量（預設 1 張）。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GpuRequest {
+    pub count: Option<u32>,
+    pub device_ids: Option<Vec<String>>,
+}
+
+/// GPU 清單來源；API 與 worker 共用，測試可替換成固定清單。
+#[async_trait]
+pub trait GpuInventory: Send + Sync {
+    async fn devices(&self) -> Result<Vec<GpuDevice>, String>;
+}
+
+/// 固定清單（GPU_DEVICES 設定或測試用 mock）。
+pub struct StaticInventory(pub Vec<GpuDevice>);
+
+#[async_trait]
+impl GpuInventory for StaticInventory {
+    async fn devices(&self) -> Result<Vec<GpuDevice>, String> {
+        Ok(self.0.clone())
+    }
+}
+
+/// 在 NVIDIA 探測容器內執行 nvidia-smi 取得清單；成功後快取（GPU 不會在執行期間增減）。
+pub struct NvidiaSmiInventory {
+    docker: Docker,
+    image: String,
+    cache: tokio::sync::OnceCell<Vec<GpuDevice>>,
+}
+
+impl NvidiaSmiInventory {
+    pub fn new(docker: Docker, image: &str) -> Self {
+        Self {
+            docker,
+            image: image.to_string(),
+            cache: tokio::sync::OnceCell::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl GpuInventory for NvidiaSmiInventory {
+    async fn devices(&self) -> Result<Vec<GpuDevice>, String> {
+        self.cache
+            .get_or_try_init(|| async {
+                let cmd = vec![
+                    "nvidia-smi".to_string(),
+                    "--query-gpu=index,uuid,name,memory.total".to_string(),
+                    "--format=csv,noheader,nounits".to_string(),
+                ];
+                let out = nvidia::run_probe(&self.docker, &self.image, cmd).await?;
+                Ok(parse_nvidia_smi_csv(&out))
+            })
+            .await
+            .cloned()
+    }
+}
+
+/// 解析 `nvidia-smi --query-gpu=index,uuid,name,memory.total --format=csv,noheader,nounits`。
+pub fn parse_nvidia_smi_csv(out: &str) -> Vec<GpuDevice> {
+    out.lines()
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split(',').map(str::trim).collect();
+            let index = cols.first()?.parse().ok()?;
+            Some(GpuDevice {
+                index: Some(index),
+                uuid: cols.get(1).filter(|s| !s.is_empty()).map(|s| s.to_string()),
+                name: cols.get(2).filter(|s| !s.is_empty()).map(|s| s.to_string()),
+                memory_total_mib: cols.get(3).and_then(|s| s.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+/// 解析 GPU_DEVICES（逗號分隔的 index 或 UUID，例如 `0,1` 或 `GPU-aaa,GPU-bbb`）；UUID 項目只以 UUID 比對。
+pub fn parse_device_list(list: &str) -> Vec<GpuDevice> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|id| match id.parse::<u32>() {
+            Ok(index) => GpuDevice {
+                index: Some(index),
+                uuid: None,
+                name: None,
+                memory_total_mib: None,
+            },
+            Err(_) => GpuDevice {
+                index: None,
+                uuid: Some(id.to_string()),
+                name: None,
+                memory_total_mib: None,
+            },
+        })
+        .collect()
+}
+
+/// 依設定選擇清單來源：有 GPU_DEVICES 用固定清單，否則以 nvidia-smi 探測。
+pub fn inventory_from_config(config: &crate::config::Config, docker: &Docker) -> Arc<dyn GpuInventory> {
+    match config.gpu_devices.as_deref() {
+        Some(list) => Arc::new(StaticInventory(parse_device_list(list))),
+        None => Arc::new(NvidiaSmiInventory::new(docker.clone(), &config.nvidia_probe_image)),
+    }
+}
+
+/// 管理中容器目前持有的 GPU：device id -> 容器名稱（含已停止的容器，啟動時仍會拿同一張卡）。
+/// 舊版以 `count: -1` 建立的容器為共用模式，不列入。
+pub async fn held_devices(docker: &Docker) -> Result<HashMap<String, String>, bollard::errors::Error> {
+    let mut filters = HashMap::new();
+    filters.insert("label".to_string(), vec![LABEL_OWNER.to_string()]);
+    let opts = ListContainersOptionsBuilder::default()
+        .all(true)
+        .filters(&filters)
+        .build();
+    let summaries = docker.list_containers(Some(opts)).await?;
+    let mut held = HashMap::new();
+    for c in summaries {
+        let id = match c.id.as_deref() {
+            Some(id) => id,
+            None => continue,
+        };
+        let inspect = match docker.inspect_container(id, None::<InspectContainerOptions>).await {
+            Ok(i) => i,
+            Err(_) => continue,
+        };
+        let name = inspect
+            .name
+            .as_deref()
+            .unwrap_or(id)
+            .trim_start_matches('/')
+            .to_string();
+        let requests = inspect
+            .host_config
+            .as_ref()
+            .and_then(|h| h.device_requests.as_ref());
+        for r in requests.into_iter().flatten() {
+            if r.driver.as_deref() != Some("nvidia") {
+                continue;
+            }
+            for device_id in r.device_ids.iter().flatten() {
+                held.insert(device_id.clone(), name.clone());
+            }
+        }
+    }
+    Ok(held)
+}
+
+/// 找出持有該卡的容器（held 的 key 可能是 index 或 UUID）。
+pub fn holder<'a>(device: &GpuDevice, held: &'a HashMap<String, String>) -> Option<&'a String> {
+    held.iter().find(|(id, _)| device.matches(id)).map(|(_, name)| name)
+}
+
+/// 依請求從清單中選出未被佔用的 GPU，回傳要傳給 Docker 的 device_ids。
+pub fn select_devices(
+    inventory: &[GpuDevice],
+    held: &HashMap<String, String>,
+    req: &GpuRequest,
+) -> Result<Vec<String>, String> {
+    if let Some(ids) = req.device_ids.as_ref().filter(|ids| !ids.is_empty()) {
+        let mut out = Vec::with_capacity(ids.len());
+        for id in ids {
+            let device = inventory
+                .iter()
+                .find(|d| d.matches(id))
+                .ok_or_else(|| format!("GPU [{}] does not exist", id))?;
+            if let Some(name) = holder(device, held) {
+                return Err(format!("GPU [{}] is already assigned to container [{}]", id, name));
+            }
+            let device_id = device.device_id();
+            if !out.contains(&device_id) {
+                out.push(device_id);
+            }
+        }
+        return Ok(out);
+    }
+    let count = req.count.unwrap_or(1).max(1) as usize;
+    let free: Vec<String> = inventory
+        .iter()
+        .filter(|d| holder(d, held).is_none())
+        .map(GpuDevice::device_id)
+        .collect();
+    if free.len() < count {
+        return Err(format!(
+            "Not enough free GPUs (requested {}, free {} of {})",
+            count,
+            free.len(),
+            inventory.len()
+        ));
+    }
+    Ok(free.into_iter().take(count).collect())
+}
+
+/// GET /api/gpus 的單筆狀態。
+#[derive(Serialize)]
+pub struct GpuStatus {
+    #[serde(flatten)]
+    pub device: GpuDevice,
+    pub device_id: String,
+    /// 持有此卡的容器名稱；None 表示空閒。
+    pub container: Option<String>,
+}
+
+/// 列出清單與各卡的持有者。
+pub async fn gpu_status(
+    docker: &Docker,
+    inventory: &dyn GpuInventory,
+) -> Result<Vec<GpuStatus>, String> {
+    let devices = inventory.devices().await?;
+    let held = held_devices(docker).await.map_err(|e| e.to_string())?;
+    Ok(devices
+        .into_iter()
+        .map(|d| GpuStatus {
+            device_id: d.device_id(),
+            container: holder(&d, &held).cloned(),
+            device: d,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries.iter().map(|(id, name)| (id.to_string(), name.to_string())).collect()
+    }
+
+    fn request(count: Option<u32>, ids: Option<&[&str]>) -> GpuRequest {
+        GpuRequest {
+            count,
+            device_ids: ids.map(|ids| ids.iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn parses_device_list() {
+        let devices = parse_device_list(" 2, GPU-aaa ,,0");
+        let keys: Vec<(Option<u32>, Option<&str>)> =
+            devices.iter().map(|d| (d.index, d.uuid.as_deref())).collect();
+        assert_eq!(keys, [(Some(2), None), (None, Some("GPU-aaa")), (Some(0), None)]);
+        assert_eq!(devices[1].device_id(), "GPU-aaa");
+        // UUID 項目不會被清單位置的 index 誤認
+        assert!(!devices[1].matches("1"));
+        assert!(parse_device_list("").is_empty());
+    }
+
+    #[test]
+    fn parses_nvidia_smi_csv() {
+        let devices = parse_nvidia_smi_csv("0, GPU-aaa, NVIDIA A100, 40960\n1, GPU-bbb, NVIDIA A100, 40960\n\nbad line\n");
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[1].index, Some(1));
+        assert_eq!(devices[1].memory_total_mib, Some(40960));
+        assert!(devices[1].matches("1") && devices[1].matches("GPU-bbb"));
+    }
+
+    #[test]
+    fn count_skips_held_devices() {
+        let inventory = parse_nvidia_smi_csv("0, GPU-aaa, A, 1\n1, GPU-bbb, A, 1\n2, GPU-ccc, A, 1\n");
+        // held 可能以 index 或 UUID 記錄
+        let held = held(&[("0", "alpha"), ("GPU-ccc", "gamma")]);
+        assert_eq!(select_devices(&inventory, &held, &request(None, None)).unwrap(), ["GPU-bbb"]);
+        let err = select_devices(&inventory, &held, &request(Some(2), None)).unwrap_err();
+        assert!(err.contains("requested 2, free 1 of 3"), "{}", err);
+        assert_eq!(
+            select_devices(&inventory, &HashMap::new(), &request(Some(2), None)).unwrap(),
+            ["GPU-aaa", "GPU-bbb"]
+        );
+    }
+
+    #[test]
+    fn device_ids_take_precedence_over_count() {
+        let inventory = parse_nvidia_smi_csv("0, GPU-aaa, A, 1\n1, GPU-bbb, A, 1\n");
+        let none = HashMap::new();
+        // index 與 UUID 指同一張卡時只配置一次
+        let ids = select_devices(&inventory, &none, &request(Some(2), Some(&["1", "GPU-bbb"]))).unwrap();
+        assert_eq!(ids, ["GPU-bbb"]);
+        // 空的 device_ids 視為未指定
+        assert_eq!(select_devices(&inventory, &none, &request(None, Some(&[]))).unwrap(), ["GPU-aaa"]);
+
+        let err = select_devices(&inventory, &none, &request(None, Some(&["7"]))).unwrap_err();
+        assert!(err.contains("does not exist"), "{}", err);
+        let err = select_devices(&inventory, &held(&[("GPU-aaa", "alpha")]), &request(None, Some(&["0"]))).unwrap_err();
+        assert!(err.contains("already assigned to container [alpha]"), "{}", err);
+    }
+
+    #[test]
+    fn uuid_entries_match_only_by_uuid() {
+        let inventory = parse_device_list("GPU-aaa,GPU-bbb");
+        let held = held(&[("0", "legacy")]);
+        assert_eq!(select_devices(&inventory, &held, &request(Some(2), None)).unwrap(), ["GPU-aaa", "GPU-bbb"]);
+        assert!(select_devices(&inventory, &HashMap::new(), &request(None, Some(&["0"]))).is_err());
+    }
+}
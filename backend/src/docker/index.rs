@@ -0,0 +1,441 @@
+//! 容器狀態索引：背景訂閱 Docker events，於記憶體維護管理中容器（狀態、埠、擁有者、label）
+//! 與所有容器綁定的 host port，讓列表與埠查詢不必每次打 Docker API。
+//! 狀態改變（包含在管理器外 `docker stop` 等操作）會經 notify_tx 推送 STATE_CHANGED 通知。
+
+use bollard::models::{ContainerInspectResponse, EventMessageTypeEnum};
+use bollard::query_parameters::{EventsOptionsBuilder, ListContainersOptionsBuilder};
+use bollard::Docker;
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use super::ports::host_ports;
+use super::{container_info, ContainerInfo};
+
+/// events 串流中斷後重新同步前的等待時間。
+const RESYNC_DELAY: Duration = Duration::from_secs(2);
+/// 啟動時 inspect 所有容器的並行上限。
+const SYNC_CONCURRENCY: usize = 8;
+
+#[derive(Default)]
+struct IndexInner {
+    /// 是否已完成第一次全量同步；未完成前查詢回 None，呼叫端改直接查 Docker。
+    ready: bool,
+    /// 管理中容器（gui-vnc 映像或帶 LABEL_OWNER）。
+    managed: HashMap<String, ContainerInfo>,
+    /// 所有容器（含非管理中的）綁定的 host port。
+    host_ports: HashMap<String, Vec<u16>>,
+}
+
+/// 由 events 維護的容器索引；存放於 AppState。
+#[derive(Default)]
+pub struct ContainerIndex {
+    inner: RwLock<IndexInner>,
+}
+
+impl ContainerIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 管理中容器清單（依名稱排序）；尚未同步時回 None。
+    pub async fn list(&self) -> Option<Vec<ContainerInfo>> {
+        let inner = self.inner.read().await;
+        if !inner.ready {
+            return None;
+        }
+        let mut out: Vec<ContainerInfo> = inner.managed.values().cloned().collect();
+        out.sort_by(|a, b| a.name.cmp(&b.name));
+        Some(out)
+    }
+
+    /// 單一管理中容器（完整 id 或至少 12 字元的前綴）；不在索引中回 None。
+    pub async fn get(&self, id: &str) -> Option<ContainerInfo> {
+        let inner = self.inner.read().await;
+        inner
+            .managed
+            .values()
+            .find(|c| c.id == id || (id.len() >= 12 && c.id.starts_with(id)))
+            .cloned()
+    }
+
+    /// 是否有任何容器綁定該 host port；尚未同步時回 None。
+    pub async fn port_in_use(&self, port: u16) -> Option<bool> {
+        let inner = self.inner.read().await;
+        if !inner.ready {
+            return None;
+        }
+        Some(inner.host_ports.values().any(|ports| ports.contains(&port)))
+    }
+
+    /// 所有容器綁定的 host port 集合；尚未同步時回 None。
+    pub async fn used_ports(&self) -> Option<HashSet<u16>> {
+        let inner = self.inner.read().await;
+        if !inner.ready {
+            return None;
+        }
+        Some(inner.host_ports.values().flatten().copied().collect())
+    }
+
+    async fn upsert(&self, id: &str, summary_image: &str, inspect: &ContainerInspectResponse) {
+        let ports = inspect
+            .host_config
+            .as_ref()
+            .and_then(|h| h.port_bindings.as_ref())
+            .map(host_ports)
+            .unwrap_or_default();
+        let info = container_info(id, summary_image, inspect, (0, 0));
+        let mut inner = self.inner.write().await;
+        inner.host_ports.insert(id.to_string(), ports);
+        match info {
+            Some(info) => {
+                inner.managed.insert(id.to_string(), info);
+            }
+            None => {
+                inner.managed.remove(id);
+            }
+        }
+    }
+
+    async fn remove(&self, id: &str) {
+        let mut inner = self.inner.write().await;
+        inner.managed.remove(id);
+        inner.host_ports.remove(id);
+    }
+
+    /// 全量同步：list 所有容器並以有限並行數 inspect。
+    async fn sync(&self, docker: &Docker) -> Result<(), bollard::errors::Error> {
+        let opts = ListContainersOptionsBuilder::default().all(true).build();
+        let summaries = docker.list_containers(Some(opts)).await?;
+        let inspected: Vec<(String, String, ContainerInspectResponse)> =
+            futures_util::stream::iter(summaries)
+                .map(|c| async move {
+                    let id = c.id?;
+                    let inspect = docker.inspect_container(&id, None).await.ok()?;
+                    Some((id, c.image.unwrap_or_default(), inspect))
+                })
+                .buffer_unordered(SYNC_CONCURRENCY)
+                .filter_map(|x| async move { x })
+                .collect()
+                .await;
+        let mut managed = HashMap::new();
+        let mut ports = HashMap::new();
+        for (id, image, inspect) in &inspected {
+            let bound = inspect
+                .host_config
+                .as_ref()
+                .and_then(|h| h.port_bindings.as_ref())
+                .map(host_ports)
+                .unwrap_or_default();
+            ports.insert(id.clone(), bound);
+            if let Some(info) = container_info(id, image, inspect, (0, 0)) {
+                managed.insert(id.clone(), info);
+            }
+        }
+        let mut inner = self.inner.write().await;
+        inner.managed = managed;
+        inner.host_ports = ports;
+        inner.ready = true;
+        Ok(())
+    }
+
+    /// 常駐迴圈：全量同步後訂閱 container events（since = 同步開始時間，避免漏掉同步期間的事件），
+    /// 串流中斷則等待後重新同步。
+    pub async fn run(
+        self: std::sync::Arc<Self>,
+        docker: Docker,
+        notify_tx: tokio::sync::broadcast::Sender<String>,
+    ) {
+        loop {
+            let since = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string();
+            if let Err(e) = self.sync(&docker).await {
+                tracing::warn!("Container index: sync failed: {}", e);
+                tokio::time::sleep(RESYNC_DELAY).await;
+                continue;
+            }
+            let mut filters = HashMap::new();
+            filters.insert("type".to_string(), vec!["container".to_string()]);
+            let opts = EventsOptionsBuilder::default()
+                .since(&since)
+                .filters(&filters)
+                .build();
+            let mut events = docker.events(Some(opts));
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(e) => e,
+                    Err(e) => {
+                        tracing::warn!("Container index: events stream error: {}", e);
+                        break;
+                    }
+                };
+                if event.typ != Some(EventMessageTypeEnum::CONTAINER) {
+                    continue;
+                }
+                let id = match event.actor.as_ref().and_then(|a| a.id.clone()) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let action = event.action.unwrap_or_default();
+                self.handle_event(&docker, &notify_tx, &id, &action).await;
+            }
+            self.inner.write().await.ready = false;
+            tokio::time::sleep(RESYNC_DELAY).await;
+        }
+    }
+
+    async fn handle_event(
+        &self,
+        docker: &Docker,
+        notify_tx: &tokio::sync::broadcast::Sender<String>,
+        id: &str,
+        action: &str,
+    ) {
+        // exec_start: ... / health_status: ... 等帶參數的事件只取動作名稱
+        let action = action.split(':').next().unwrap_or(action).trim();
+        let before = self.inner.read().await.managed.get(id).cloned();
+        match action {
+            "destroy" => {
+                self.remove(id).await;
+                if let Some(c) = before {
+                    notify_state_change(notify_tx, &c, "removed", action);
+                }
+            }
+            "create" | "start" | "restart" | "die" | "stop" | "kill" | "pause" | "unpause"
+            | "rename" | "update" | "oom" => {
+                let inspect = match docker.inspect_container(id, None).await {
+                    Ok(i) => i,
+                    Err(_) => return,
+                };
+                let image = before.as_ref().map(|c| c.image_tag.clone()).unwrap_or_default();
+                self.upsert(id, &image, &inspect).await;
+                let after = self.inner.read().await.managed.get(id).cloned();
+                if let Some(c) = after {
+                    let changed = before.as_ref().map(|b| b.status != c.status).unwrap_or(true);
+                    if changed {
+                        notify_state_change(notify_tx, &c, &c.status, action);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 推送 STATE_CHANGED 通知（格式同佇列 worker 的通知，另帶 data 供前端更新單筆狀態）。
+fn notify_state_change(
+    notify_tx: &tokio::sync::broadcast::Sender<String>,
+    container: &ContainerInfo,
+    status: &str,
+    event: &str,
+) {
+    let msg = serde_json::json!({
+        "message": {
+            "action": "STATE_CHANGED",
+            "details": format!("Container [{}] is now {}", container.name, status),
+            "data": {
+                "container_id": container.id,
+                "status": status,
+                "event": event,
+            }
+        }
+    });
+    let _ = notify_tx.send(msg.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::Uri, response::IntoResponse, Json, Router};
+    use bollard::models::{
+        ContainerConfig, ContainerState, ContainerStateStatusEnum, ContainerSummary, HostConfig,
+        PortBinding,
+    };
+    use crate::docker::port_used_by_container;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::broadcast;
+
+    /// 模擬的 Docker：id -> inspect 結果，測試中直接改寫以模擬容器狀態變化。
+    type MockContainers = Arc<Mutex<HashMap<String, ContainerInspectResponse>>>;
+
+    /// 模擬 Docker Engine API 中 list/inspect 用到的端點（可帶 `/v1.xx` 前綴）。
+    async fn mock_api(State(containers): State<MockContainers>, uri: Uri) -> axum::response::Response {
+        let path = uri.path().trim_start_matches('/');
+        let path = match path.split_once('/') {
+            Some((version, rest)) if version.starts_with('v') => rest,
+            _ => path,
+        };
+        let containers = containers.lock().unwrap();
+        if path == "containers/json" {
+            let list: Vec<ContainerSummary> = containers
+                .values()
+                .map(|c| ContainerSummary {
+                    id: c.id.clone(),
+                    image: c.config.as_ref().and_then(|cfg| cfg.image.clone()),
+                    ..Default::default()
+                })
+                .collect();
+            return Json(list).into_response();
+        }
+        let id = path.strip_prefix("containers/").and_then(|p| p.strip_suffix("/json"));
+        match id.and_then(|id| containers.get(id)) {
+            Some(c) => Json(c.clone()).into_response(),
+            None => axum::http::StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn mock_docker() -> (Docker, MockContainers) {
+        let containers = MockContainers::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().fallback(mock_api).with_state(containers.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let docker = Docker::connect_with_http(&format!("http://{}", addr), 10, bollard::API_DEFAULT_VERSION).unwrap();
+        (docker, containers)
+    }
+
+    fn container(
+        id: &str,
+        name: &str,
+        image: &str,
+        status: ContainerStateStatusEnum,
+        host_port: u16,
+    ) -> ContainerInspectResponse {
+        let bindings = HashMap::from([(
+            "22/tcp".to_string(),
+            Some(vec![PortBinding {
+                host_ip: Some("0.0.0.0".into()),
+                host_port: Some(host_port.to_string()),
+            }]),
+        )]);
+        ContainerInspectResponse {
+            id: Some(id.into()),
+            name: Some(format!("/{}", name)),
+            config: Some(ContainerConfig {
+                image: Some(image.into()),
+                ..Default::default()
+            }),
+            state: Some(ContainerState {
+                status: Some(status),
+                ..Default::default()
+            }),
+            host_config: Some(HostConfig {
+                port_bindings: Some(bindings),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn set_status(containers: &MockContainers, id: &str, status: ContainerStateStatusEnum) {
+        let mut containers = containers.lock().unwrap();
+        containers.get_mut(id).unwrap().state.as_mut().unwrap().status = Some(status);
+    }
+
+    /// 取出目前收到的 STATE_CHANGED 通知，回傳 (container_id, status, event)。
+    fn drain(rx: &mut broadcast::Receiver<String>) -> Vec<(String, String, String)> {
+        let mut out = Vec::new();
+        while let Ok(msg) = rx.try_recv() {
+            let v: serde_json::Value = serde_json::from_str(&msg).unwrap();
+            let data = &v["message"]["data"];
+            assert_eq!(v["message"]["action"], "STATE_CHANGED");
+            out.push((
+                data["container_id"].as_str().unwrap().to_string(),
+                data["status"].as_str().unwrap().to_string(),
+                data["event"].as_str().unwrap().to_string(),
+            ));
+        }
+        out
+    }
+
+    fn notice(id: &str, status: &str, event: &str) -> (String, String, String) {
+        (id.to_string(), status.to_string(), event.to_string())
+    }
+
+    #[tokio::test]
+    async fn events_update_index() {
+        use ContainerStateStatusEnum::{EXITED, RUNNING};
+        let (docker, containers) = mock_docker().await;
+        let (tx, mut rx) = broadcast::channel(16);
+        let id = "a".repeat(64);
+        containers
+            .lock()
+            .unwrap()
+            .insert("web".into(), container("web", "nginx", "nginx:latest", RUNNING, 8080));
+        let index = ContainerIndex::new();
+        index.sync(&docker).await.unwrap();
+        assert_eq!(index.list().await.unwrap().len(), 0);
+        assert_eq!(index.port_in_use(8080).await, Some(true));
+
+        // create：進入索引（created 狀態），首次出現即通知
+        containers
+            .lock()
+            .unwrap()
+            .insert(id.clone(), container(&id, "desk", "gui-vnc:latest", ContainerStateStatusEnum::CREATED, 2222));
+        index.handle_event(&docker, &tx, &id, "create").await;
+        assert_eq!(index.get(&id[..12]).await.unwrap().status, "created");
+        assert_eq!(index.port_in_use(2222).await, Some(true));
+        assert_eq!(drain(&mut rx), [notice(&id, "created", "create")]);
+
+        set_status(&containers, &id, RUNNING);
+        index.handle_event(&docker, &tx, &id, "start").await;
+        assert_eq!(index.get(&id).await.unwrap().status, "running");
+        assert_eq!(drain(&mut rx), [notice(&id, "running", "start")]);
+
+        // 管理器外的 docker stop：die 先改變狀態，隨後的 stop 狀態不變，不重複通知
+        set_status(&containers, &id, EXITED);
+        index.handle_event(&docker, &tx, &id, "die").await;
+        index.handle_event(&docker, &tx, &id, "stop").await;
+        assert_eq!(index.get(&id).await.unwrap().status, "exited");
+        assert_eq!(drain(&mut rx), [notice(&id, "exited", "die")]);
+
+        // rename：更新名稱，但狀態未變不通知
+        containers.lock().unwrap().get_mut(&id).unwrap().name = Some("/renamed".into());
+        index.handle_event(&docker, &tx, &id, "rename").await;
+        let list = index.list().await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "renamed");
+        assert!(drain(&mut rx).is_empty());
+
+        // 帶參數的事件只取動作名稱；未處理的動作不影響索引
+        index.handle_event(&docker, &tx, &id, "exec_start: bash").await;
+        assert!(drain(&mut rx).is_empty());
+
+        containers.lock().unwrap().remove(&id);
+        index.handle_event(&docker, &tx, &id, "destroy").await;
+        assert!(index.get(&id).await.is_none());
+        assert_eq!(index.port_in_use(2222).await, Some(false));
+        assert_eq!(drain(&mut rx), [notice(&id, "removed", "destroy")]);
+
+        // 非管理中容器：只記錄埠，不進清單也不通知
+        containers.lock().unwrap().remove("web");
+        index.handle_event(&docker, &tx, "web", "destroy").await;
+        assert_eq!(index.port_in_use(8080).await, Some(false));
+        assert!(drain(&mut rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn port_lookup_falls_back_to_docker_before_sync() {
+        let (docker, containers) = mock_docker().await;
+        containers.lock().unwrap().insert(
+            "web".into(),
+            container("web", "web", "nginx:latest", ContainerStateStatusEnum::RUNNING, 8080),
+        );
+        let index = ContainerIndex::new();
+        assert_eq!(index.port_in_use(8080).await, None);
+        assert!(index.list().await.is_none());
+        assert!(index.used_ports().await.is_none());
+        assert!(port_used_by_container(&docker, &index, 8080).await.unwrap());
+        assert!(!port_used_by_container(&docker, &index, 8081).await.unwrap());
+
+        // 同步後改查索引：Docker 端的變化要等事件進來才會反映
+        index.sync(&docker).await.unwrap();
+        containers.lock().unwrap().clear();
+        assert!(port_used_by_container(&docker, &index, 8080).await.unwrap());
+        assert_eq!(index.used_ports().await, Some(HashSet::from([8080])));
+    }
+}
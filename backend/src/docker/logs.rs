@@ -0,0 +1,51 @@
+//! 容器日誌（stdout/stderr）：包裝 bollard logs 串流，供 HTTP chunked 與唯讀 WebSocket 共用。
+
+use bollard::query_parameters::LogsOptionsBuilder;
+use bollard::Docker;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+
+/// GET /api/containers/:id/logs 與 /ws/logs 共用的查詢參數。
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LogsParams {
+    /// 最後 N 行（或 "all"）；預設 100。
+    pub tail: Option<String>,
+    /// 只取此 UNIX 時間（秒）之後的日誌。
+    pub since: Option<i32>,
+    #[serde(default)]
+    pub timestamps: bool,
+    /// 持續追蹤新輸出（HTTP 預設 false；WebSocket 一律 follow）。
+    #[serde(default)]
+    pub follow: bool,
+}
+
+impl LogsParams {
+    /// tail 只接受數字或 "all"，其餘視為預設值。
+    fn tail(&self) -> String {
+        match self.tail.as_deref() {
+            Some("all") => "all".to_string(),
+            Some(n) if n.parse::<u32>().is_ok() => n.to_string(),
+            _ => "100".to_string(),
+        }
+    }
+}
+
+/// 開啟容器的 stdout/stderr 串流；每個項目為一段原始輸出位元組。
+pub fn log_stream(
+    docker: &Docker,
+    id: &str,
+    params: &LogsParams,
+) -> impl Stream<Item = Result<Bytes, bollard::errors::Error>> + Send + Unpin {
+    let opts = LogsOptionsBuilder::default()
+        .stdout(true)
+        .stderr(true)
+        .follow(params.follow)
+        .timestamps(params.timestamps)
+        .since(params.since.unwrap_or(0))
+        .tail(&params.tail())
+        .build();
+    docker
+        .logs(id, Some(opts))
+        .map(|item| item.map(|out| out.into_bytes()))
+        .boxed()
+}
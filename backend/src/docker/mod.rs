@@ -1,27 +1,52 @@
-//! 透過 bollard 操作 Docker：列容器/映像、解析埠、NVIDIA 檢測、console 元資料。
+//! 透過 bollard 操作 Docker：列容器/映像、解析埠、NVIDIA 檢測與 GPU 配置、console 元資料、日誌串流、資源用量。
 //! 與 Django 的容器/映像/埠邏輯對齊；僅處理使用 gui-vnc 前綴的映像。
 
+pub mod archive;
+pub mod gpu;
+pub mod index;
+pub mod logs;
 pub mod nvidia;
 pub mod ports;
+pub mod stats;
 
-use bollard::query_parameters::{
-    InspectContainerOptionsBuilder, ListContainersOptionsBuilder, ListImagesOptionsBuilder,
-};
+use bollard::models::{ContainerInspectResponse, ContainerSummary};
+use bollard::query_parameters::{ListContainersOptionsBuilder, ListImagesOptionsBuilder};
 use bollard::Docker;
-use std::collections::HashMap;
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
+
+use crate::permissions::Permission;
 
 use ports::parse_ports_bollard;
 
 /// 映像 tag 前綴，用於篩選 GUI 容器/映像（與 Django DOCKER_IMAGE_NAME 一致）。
 pub const GUI_IMAGE_TAG_PREFIX: &str = "gui-vnc";
 
+/// 管理器建立的容器/匯出映像上記錄擁有者 user id 的 label。
+pub const LABEL_OWNER: &str = "dev-dock-manager.owner";
+/// 管理器建立時的容器名稱（匯出映像也會帶上，供下載檔名使用）。
+pub const LABEL_NAME: &str = "dev-dock-manager.name";
+
+/// 匯出容器時 commit 出的映像 repository；tag 為匯出任務的 task_id。
+pub const EXPORT_IMAGE_REPO: &str = "dev-dock-export";
+
+/// 匯出任務對應的映像名稱（`dev-dock-export:<task_id>`）。
+pub fn export_image_ref(task_id: &str) -> String {
+    format!("{}:{}", EXPORT_IMAGE_REPO, task_id)
+}
+
+/// 匯入的映像重新 tag 的名稱（`dev-dock-export/<owner>:<task_id>`）：每次匯入各自一個 tag，不沿用 tarball 宣告的 tag。
+pub fn import_image_ref(owner_id: i64, task_id: &str) -> String {
+    format!("{}/{}:{}", EXPORT_IMAGE_REPO, owner_id, task_id)
+}
+
 /// 建立 Docker 連線（依環境 DOCKER_HOST / 本機預設）。
 pub fn connect() -> Result<Docker, bollard::errors::Error> {
     Docker::connect_with_local_defaults()
 }
 
 /// 單一容器摘要，對應 Django API 回傳格式。
-#[derive(serde::Serialize)]
+#[derive(Clone, serde::Serialize)]
 pub struct ContainerInfo {
     pub id: String,
     pub name: String,
@@ -30,86 +55,165 @@ pub struct ContainerInfo {
     pub short_id: String,
     pub image_tag: String,
     pub ports: HashMap<String, String>,
+    /// 服務名 -> 綁定位址（含介面，例如 `127.0.0.1:2222`、`[::1]:2222`）。
+    pub port_addresses: HashMap<String, Vec<String>>,
     pub privileged: bool,
     pub nvdocker: bool,
     pub size_raw: i64,
     pub size_fs: i64,
+    /// 擁有者 user id（LABEL_OWNER）；舊版建立的容器沒有此 label。
+    pub owner_id: Option<i64>,
 }
 
-/// 列出使用 gui-vnc 前綴映像的容器；每筆會 inspect 以取得名稱、狀態、埠等。
-pub async fn list_containers_gui_vnc(
+/// 從 label 解析擁有者 user id。
+pub fn owner_from_labels(labels: Option<&HashMap<String, String>>) -> Option<i64> {
+    labels
+        .and_then(|l| l.get(LABEL_OWNER))
+        .and_then(|v| v.parse().ok())
+}
+
+/// 有 `containers.any` 可存取所有容器；其他使用者只能存取 owner label 為自己的容器（無 owner 的舊容器亦同）。
+pub fn user_can_access(user: &crate::db::User, owner_id: Option<i64>) -> bool {
+    user.can(Permission::ContainersAny) || owner_id == Some(user.id)
+}
+
+/// 查詢容器的擁有者 user id（LABEL_OWNER）。
+pub async fn container_owner(
     docker: &Docker,
-) -> Result<Vec<ContainerInfo>, bollard::errors::Error> {
-    let opts = ListContainersOptionsBuilder::default()
-        .all(true)
-        .build();
-    let summaries = docker.list_containers(Some(opts)).await?;
-    let mut out = Vec::new();
-    for c in summaries {
-        let id = c.id.as_deref().unwrap_or("");
-        let image = c.image.as_deref().unwrap_or("");
-        // Image in summary can be tag or id; we need to inspect to get tags and filter.
-        // size=true is required for SizeRw and SizeRootFs to be returned
-        let inspect_opts = InspectContainerOptionsBuilder::default()
-            .size(true)
+    id: &str,
+) -> Result<Option<i64>, bollard::errors::Error> {
+    let inspect = docker.inspect_container(id, None).await?;
+    Ok(owner_from_labels(
+        inspect.config.as_ref().and_then(|c| c.labels.as_ref()),
+    ))
+}
+
+/// 同時進行的 inspect 上限（避免主機上容器很多時一次打爆 Docker API）。
+const INSPECT_CONCURRENCY: usize = 8;
+
+/// 以 Docker filter 在伺服器端篩出管理中的容器摘要：帶 LABEL_OWNER 者，加上 ancestor 為 gui-vnc 映像的舊容器。
+/// Docker 對不同 filter key 取交集，因此分兩次查詢後依 id 合併。`with_size` 時由 list 一併計算 SizeRw/SizeRootFs。
+async fn list_manager_summaries(
+    docker: &Docker,
+    with_size: bool,
+) -> Result<Vec<ContainerSummary>, bollard::errors::Error> {
+    let mut by_label = HashMap::new();
+    by_label.insert("label".to_string(), vec![LABEL_OWNER.to_string()]);
+    let mut queries = vec![by_label];
+
+    let gui_images: Vec<String> = list_images(docker, GUI_IMAGE_TAG_PREFIX)
+        .await?
+        .into_iter()
+        .flat_map(|img| img.tags)
+        .filter(|t| t.starts_with(GUI_IMAGE_TAG_PREFIX))
+        .collect();
+    if !gui_images.is_empty() {
+        let mut by_ancestor = HashMap::new();
+        by_ancestor.insert("ancestor".to_string(), gui_images);
+        queries.push(by_ancestor);
+    }
+
+    let mut out: Vec<ContainerSummary> = Vec::new();
+    for filters in queries {
+        let opts = ListContainersOptionsBuilder::default()
+            .all(true)
+            .size(with_size)
+            .filters(&filters)
             .build();
-        let inspect = match docker.inspect_container(id, Some(inspect_opts)).await {
-            Ok(i) => i,
-            Err(_) => continue,
-        };
-        let image_tag = inspect
-            .config
-            .as_ref()
-            .and_then(|cfg| cfg.image.as_deref())
-            .unwrap_or(image);
-        if !image_tag.starts_with(GUI_IMAGE_TAG_PREFIX) {
-            continue;
+        for c in docker.list_containers(Some(opts)).await? {
+            if !out.iter().any(|o| o.id == c.id) {
+                out.push(c);
+            }
         }
-        let name = inspect
-            .name
-            .as_deref()
-            .map(|n| n.trim_start_matches('/'))
-            .unwrap_or("")
-            .to_string();
-        let state = inspect
-            .state
-            .as_ref()
-            .and_then(|s| s.status.as_ref())
-            .map(|st| format!("{:?}", st).to_lowercase())
-            .unwrap_or_else(|| "unknown".to_string());
-        let host_config = inspect.host_config.as_ref();
-        let port_bindings = host_config
-            .and_then(|h| h.port_bindings.as_ref())
-            .map(parse_ports_bollard)
-            .unwrap_or_default();
-        let privileged = host_config
-            .map(|h| h.privileged.unwrap_or(false))
-            .unwrap_or(false);
-        let nvdocker = host_config
-            .and_then(|h| h.device_requests.as_ref())
-            .map(|dr| {
-                dr.iter()
-                    .any(|r| r.driver.as_deref() == Some("nvidia"))
-            })
-            .unwrap_or(false);
-        let short_id = id.chars().take(12).collect::<String>();
-        out.push(ContainerInfo {
-            id: id.to_string(),
-            name,
-            status: state,
-            command: inspect.config.as_ref().and_then(|c| c.cmd.clone()),
-            short_id,
-            image_tag: image_tag.to_string(),
-            ports: port_bindings,
-            privileged,
-            nvdocker,
-            size_raw: inspect.size_rw.unwrap_or(0),
-            size_fs: inspect.size_root_fs.unwrap_or(0),
-        });
     }
     Ok(out)
 }
 
+/// 由 inspect 結果組出 ContainerInfo；不是 gui-vnc 映像且沒有 LABEL_OWNER 的容器回 None。
+/// `sizes` 為 (SizeRw, SizeRootFs)，未要求大小時為 0。
+pub fn container_info(
+    id: &str,
+    summary_image: &str,
+    inspect: &ContainerInspectResponse,
+    sizes: (i64, i64),
+) -> Option<ContainerInfo> {
+    let image_tag = inspect
+        .config
+        .as_ref()
+        .and_then(|cfg| cfg.image.as_deref())
+        .unwrap_or(summary_image);
+    let owner_id = owner_from_labels(inspect.config.as_ref().and_then(|c| c.labels.as_ref()));
+    if !image_tag.starts_with(GUI_IMAGE_TAG_PREFIX) && owner_id.is_none() {
+        return None;
+    }
+    let name = inspect
+        .name
+        .as_deref()
+        .map(|n| n.trim_start_matches('/'))
+        .unwrap_or("")
+        .to_string();
+    let state = inspect
+        .state
+        .as_ref()
+        .and_then(|s| s.status.as_ref())
+        .map(|st| format!("{:?}", st).to_lowercase())
+        .unwrap_or_else(|| "unknown".to_string());
+    let host_config = inspect.host_config.as_ref();
+    let raw_bindings = host_config.and_then(|h| h.port_bindings.as_ref());
+    let port_bindings = raw_bindings.map(parse_ports_bollard).unwrap_or_default();
+    let port_addresses = raw_bindings
+        .map(ports::parse_port_addresses)
+        .unwrap_or_default();
+    let privileged = host_config
+        .map(|h| h.privileged.unwrap_or(false))
+        .unwrap_or(false);
+    let nvdocker = host_config
+        .and_then(|h| h.device_requests.as_ref())
+        .map(|dr| {
+            dr.iter()
+                .any(|r| r.driver.as_deref() == Some("nvidia"))
+        })
+        .unwrap_or(false);
+    let short_id = id.chars().take(12).collect::<String>();
+    Some(ContainerInfo {
+        id: id.to_string(),
+        name,
+        status: state,
+        command: inspect.config.as_ref().and_then(|c| c.cmd.clone()),
+        short_id,
+        image_tag: image_tag.to_string(),
+        ports: port_bindings,
+        port_addresses,
+        privileged,
+        nvdocker,
+        size_raw: sizes.0,
+        size_fs: sizes.1,
+        owner_id,
+    })
+}
+
+/// 列出使用 gui-vnc 前綴映像（或帶有 LABEL_OWNER，例如由匯入映像還原）的容器。
+/// 摘要先由 Docker filter 篩選，再以有限並行數 inspect 取得埠、privileged、GPU 等；
+/// 容器大小計算成本高，僅在 `with_size` 時由 Docker 計算。
+pub async fn list_containers_gui_vnc(
+    docker: &Docker,
+    with_size: bool,
+) -> Result<Vec<ContainerInfo>, bollard::errors::Error> {
+    let summaries = list_manager_summaries(docker, with_size).await?;
+    let out = futures_util::stream::iter(summaries)
+        .map(|c| async move {
+            let id = c.id.clone().unwrap_or_default();
+            let inspect = docker.inspect_container(&id, None).await.ok()?;
+            let sizes = (c.size_rw.unwrap_or(0), c.size_root_fs.unwrap_or(0));
+            container_info(&id, c.image.as_deref().unwrap_or(""), &inspect, sizes)
+        })
+        .buffered(INSPECT_CONCURRENCY)
+        .filter_map(|info| async move { info })
+        .collect()
+        .await;
+    Ok(out)
+}
+
 /// Image list item matching Django API response shape.
 #[derive(serde::Serialize)]
 pub struct ImageInfo {
@@ -166,54 +270,77 @@ pub async fn system_info(
     serde_json::to_value(info).map_err(|e| e.to_string())
 }
 
-/// Check if any container is using the given host port.
-pub async fn is_port_used_by_container(
-    docker: &Docker,
-    port: u16,
-) -> Result<bool, bollard::errors::Error> {
+/// 所有容器綁定的 host port（直接查 Docker：list 一次並以有限並行數 inspect）。
+pub async fn container_host_ports(docker: &Docker) -> Result<HashSet<u16>, bollard::errors::Error> {
     let opts = ListContainersOptionsBuilder::default()
         .all(true)
         .build();
     let summaries = docker.list_containers(Some(opts)).await?;
-    let port_str = port.to_string();
-    for c in summaries {
-        let id = match &c.id { Some(x) => x.as_str(), None => continue };
-        let inspect = match docker.inspect_container(id, None).await {
-            Ok(i) => i,
-            Err(_) => continue,
-        };
-        let bindings = inspect
-            .host_config
-            .as_ref()
-            .and_then(|h| h.port_bindings.as_ref());
-        if let Some(pb) = bindings {
-            let parsed = parse_ports_bollard(pb);
-            if parsed.values().any(|v| v == &port_str) {
-                return Ok(true);
-            }
-        }
+    let out = futures_util::stream::iter(summaries)
+        .map(|c| async move {
+            let id = c.id?;
+            let inspect = docker.inspect_container(&id, None).await.ok()?;
+            inspect
+                .host_config
+                .and_then(|h| h.port_bindings)
+                .map(|pb| ports::host_ports(&pb))
+        })
+        .buffer_unordered(INSPECT_CONCURRENCY)
+        .filter_map(|x| async move { x })
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+    Ok(out)
+}
+
+/// Check if any container is using the given host port (queries Docker directly; see `port_used_by_container`).
+pub async fn is_port_used_by_container(
+    docker: &Docker,
+    port: u16,
+) -> Result<bool, bollard::errors::Error> {
+    Ok(container_host_ports(docker).await?.contains(&port))
+}
+
+/// 所有容器綁定的 host port：優先取事件索引，索引尚未同步時才直接查 Docker。
+pub async fn used_container_ports(
+    docker: &Docker,
+    index: &index::ContainerIndex,
+) -> Result<HashSet<u16>, bollard::errors::Error> {
+    match index.used_ports().await {
+        Some(used) => Ok(used),
+        None => container_host_ports(docker).await,
     }
-    Ok(false)
 }
 
-/// Find up to `count` free ports (not in use on host and not used by any container).
+/// 埠是否被任何容器綁定：優先查事件索引，索引尚未同步時才直接查 Docker。
+pub async fn port_used_by_container(
+    docker: &Docker,
+    index: &index::ContainerIndex,
+    port: u16,
+) -> Result<bool, bollard::errors::Error> {
+    match index.port_in_use(port).await {
+        Some(used) => Ok(used),
+        None => is_port_used_by_container(docker, port).await,
+    }
+}
+
+/// Find up to `count` free ports within the policy (not in use on host, not used by any container, not in `reserved`).
+/// 容器佔用的埠只計算一次；host 檢查並行進行，找到足夠數量即停止。
 pub async fn find_multiple_free_ports(
     docker: &Docker,
-    host: &str,
+    index: &index::ContainerIndex,
+    policy: &ports::PortPolicy,
+    reserved: &HashSet<u16>,
+    hosts: &[ports::CheckHost],
     count: u32,
 ) -> Result<Vec<u16>, String> {
-    let mut out = Vec::with_capacity(count as usize);
-    let mut port: u16 = 1024;
-    while (out.len() as u32) < count && port < 65535 {
-        if !ports::check_port_in_use(host, port)
-            && !is_port_used_by_container(docker, port)
-                .await
-                .map_err(|e| e.to_string())?
-        {
-            out.push(port);
-        }
-        port += 1;
-    }
+    let mut used = used_container_ports(docker, index)
+        .await
+        .map_err(|e| e.to_string())?;
+    used.extend(reserved);
+    let out = ports::free_host_ports(hosts, policy.candidates(&used), count as usize).await;
     if (out.len() as u32) < count {
         return Err("Not enough free ports available.".to_string());
     }
@@ -272,3 +399,221 @@ pub async fn get_console_meta(
         action: action.to_string(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::{Query, State},
+        http::Uri,
+        response::{IntoResponse, Response},
+        Json, Router,
+    };
+    use bollard::models::{ContainerConfig, ContainerState, ContainerStateStatusEnum, ImageSummary};
+    use bollard::query_parameters::InspectContainerOptionsBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct MockContainer {
+        id: &'static str,
+        /// 建立時指定的映像（Config.Image）。
+        image: &'static str,
+        /// 映像本身與其上層映像的 id（ancestor filter 用）。
+        image_chain: &'static [&'static str],
+        labels: &'static [(&'static str, &'static str)],
+        size_rw: i64,
+    }
+
+    struct MockDocker {
+        images: Vec<(&'static str, Vec<String>)>,
+        containers: Vec<MockContainer>,
+        inspects: AtomicUsize,
+    }
+
+    impl MockDocker {
+        fn image_id(&self, tag: &str) -> Option<&'static str> {
+            self.images.iter().find(|(_, tags)| tags.iter().any(|t| t == tag)).map(|(id, _)| *id)
+        }
+
+        /// Docker 的 filter 語意：同一 key 的值取聯集，不同 key 取交集。
+        fn matches(&self, c: &MockContainer, filters: &HashMap<String, Vec<String>>) -> bool {
+            filters.iter().all(|(key, values)| match key.as_str() {
+                "label" => values.iter().any(|v| c.labels.iter().any(|(k, _)| k == v)),
+                "ancestor" => values
+                    .iter()
+                    .filter_map(|tag| self.image_id(tag))
+                    .any(|id| c.image_chain.contains(&id)),
+                other => panic!("unexpected filter {}", other),
+            })
+        }
+
+        fn labels(c: &MockContainer) -> HashMap<String, String> {
+            c.labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        }
+    }
+
+    /// 模擬 Docker Engine API 中 list/inspect 用到的端點（可帶 `/v1.xx` 前綴）。
+    async fn mock_api(
+        State(docker): State<Arc<MockDocker>>,
+        uri: Uri,
+        Query(q): Query<HashMap<String, String>>,
+    ) -> Response {
+        let path = uri.path().trim_start_matches('/');
+        let path = match path.split_once('/') {
+            Some((version, rest)) if version.starts_with('v') => rest,
+            _ => path,
+        };
+        let with_size = q.get("size").map(String::as_str) == Some("true");
+        if path == "images/json" {
+            let images: Vec<ImageSummary> = docker
+                .images
+                .iter()
+                .map(|(id, tags)| ImageSummary {
+                    id: id.to_string(),
+                    repo_tags: tags.clone(),
+                    ..Default::default()
+                })
+                .collect();
+            return Json(images).into_response();
+        }
+        if path == "containers/json" {
+            let filters: HashMap<String, Vec<String>> = q
+                .get("filters")
+                .map(|f| serde_json::from_str(f).unwrap())
+                .unwrap_or_default();
+            let list: Vec<ContainerSummary> = docker
+                .containers
+                .iter()
+                .filter(|c| docker.matches(c, &filters))
+                .map(|c| ContainerSummary {
+                    id: Some(c.id.into()),
+                    image: Some(c.image.into()),
+                    labels: Some(MockDocker::labels(c)),
+                    size_rw: with_size.then_some(c.size_rw),
+                    size_root_fs: with_size.then_some(c.size_rw * 10),
+                    ..Default::default()
+                })
+                .collect();
+            return Json(list).into_response();
+        }
+        let id = path.strip_prefix("containers/").and_then(|p| p.strip_suffix("/json"));
+        match docker.containers.iter().find(|c| Some(c.id) == id) {
+            Some(c) => {
+                docker.inspects.fetch_add(1, Ordering::SeqCst);
+                Json(ContainerInspectResponse {
+                    id: Some(c.id.into()),
+                    name: Some(format!("/{}", c.id)),
+                    config: Some(ContainerConfig {
+                        image: Some(c.image.into()),
+                        labels: Some(MockDocker::labels(c)),
+                        ..Default::default()
+                    }),
+                    state: Some(ContainerState {
+                        status: Some(ContainerStateStatusEnum::RUNNING),
+                        ..Default::default()
+                    }),
+                    size_rw: with_size.then_some(c.size_rw),
+                    size_root_fs: with_size.then_some(c.size_rw * 10),
+                    ..Default::default()
+                })
+                .into_response()
+            }
+            None => axum::http::StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn mock_docker() -> (Docker, Arc<MockDocker>) {
+        let state = Arc::new(MockDocker {
+            images: vec![
+                ("sha256:gui", vec!["gui-vnc:latest".into(), "gui-vnc:v2".into()]),
+                ("sha256:desk", vec!["mydesk:1".into()]),
+                ("sha256:custom", vec!["custom:1".into()]),
+                ("sha256:nginx", vec!["nginx:latest".into()]),
+            ],
+            containers: vec![
+                MockContainer {
+                    id: "owned",
+                    image: "custom:1",
+                    image_chain: &["sha256:custom"],
+                    labels: &[(LABEL_OWNER, "7")],
+                    size_rw: 1,
+                },
+                MockContainer {
+                    id: "legacy",
+                    image: "gui-vnc:latest",
+                    image_chain: &["sha256:gui"],
+                    labels: &[],
+                    size_rw: 2,
+                },
+                // 同時符合 label 與 ancestor：只能出現一次
+                MockContainer {
+                    id: "owned-gui",
+                    image: "gui-vnc:v2",
+                    image_chain: &["sha256:gui"],
+                    labels: &[(LABEL_OWNER, "8")],
+                    size_rw: 3,
+                },
+                // 以 gui-vnc 為基底的其他映像：ancestor 會列出，但不是管理中的容器
+                MockContainer {
+                    id: "derived",
+                    image: "mydesk:1",
+                    image_chain: &["sha256:desk", "sha256:gui"],
+                    labels: &[],
+                    size_rw: 4,
+                },
+                MockContainer {
+                    id: "other",
+                    image: "nginx:latest",
+                    image_chain: &["sha256:nginx"],
+                    labels: &[("com.example", "x")],
+                    size_rw: 5,
+                },
+            ],
+            inspects: AtomicUsize::new(0),
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().fallback(mock_api).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let docker = Docker::connect_with_http(&format!("http://{}", addr), 10, bollard::API_DEFAULT_VERSION).unwrap();
+        (docker, state)
+    }
+
+    /// 改寫前的做法：列出所有容器並逐一 inspect（含大小），再依映像與 label 篩選。
+    async fn list_all_then_inspect(docker: &Docker) -> Vec<ContainerInfo> {
+        let opts = ListContainersOptionsBuilder::default().all(true).build();
+        let mut out = Vec::new();
+        for c in docker.list_containers(Some(opts)).await.unwrap() {
+            let id = c.id.unwrap_or_default();
+            let inspect_opts = InspectContainerOptionsBuilder::default().size(true).build();
+            let inspect = docker.inspect_container(&id, Some(inspect_opts)).await.unwrap();
+            let sizes = (inspect.size_rw.unwrap_or(0), inspect.size_root_fs.unwrap_or(0));
+            out.extend(container_info(&id, c.image.as_deref().unwrap_or(""), &inspect, sizes));
+        }
+        out
+    }
+
+    fn by_id(list: Vec<ContainerInfo>) -> Vec<serde_json::Value> {
+        let mut values: Vec<serde_json::Value> = list.iter().map(|c| serde_json::to_value(c).unwrap()).collect();
+        values.sort_by_key(|v| v["id"].as_str().unwrap_or_default().to_string());
+        values
+    }
+
+    #[tokio::test]
+    async fn filtered_listing_matches_full_scan() {
+        let (docker, state) = mock_docker().await;
+        let expected = by_id(list_all_then_inspect(&docker).await);
+        let ids: Vec<&str> = expected.iter().filter_map(|v| v["id"].as_str()).collect();
+        assert_eq!(ids, ["legacy", "owned", "owned-gui"]);
+
+        state.inspects.store(0, Ordering::SeqCst);
+        let filtered = list_containers_gui_vnc(&docker, true).await.unwrap();
+        assert_eq!(by_id(filtered), expected);
+        // 只 inspect filter 篩出的容器（不含 other）
+        assert_eq!(state.inspects.load(Ordering::SeqCst), 4);
+
+        let without_size = list_containers_gui_vnc(&docker, false).await.unwrap();
+        assert_eq!(without_size.len(), expected.len());
+        assert!(without_size.iter().all(|c| c.size_raw == 0 && c.size_fs == 0));
+    }
+}
@@ -1,12 +1,21 @@
 //! 檢測本機是否可用 NVIDIA Docker（nvidia runtime）。
-//! 透過建立並執行一個最小 nvidia/cuda 容器執行 nvidia-smi 判斷。
+//! 先看 `docker info` 的 runtimes；有 nvidia runtime 才建立最小 nvidia/cuda 容器執行 nvidia-smi 判斷，
+//! 並解析 driver / CUDA 版本。結果快取於 `NvidiaProbe`（TTL + 背景刷新）；同一個探測容器也用來讀取 GPU 清單。
 
 use bollard::models::ContainerCreateBody;
-use bollard::query_parameters::CreateContainerOptions;
+use bollard::query_parameters::{
+    CreateContainerOptions, CreateImageOptions, LogsOptionsBuilder, RemoveContainerOptions,
+};
 use bollard::Docker;
+use futures_util::StreamExt;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
 
-/// 建立暫存容器執行 nvidia-smi；成功則表示 nvdocker 可用，結束後會移除容器。
-pub async fn can_use_nvidia_docker(docker: &Docker) -> bool {
+/// 預設探測用映像（可由 NVIDIA_PROBE_IMAGE 覆寫）；本機沒有時會先 pull。
+pub const DEFAULT_PROBE_IMAGE: &str = "nvidia/cuda:11.0.3-base-ubuntu20.04";
+
+/// 以 nvidia runtime 執行探測容器並回傳 stdout；非 0 結束或建立失敗回 Err。結束後會移除容器。
+pub async fn run_probe(docker: &Docker, image: &str, cmd: Vec<String>) -> Result<String, String> {
     let name = format!(
         "nvidia-check-{}",
         std::time::SystemTime::now()
@@ -19,21 +28,194 @@ pub async fn can_use_nvidia_docker(docker: &Docker) -> bool {
         ..Default::default()
     };
     let body = ContainerCreateBody {
-        image: Some("nvidia/cuda:11.0.3-base-ubuntu20.04".to_string()),
-        cmd: Some(vec!["nvidia-smi".to_string()]),
+        image: Some(image.to_string()),
+        cmd: Some(cmd),
         host_config: Some(bollard::models::HostConfig {
             runtime: Some("nvidia".to_string()),
-            auto_remove: Some(true),
             ..Default::default()
         }),
         ..Default::default()
     };
-    let create = match docker.create_container(Some(opts), body).await {
-        Ok(res) => res,
-        Err(_) => return false,
-    };
+    let create = docker
+        .create_container(Some(opts), body)
+        .await
+        .map_err(|e| e.to_string())?;
     let id = create.id.as_str();
-    let start = docker.start_container(id, None).await;
-    let _ = docker.remove_container(id, None).await;
-    start.is_ok()
+    let result = collect_probe_output(docker, id).await;
+    let remove_opts = RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    };
+    let _ = docker.remove_container(id, Some(remove_opts)).await;
+    result
+}
+
+async fn collect_probe_output(docker: &Docker, id: &str) -> Result<String, String> {
+    docker
+        .start_container(id, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut wait = docker.wait_container(id, None);
+    while let Some(res) = wait.next().await {
+        res.map_err(|e| e.to_string())?;
+    }
+    let opts = LogsOptionsBuilder::default().stdout(true).build();
+    let mut logs = docker.logs(id, Some(opts));
+    let mut out = Vec::new();
+    while let Some(chunk) = logs.next().await {
+        out.extend_from_slice(&chunk.map_err(|e| e.to_string())?.into_bytes());
+    }
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// 探測結果；GET /api/nvdocker/check 回傳此結構。
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NvidiaStatus {
+    pub nvidia_docker_available: bool,
+    /// `docker info` 回報的 runtime 名稱（例如 runc、nvidia）。
+    pub runtimes: Vec<String>,
+    pub driver_version: Option<String>,
+    pub cuda_version: Option<String>,
+    /// 探測時間（UNIX 秒）。
+    pub checked_at: i64,
+    /// 不可用時的原因（例如 runtime 不存在、映像拉取失敗）。
+    pub error: Option<String>,
+}
+
+/// 從 nvidia-smi 標頭取出 `Driver Version: 535.104.05` / `CUDA Version: 12.2` 之類的欄位值。
+fn header_field(out: &str, key: &str) -> Option<String> {
+    let rest = &out[out.find(key)? + key.len()..];
+    rest.split_whitespace().next().map(str::to_string)
+}
+
+/// 本機沒有探測映像時先 pull（只有在 nvidia runtime 存在時才會走到這一步）。
+async fn ensure_image(docker: &Docker, image: &str) -> Result<(), String> {
+    if docker.inspect_image(image).await.is_ok() {
+        return Ok(());
+    }
+    let opts = CreateImageOptions {
+        from_image: Some(image.to_string()),
+        ..Default::default()
+    };
+    let mut pull = docker.create_image(Some(opts), None, None);
+    while let Some(item) = pull.next().await {
+        item.map_err(|e| format!("pull {} failed: {}", image, e))?;
+    }
+    Ok(())
+}
+
+/// 執行一次完整探測：docker info runtimes → （有 nvidia runtime 時）確保映像 → 執行 nvidia-smi。
+pub async fn probe(docker: &Docker, image: &str) -> NvidiaStatus {
+    let checked_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let mut status = NvidiaStatus {
+        nvidia_docker_available: false,
+        runtimes: Vec::new(),
+        driver_version: None,
+        cuda_version: None,
+        checked_at,
+        error: None,
+    };
+    match docker.info().await {
+        Ok(info) => {
+            let mut runtimes: Vec<String> = info.runtimes.unwrap_or_default().into_keys().collect();
+            runtimes.sort();
+            status.runtimes = runtimes;
+        }
+        Err(e) => {
+            status.error = Some(e.to_string());
+            return status;
+        }
+    }
+    if !status.runtimes.iter().any(|r| r == "nvidia") {
+        status.error = Some("nvidia runtime is not registered with Docker".to_string());
+        return status;
+    }
+    if let Err(e) = ensure_image(docker, image).await {
+        status.error = Some(e);
+        return status;
+    }
+    match run_probe(docker, image, vec!["nvidia-smi".to_string()]).await {
+        Ok(out) => {
+            status.nvidia_docker_available = true;
+            status.driver_version = header_field(&out, "Driver Version:");
+            status.cuda_version = header_field(&out, "CUDA Version:");
+        }
+        Err(e) => status.error = Some(e),
+    }
+    status
+}
+
+/// 探測結果快取：啟動時與每 TTL 於背景刷新，逾期時讀取也會觸發刷新；同時間只跑一個探測。
+pub struct NvidiaProbe {
+    docker: Docker,
+    image: String,
+    ttl: Duration,
+    cached: RwLock<Option<(Instant, NvidiaStatus)>>,
+    refresh_lock: Mutex<()>,
+}
+
+impl NvidiaProbe {
+    pub fn new(docker: Docker, image: &str, ttl: Duration) -> Self {
+        Self {
+            docker,
+            image: image.to_string(),
+            ttl,
+            cached: RwLock::new(None),
+            refresh_lock: Mutex::new(()),
+        }
+    }
+
+    /// 未逾期的快取結果（不觸發探測）。
+    pub async fn cached(&self) -> Option<NvidiaStatus> {
+        self.cached
+            .read()
+            .await
+            .as_ref()
+            .filter(|(at, _)| at.elapsed() < self.ttl)
+            .map(|(_, s)| s.clone())
+    }
+
+    /// 最近一次探測結果（不論是否逾期，不觸發探測）；尚未探測過回 None。
+    pub async fn latest(&self) -> Option<NvidiaStatus> {
+        self.cached.read().await.as_ref().map(|(_, s)| s.clone())
+    }
+
+    /// 取得結果：快取未逾期直接回傳，否則重新探測。
+    pub async fn get(&self) -> NvidiaStatus {
+        if let Some(s) = self.cached().await {
+            return s;
+        }
+        self.refresh().await
+    }
+
+    /// 強制重新探測並更新快取；若已有探測進行中則等待並沿用其結果。
+    pub async fn refresh(&self) -> NvidiaStatus {
+        let started = Instant::now();
+        let _guard = self.refresh_lock.lock().await;
+        if let Some((at, s)) = self.cached.read().await.as_ref() {
+            if *at >= started {
+                return s.clone();
+            }
+        }
+        let status = probe(&self.docker, &self.image).await;
+        *self.cached.write().await = Some((Instant::now(), status.clone()));
+        status
+    }
+
+    /// 常駐迴圈：立即探測一次，之後每 TTL 刷新。
+    pub async fn run_refresher(self: std::sync::Arc<Self>) {
+        loop {
+            let status = self.refresh().await;
+            tracing::info!(
+                "NVIDIA probe: available={} driver={:?} cuda={:?}",
+                status.nvidia_docker_available,
+                status.driver_version,
+                status.cuda_version
+            );
+            tokio::time::sleep(self.ttl).await;
+        }
+    }
 }
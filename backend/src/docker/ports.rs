@@ -1,10 +1,18 @@
 //! 埠解析與佔用檢查：將 Docker 埠綁定對應為服務名（vnc/novnc/ssh）與 host port，並可檢查 host 上埠是否被佔用。
+//! 可分配的埠範圍與排除清單由 `PortPolicy`（Config 的 PORT_RANGE / PORT_EXCLUDE）決定；
+//! host port 綁定的介面（IPv4/IPv6/僅 loopback）由 PORT_BIND_ADDRS 決定，綁定、佔用檢查與解析皆依此設定。
 
 use bollard::models::PortBinding;
-use std::collections::HashMap;
-use std::net::ToSocketAddrs;
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::time::Duration;
 
+/// 單一 host port 連線檢查的逾時。
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// 同時進行的 host port 連線檢查數。
+const CHECK_CONCURRENCY: usize = 64;
+
 const PORT_SERVICE: &[(&str, &str)] = &[
     ("5901/tcp", "vnc"),
     ("6901/tcp", "novnc"),
@@ -12,50 +20,319 @@ const PORT_SERVICE: &[(&str, &str)] = &[
 ];
 
 /// 將 bollard 的 port_bindings 轉成「服務名 -> host port」對應（僅處理 vnc/novnc/ssh）。
+/// 同一個容器埠可能綁在多個介面（例如 127.0.0.1 與 ::1），取第一個有 host port 的綁定。
 pub fn parse_ports_bollard(
     port_bindings: &HashMap<String, Option<Vec<PortBinding>>>,
 ) -> HashMap<String, String> {
     let mut result = HashMap::new();
     for (port, bindings) in port_bindings {
-        if let Some(binds) = bindings {
-            if let Some(first) = binds.first() {
-                if let Some(ref host_port) = first.host_port {
-                    if let Some(service) = PORT_SERVICE.iter().find(|(p, _)| *p == port) {
-                        result.insert((*service.1).to_string(), host_port.clone());
-                    }
-                }
-            }
+        let service = match PORT_SERVICE.iter().find(|(p, _)| *p == port) {
+            Some(s) => s.1,
+            None => continue,
+        };
+        let host_port = bindings
+            .iter()
+            .flatten()
+            .filter_map(|b| b.host_port.as_deref())
+            .find(|p| !p.is_empty());
+        if let Some(host_port) = host_port {
+            result.insert(service.to_string(), host_port.to_string());
         }
     }
     result
 }
 
+/// 服務名 -> 所有綁定位址（`127.0.0.1:2222`、`[::1]:2222`；未指定介面為 `0.0.0.0:2222`）。
+pub fn parse_port_addresses(
+    port_bindings: &HashMap<String, Option<Vec<PortBinding>>>,
+) -> HashMap<String, Vec<String>> {
+    let mut result = HashMap::new();
+    for (port, bindings) in port_bindings {
+        let service = match PORT_SERVICE.iter().find(|(p, _)| *p == port) {
+            Some(s) => s.1,
+            None => continue,
+        };
+        let addrs: Vec<String> = bindings
+            .iter()
+            .flatten()
+            .filter_map(|b| {
+                let host_port: u16 = b.host_port.as_deref()?.parse().ok()?;
+                let ip = b
+                    .host_ip
+                    .as_deref()
+                    .filter(|ip| !ip.is_empty())
+                    .and_then(|ip| ip.parse().ok())
+                    .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+                Some(SocketAddr::new(ip, host_port).to_string())
+            })
+            .collect();
+        if !addrs.is_empty() {
+            result.insert(service.to_string(), addrs);
+        }
+    }
+    result
+}
+
+/// 解析 PORT_BIND_ADDRS：逗號分隔的 IPv4/IPv6 位址（IPv6 可加方括號），
+/// `loopback` 代表 127.0.0.1 與 ::1；空值或 `all` 表示沿用 Docker 預設（所有介面）。
+pub fn parse_bind_addrs(list: &str) -> Vec<IpAddr> {
+    let mut out = Vec::new();
+    for item in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match item {
+            "all" => {}
+            "loopback" => {
+                out.push(IpAddr::V4(Ipv4Addr::LOCALHOST));
+                out.push(IpAddr::V6(Ipv6Addr::LOCALHOST));
+            }
+            _ => match item.trim_start_matches('[').trim_end_matches(']').parse() {
+                Ok(ip) => out.push(ip),
+                Err(_) => tracing::warn!("PORT_BIND_ADDRS: ignoring invalid address {:?}", item),
+            },
+        }
+        out.dedup();
+    }
+    out
+}
+
+/// 建立容器時某個容器埠的 PortBinding：未設定介面時為單一不指定 host_ip 的綁定，否則每個介面各一筆。
+pub fn port_bindings_for(bind_addrs: &[IpAddr], host_port: u16) -> Vec<PortBinding> {
+    if bind_addrs.is_empty() {
+        return vec![PortBinding {
+            host_ip: None,
+            host_port: Some(host_port.to_string()),
+        }];
+    }
+    bind_addrs
+        .iter()
+        .map(|ip| PortBinding {
+            host_ip: Some(ip.to_string()),
+            host_port: Some(host_port.to_string()),
+        })
+        .collect()
+}
+
+/// 位址族（IPv4 / IPv6）。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn of(ip: &IpAddr) -> Family {
+        match ip {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+}
+
+/// 佔用檢查的目標：`host` 解析出的第一個 `family` 位址。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckHost {
+    pub host: String,
+    pub family: Family,
+}
+
+/// 佔用檢查要連線的主機：一律連 HOST_FOR_PORT_CHECK（後端通常跑在容器內，直接連 loopback 或綁定的 IP
+/// 只會連到後端容器自己的 network namespace，永遠回報「未佔用」），PORT_BIND_ADDRS 中每個位址族各檢查一次。
+/// 未設定 PORT_BIND_ADDRS 時 Docker 綁在所有介面，IPv4 與 IPv6 都檢查。
+pub fn check_hosts(config: &crate::config::Config) -> Vec<CheckHost> {
+    check_hosts_for(&config.host_for_port_check, &config.port_bind_addrs)
+}
+
+/// 依綁定位址決定要檢查的位址族（每族一次，依 PORT_BIND_ADDRS 中首次出現的順序）。
+pub fn check_hosts_for(host: &str, bind_addrs: &[IpAddr]) -> Vec<CheckHost> {
+    let mut families: Vec<Family> = bind_addrs.iter().map(Family::of).collect();
+    if families.is_empty() {
+        families = vec![Family::V4, Family::V6];
+    }
+    let mut out: Vec<CheckHost> = Vec::new();
+    for family in families {
+        if !out.iter().any(|h| h.family == family) {
+            out.push(CheckHost {
+                host: host.to_string(),
+                family,
+            });
+        }
+    }
+    out
+}
+
+/// 取出 port_bindings 中所有綁定的 host port（不限 vnc/novnc/ssh），供埠佔用查詢。
+pub fn host_ports(port_bindings: &HashMap<String, Option<Vec<PortBinding>>>) -> Vec<u16> {
+    port_bindings
+        .values()
+        .flatten()
+        .flatten()
+        .filter_map(|b| b.host_port.as_deref())
+        .filter_map(|p| p.parse().ok())
+        .collect()
+}
+
 /// 將泛型 port_bindings（HashMap 格式）轉成服務名 -> host port；供測試或其它呼叫端使用。
 pub fn parse_ports(port_bindings: &HashMap<String, Option<Vec<HashMap<String, String>>>>) -> HashMap<String, String> {
     let mut result = HashMap::new();
     for (port, bindings) in port_bindings {
-        if let Some(binds) = bindings {
-            if let Some(first) = binds.first() {
-                if let Some(host_port) = first.get("HostPort") {
-                    if let Some(service) = PORT_SERVICE.iter().find(|(p, _)| *p == port.as_str()) {
-                        result.insert((*service.1).to_string(), host_port.clone());
-                    }
+        let service = match PORT_SERVICE.iter().find(|(p, _)| *p == port.as_str()) {
+            Some(s) => s.1,
+            None => continue,
+        };
+        let host_port = bindings
+            .iter()
+            .flatten()
+            .filter_map(|b| b.get("HostPort"))
+            .find(|p| !p.is_empty());
+        if let Some(host_port) = host_port {
+            result.insert(service.to_string(), host_port.clone());
+        }
+    }
+    result
+}
+
+/// 可分配給容器的 host port：範圍內且不在排除清單。
+#[derive(Clone, Debug)]
+pub struct PortPolicy {
+    pub start: u16,
+    pub end: u16,
+    pub exclude: HashSet<u16>,
+}
+
+impl PortPolicy {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        let (start, end) = config.port_range;
+        Self {
+            start,
+            end,
+            exclude: config.port_exclude.iter().copied().collect(),
+        }
+    }
+
+    pub fn allows(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port) && !self.exclude.contains(&port)
+    }
+
+    /// 範圍內未被排除、也不在 `used` 中的候選埠（由小到大）。
+    pub fn candidates<'a>(&'a self, used: &'a HashSet<u16>) -> impl Iterator<Item = u16> + 'a {
+        (self.start..=self.end).filter(move |p| self.allows(*p) && !used.contains(p))
+    }
+}
+
+/// 解析 `8000,8080,9000-9100` 形式的埠清單（PORT_EXCLUDE）；無法解析的項目略過。
+pub fn parse_port_list(list: &str) -> Vec<u16> {
+    let mut out = Vec::new();
+    for item in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match item.split_once('-') {
+            Some((a, b)) => {
+                if let (Ok(a), Ok(b)) = (a.trim().parse::<u16>(), b.trim().parse::<u16>()) {
+                    out.extend(a..=b);
+                }
+            }
+            None => {
+                if let Ok(p) = item.parse() {
+                    out.push(p);
                 }
             }
         }
     }
-    result
+    out
+}
+
+/// 解析 `start-end` 形式的埠範圍（PORT_RANGE）。
+pub fn parse_port_range(range: &str) -> Option<(u16, u16)> {
+    let (a, b) = range.split_once('-')?;
+    let (a, b) = (a.trim().parse().ok()?, b.trim().parse().ok()?);
+    (a <= b).then_some((a, b))
 }
 
-/// 以 TCP 連線嘗試判斷指定 host:port 是否已被佔用。
-pub fn check_port_in_use(host: &str, port: u16) -> bool {
-    let addr = match (host, port).to_socket_addrs() {
-        Ok(mut a) => a.next(),
-        Err(_) => return false,
-    };
-    let addr = match addr {
-        Some(a) => a,
-        None => return false,
-    };
-    std::net::TcpStream::connect_timeout(&addr, Duration::from_secs(1)).is_ok()
+/// 解析所有檢查目標一次（每個目標取該位址族的第一個位址；主機沒有該位址族時略過），供大量埠檢查共用。
+async fn resolve_hosts(hosts: &[CheckHost]) -> Vec<SocketAddr> {
+    let mut out = Vec::new();
+    for target in hosts {
+        let Ok(mut addrs) = tokio::net::lookup_host((target.host.as_str(), 0)).await else {
+            continue;
+        };
+        if let Some(addr) = addrs.find(|a| Family::of(&a.ip()) == target.family) {
+            if !out.contains(&addr) {
+                out.push(addr);
+            }
+        }
+    }
+    out
+}
+
+async fn connect_ok(mut addr: SocketAddr, port: u16) -> bool {
+    addr.set_port(port);
+    matches!(
+        tokio::time::timeout(CONNECT_TIMEOUT, tokio::net::TcpStream::connect(addr)).await,
+        Ok(Ok(_))
+    )
+}
+
+/// 任一位址上的 port 可連線即視為佔用。
+async fn any_connect_ok(addrs: &[SocketAddr], port: u16) -> bool {
+    futures_util::future::join_all(addrs.iter().map(|a| connect_ok(*a, port)))
+        .await
+        .into_iter()
+        .any(|ok| ok)
+}
+
+/// 以 TCP 連線嘗試判斷 port 在任一檢查主機（見 `check_hosts`）上是否已被佔用（非同步，不阻塞 runtime）。
+pub async fn check_port_in_use(hosts: &[CheckHost], port: u16) -> bool {
+    any_connect_ok(&resolve_hosts(hosts).await, port).await
+}
+
+/// 依序（但並行檢查）從候選埠中找出所有檢查主機上都未被佔用的前 `count` 個；找到足夠數量即停止。
+pub async fn free_host_ports(
+    hosts: &[CheckHost],
+    candidates: impl Iterator<Item = u16>,
+    count: usize,
+) -> Vec<u16> {
+    let addrs = resolve_hosts(hosts).await;
+    let addrs = &addrs;
+    futures_util::stream::iter(candidates)
+        .map(|port| async move { (port, any_connect_ok(addrs, port).await) })
+        .buffered(CHECK_CONCURRENCY)
+        .filter_map(|(port, used)| async move { (!used).then_some(port) })
+        .take(count)
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn families(bind: &str) -> Vec<Family> {
+        check_hosts_for("host.docker.internal", &parse_bind_addrs(bind))
+            .into_iter()
+            .map(|h| {
+                assert_eq!(h.host, "host.docker.internal");
+                h.family
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_hosts_probe_each_bind_family_once() {
+        assert_eq!(families(""), [Family::V4, Family::V6]);
+        assert_eq!(families("all"), [Family::V4, Family::V6]);
+        assert_eq!(families("0.0.0.0"), [Family::V4]);
+        assert_eq!(families("127.0.0.1,10.0.0.5"), [Family::V4]);
+        assert_eq!(families("::"), [Family::V6]);
+        assert_eq!(families("[::1],127.0.0.1"), [Family::V6, Family::V4]);
+        assert_eq!(families("loopback"), [Family::V4, Family::V6]);
+    }
+
+    #[tokio::test]
+    async fn port_check_uses_only_the_requested_family() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let v4 = check_hosts_for("127.0.0.1", &[IpAddr::V4(Ipv4Addr::UNSPECIFIED)]);
+        assert!(check_port_in_use(&v4, port).await);
+        // 127.0.0.1 沒有 IPv6 位址：只綁 IPv6 時不會改連 IPv4
+        let v6 = check_hosts_for("127.0.0.1", &[IpAddr::V6(Ipv6Addr::UNSPECIFIED)]);
+        assert!(resolve_hosts(&v6).await.is_empty());
+        assert!(!check_port_in_use(&v6, port).await);
+    }
 }
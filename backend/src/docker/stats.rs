@@ -0,0 +1,249 @@
+//! 容器資源用量：由 bollard stats 計算 CPU %、記憶體、網路 I/O、磁碟 I/O 與 PIDs（算法同 docker stats）。
+
+use bollard::models::ContainerStatsResponse;
+use bollard::query_parameters::StatsOptionsBuilder;
+use bollard::Docker;
+use futures_util::StreamExt;
+
+/// 單一容器的一筆資源取樣。
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ContainerStats {
+    pub id: String,
+    pub name: String,
+    pub cpu_percent: f64,
+    /// 記憶體用量（已扣除 page cache 的 inactive_file），bytes。
+    pub memory_usage: u64,
+    pub memory_limit: u64,
+    pub memory_percent: f64,
+    pub net_rx: u64,
+    pub net_tx: u64,
+    pub block_read: u64,
+    pub block_write: u64,
+    pub pids: u64,
+}
+
+impl ContainerStats {
+    /// 由 Docker 原始 stats 計算；需含 precpu_stats（非 one_shot）才有 CPU %。
+    pub fn from_response(id: &str, s: &ContainerStatsResponse) -> Self {
+        let name = s
+            .name
+            .as_deref()
+            .map(|n| n.trim_start_matches('/').to_string())
+            .unwrap_or_default();
+
+        let total = |c: Option<&bollard::models::ContainerCpuStats>| {
+            c.and_then(|c| c.cpu_usage.as_ref())
+                .and_then(|u| u.total_usage)
+                .unwrap_or(0)
+        };
+        let system = |c: Option<&bollard::models::ContainerCpuStats>| {
+            c.and_then(|c| c.system_cpu_usage).unwrap_or(0)
+        };
+        let cpu = s.cpu_stats.as_ref();
+        let precpu = s.precpu_stats.as_ref();
+        let cpu_delta = total(cpu).saturating_sub(total(precpu)) as f64;
+        let system_delta = system(cpu).saturating_sub(system(precpu)) as f64;
+        let online_cpus = cpu
+            .and_then(|c| c.online_cpus.map(u64::from))
+            .or_else(|| {
+                cpu.and_then(|c| c.cpu_usage.as_ref())
+                    .and_then(|u| u.percpu_usage.as_ref())
+                    .map(|p| p.len() as u64)
+            })
+            .unwrap_or(1)
+            .max(1) as f64;
+        let cpu_percent = if system_delta > 0.0 && cpu_delta > 0.0 {
+            cpu_delta / system_delta * online_cpus * 100.0
+        } else {
+            0.0
+        };
+
+        let mem = s.memory_stats.as_ref();
+        let raw_usage = mem.and_then(|m| m.usage).unwrap_or(0);
+        // cgroup v1 為 total_inactive_file，v2 為 inactive_file
+        let inactive_file = mem
+            .and_then(|m| m.stats.as_ref())
+            .and_then(|st| st.get("total_inactive_file").or_else(|| st.get("inactive_file")))
+            .copied()
+            .unwrap_or(0);
+        let memory_usage = raw_usage.saturating_sub(inactive_file);
+        let memory_limit = mem.and_then(|m| m.limit).unwrap_or(0);
+        let memory_percent = if memory_limit > 0 {
+            memory_usage as f64 / memory_limit as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        let (net_rx, net_tx) = s
+            .networks
+            .as_ref()
+            .map(|nets| {
+                nets.values().fold((0u64, 0u64), |(rx, tx), n| {
+                    (rx + n.rx_bytes.unwrap_or(0), tx + n.tx_bytes.unwrap_or(0))
+                })
+            })
+            .unwrap_or((0, 0));
+
+        let (block_read, block_write) = s
+            .blkio_stats
+            .as_ref()
+            .and_then(|b| b.io_service_bytes_recursive.as_ref())
+            .map(|entries| {
+                entries.iter().fold((0u64, 0u64), |(r, w), e| {
+                    let v = e.value.unwrap_or(0);
+                    match e.op.as_deref().map(str::to_ascii_lowercase).as_deref() {
+                        Some("read") => (r + v, w),
+                        Some("write") => (r, w + v),
+                        _ => (r, w),
+                    }
+                })
+            })
+            .unwrap_or((0, 0));
+
+        let pids = s.pids_stats.as_ref().and_then(|p| p.current).unwrap_or(0);
+
+        Self {
+            id: id.to_string(),
+            name,
+            cpu_percent: (cpu_percent * 100.0).round() / 100.0,
+            memory_usage,
+            memory_limit,
+            memory_percent: (memory_percent * 100.0).round() / 100.0,
+            net_rx,
+            net_tx,
+            block_read,
+            block_write,
+            pids,
+        }
+    }
+}
+
+/// 取得容器單筆取樣（stream=false，Docker 會取兩次樣本以計算 CPU %，約需 1 秒）。
+pub async fn container_stats(
+    docker: &Docker,
+    id: &str,
+) -> Result<ContainerStats, bollard::errors::Error> {
+    let opts = StatsOptionsBuilder::default()
+        .stream(false)
+        .one_shot(false)
+        .build();
+    let mut stream = docker.stats(id, Some(opts));
+    match stream.next().await {
+        Some(Ok(s)) => Ok(ContainerStats::from_response(id, &s)),
+        Some(Err(e)) => Err(e),
+        None => Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            message: format!("no stats for container {}", id),
+        }),
+    }
+}
+
+/// 以有限並行數（INSPECT_CONCURRENCY）取得多個容器的取樣，順序與 `ids` 相同；個別失敗（例如剛停止）直接略過。
+pub async fn stats_for(docker: &Docker, ids: &[String]) -> Vec<ContainerStats> {
+    // 以 owned 的 id 與 Docker（內部為 Arc）建立 future，避免借用造成 Send 推導失敗
+    futures_util::stream::iter(ids.to_vec())
+        .map(|id| {
+            let docker = docker.clone();
+            async move { container_stats(&docker, &id).await }
+        })
+        .buffered(super::INSPECT_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `docker stats` 的原始回應（只留計算用到的欄位）。
+    fn fixture(overrides: serde_json::Value) -> ContainerStatsResponse {
+        let mut v = serde_json::json!({
+            "name": "/desk",
+            "cpu_stats": {
+                "cpu_usage": { "total_usage": 400_000_000u64, "percpu_usage": [1, 2, 3, 4] },
+                "system_cpu_usage": 20_000_000_000u64,
+                "online_cpus": 2
+            },
+            "precpu_stats": {
+                "cpu_usage": { "total_usage": 300_000_000u64 },
+                "system_cpu_usage": 19_000_000_000u64
+            },
+            "memory_stats": {
+                "usage": 300 * 1024 * 1024,
+                "limit": 1024 * 1024 * 1024,
+                "stats": { "inactive_file": 44 * 1024 * 1024 }
+            },
+            "networks": {
+                "eth0": { "rx_bytes": 1000, "tx_bytes": 200 },
+                "eth1": { "rx_bytes": 24, "tx_bytes": 6 }
+            },
+            "blkio_stats": {
+                "io_service_bytes_recursive": [
+                    { "major": 8, "minor": 0, "op": "read", "value": 4096 },
+                    { "major": 8, "minor": 0, "op": "Write", "value": 512 },
+                    { "major": 8, "minor": 16, "op": "read", "value": 1024 },
+                    { "major": 8, "minor": 0, "op": "sync", "value": 9999 }
+                ]
+            },
+            "pids_stats": { "current": 17 }
+        });
+        let (serde_json::Value::Object(base), serde_json::Value::Object(extra)) = (&mut v, overrides) else {
+            unreachable!()
+        };
+        base.extend(extra);
+        serde_json::from_value(v).unwrap()
+    }
+
+    #[test]
+    fn computes_docker_stats_values() {
+        let s = ContainerStats::from_response("abc", &fixture(serde_json::json!({})));
+        assert_eq!((s.id.as_str(), s.name.as_str()), ("abc", "desk"));
+        // 0.1s / 1s × 2 顆 CPU（online_cpus 優先於 percpu_usage 長度）
+        assert_eq!(s.cpu_percent, 20.0);
+        assert_eq!(s.memory_usage, 256 * 1024 * 1024);
+        assert_eq!(s.memory_limit, 1024 * 1024 * 1024);
+        assert_eq!(s.memory_percent, 25.0);
+        assert_eq!((s.net_rx, s.net_tx), (1024, 206));
+        assert_eq!((s.block_read, s.block_write), (5120, 512));
+        assert_eq!(s.pids, 17);
+    }
+
+    #[test]
+    fn zero_system_delta_gives_zero_cpu() {
+        let s = ContainerStats::from_response(
+            "abc",
+            &fixture(serde_json::json!({
+                "precpu_stats": {
+                    "cpu_usage": { "total_usage": 300_000_000u64 },
+                    "system_cpu_usage": 20_000_000_000u64
+                }
+            })),
+        );
+        assert_eq!(s.cpu_percent, 0.0);
+        // one_shot 回應沒有 precpu_stats 時也不會除以零
+        let s = ContainerStats::from_response("abc", &fixture(serde_json::json!({ "precpu_stats": null })));
+        assert!(s.cpu_percent.is_finite());
+    }
+
+    #[test]
+    fn cgroup_v1_cache_and_missing_sections() {
+        let s = ContainerStats::from_response(
+            "abc",
+            &fixture(serde_json::json!({
+                "memory_stats": {
+                    "usage": 100,
+                    "stats": { "total_inactive_file": 40, "inactive_file": 10 }
+                },
+                "networks": null,
+                "blkio_stats": {},
+                "pids_stats": null
+            })),
+        );
+        assert_eq!(s.memory_usage, 60);
+        assert_eq!((s.memory_limit, s.memory_percent), (0, 0.0));
+        assert_eq!((s.net_rx, s.net_tx, s.block_read, s.block_write, s.pids), (0, 0, 0, 0, 0));
+    }
+}
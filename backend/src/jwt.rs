@@ -1,7 +1,23 @@
 //! JWT 發放與驗證：access/refresh token；不處理 Google ID token。
+//!
+//! 每個 token 帶 `typ`（access / refresh / mfa），驗證時強制比對，refresh 與 MFA challenge 不能當 Bearer 使用；
+//! 另驗證 `iss` / `aud`。簽章金鑰以 `kid` 區分：以 active kid 簽發，驗證時依 header 的 kid 選擇金鑰，
+//! 因此輪替時可同時保留舊金鑰。預設 HS256（JWT_SECRET / JWT_SECRETS），
+//! 也可改用 RS256 / EdDSA（私鑰與公鑰由 PEM 檔載入）。
 
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::config::Config;
+
+/// access token 的 `typ`。
+pub const TYP_ACCESS: &str = "access";
+/// refresh token 的 `typ`。
+pub const TYP_REFRESH: &str = "refresh";
+/// MFA challenge token 的 `typ`（密碼正確、尚待第二步驗證）。
+pub const TYP_MFA: &str = "mfa";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AccessClaims {
@@ -9,6 +25,9 @@ pub struct AccessClaims {
     pub user_id: i64,
     pub exp: i64,
     pub iat: i64,
+    pub typ: String,
+    pub iss: String,
+    pub aud: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,59 +36,278 @@ pub struct RefreshClaims {
     pub user_id: i64,
     pub exp: i64,
     pub iat: i64,
+    pub typ: String,
+    pub iss: String,
+    pub aud: String,
+    /// 對應 refresh_tokens.jti（單次使用，見 `db::refresh_token`）。
+    pub jti: String,
 }
 
-/// Issue access + refresh tokens (compatible with SimpleJWT-style response).
-pub fn issue_tokens(
-    user_id: i64,
-    username: &str,
-    secret: &[u8],
-    access_ttl_secs: i64,
-    refresh_ttl_secs: i64,
-) -> Result<(String, String), jsonwebtoken::errors::Error> {
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
-    let access = encode(
-        &Header::default(),
-        &AccessClaims {
+/// 登入第一步通過後的短效 challenge；只能換取 /auth/mfa/* 的第二步，不能當 Bearer 使用。
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaClaims {
+    pub sub: String,
+    pub user_id: i64,
+    pub exp: i64,
+    pub iat: i64,
+    pub typ: String,
+    pub iss: String,
+    pub aud: String,
+    /// 使用者尚未設定 TOTP（staff 要求 MFA），須先完成設定。
+    pub enroll: bool,
+    /// 第一步的登入方式（`password` / `oidc`），供第二步的稽核紀錄。
+    #[serde(default = "default_mfa_method")]
+    pub method: String,
+}
+
+fn default_mfa_method() -> String {
+    "password".into()
+}
+
+/// 可驗證 `typ` 的 claims。
+pub trait TypedClaims {
+    fn typ(&self) -> &str;
+}
+
+impl TypedClaims for AccessClaims {
+    fn typ(&self) -> &str {
+        &self.typ
+    }
+}
+
+impl TypedClaims for RefreshClaims {
+    fn typ(&self) -> &str {
+        &self.typ
+    }
+}
+
+impl TypedClaims for MfaClaims {
+    fn typ(&self) -> &str {
+        &self.typ
+    }
+}
+
+/// 簽發與驗證用的金鑰組；啟動時由 Config 建立並存放於 AppState。
+pub struct JwtKeys {
+    algorithm: Algorithm,
+    active_kid: String,
+    encoding: EncodingKey,
+    decoding: HashMap<String, DecodingKey>,
+    issuer: String,
+    audience: String,
+}
+
+/// 解析 `kid:value,kid:value`（JWT_SECRETS、JWT_PUBLIC_KEY_FILES）；value 內可含 `:`。
+pub fn parse_kid_list(list: &str) -> Vec<(String, String)> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|item| {
+            let (kid, value) = item.split_once(':')?;
+            let (kid, value) = (kid.trim(), value.trim());
+            (!kid.is_empty() && !value.is_empty()).then(|| (kid.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("cannot read key file {}: {}", path, e))
+}
+
+impl JwtKeys {
+    /// 依設定載入金鑰：HS256 用 JWT_SECRETS（未設定時為 kid `default` 的 JWT_SECRET）；
+    /// RS256 / EdDSA 用 JWT_PRIVATE_KEY_FILE 簽發、JWT_PUBLIC_KEY_FILES 驗證。
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let algorithm: Algorithm = config
+            .jwt_algorithm
+            .parse()
+            .map_err(|_| format!("unsupported JWT_ALGORITHM {:?}", config.jwt_algorithm))?;
+        let mut decoding = HashMap::new();
+        let (active_kid, encoding) = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let secrets = config.jwt_secrets();
+                for (kid, secret) in &secrets {
+                    decoding.insert(kid.clone(), DecodingKey::from_secret(secret.as_bytes()));
+                }
+                let active_kid = config
+                    .jwt_active_kid
+                    .clone()
+                    .unwrap_or_else(|| secrets[0].0.clone());
+                let secret = secrets
+                    .iter()
+                    .find(|(kid, _)| *kid == active_kid)
+                    .map(|(_, s)| s)
+                    .ok_or_else(|| format!("JWT_ACTIVE_KID {:?} is not in JWT_SECRETS", active_kid))?;
+                (active_kid, EncodingKey::from_secret(secret.as_bytes()))
+            }
+            Algorithm::RS256 | Algorithm::EdDSA => {
+                let private_path = config
+                    .jwt_private_key_file
+                    .as_deref()
+                    .ok_or("JWT_PRIVATE_KEY_FILE is required for asymmetric JWT_ALGORITHM")?;
+                let private = read_pem(private_path)?;
+                let encoding = if algorithm == Algorithm::RS256 {
+                    EncodingKey::from_rsa_pem(&private)
+                } else {
+                    EncodingKey::from_ed_pem(&private)
+                }
+                .map_err(|e| format!("invalid private key {}: {}", private_path, e))?;
+                let public_files = parse_kid_list(&config.jwt_public_key_files);
+                if public_files.is_empty() {
+                    return Err("JWT_PUBLIC_KEY_FILES is required for asymmetric JWT_ALGORITHM".into());
+                }
+                for (kid, path) in &public_files {
+                    let pem = read_pem(path)?;
+                    let key = if algorithm == Algorithm::RS256 {
+                        DecodingKey::from_rsa_pem(&pem)
+                    } else {
+                        DecodingKey::from_ed_pem(&pem)
+                    }
+                    .map_err(|e| format!("invalid public key {}: {}", path, e))?;
+                    decoding.insert(kid.clone(), key);
+                }
+                let active_kid = config
+                    .jwt_active_kid
+                    .clone()
+                    .unwrap_or_else(|| public_files[0].0.clone());
+                if !decoding.contains_key(&active_kid) {
+                    return Err(format!(
+                        "JWT_ACTIVE_KID {:?} has no public key in JWT_PUBLIC_KEY_FILES",
+                        active_kid
+                    ));
+                }
+                (active_kid, encoding)
+            }
+            other => return Err(format!("unsupported JWT_ALGORITHM {:?}", other)),
+        };
+        Ok(Self {
+            algorithm,
+            active_kid,
+            encoding,
+            decoding,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+        })
+    }
+
+    fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.active_kid.clone());
+        header
+    }
+
+    /// 以 active kid 簽發任意 claims。
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        encode(&self.header(), claims, &self.encoding)
+    }
+
+    /// 依 header 的 kid 選擇金鑰驗證簽章、exp、iss、aud，並要求 `typ` 相符。
+    pub fn verify<T: DeserializeOwned + TypedClaims>(
+        &self,
+        token: &str,
+        typ: &str,
+    ) -> Result<T, jsonwebtoken::errors::Error> {
+        use jsonwebtoken::errors::ErrorKind;
+        let header = decode_header(token)?;
+        let kid = header.kid.unwrap_or_else(|| self.active_kid.clone());
+        let key = self
+            .decoding
+            .get(&kid)
+            .ok_or(jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken))?;
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        let claims = decode::<T>(token, key, &validation)?.claims;
+        if claims.typ() != typ {
+            return Err(ErrorKind::InvalidToken.into());
+        }
+        Ok(claims)
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn audience(&self) -> &str {
+        &self.audience
+    }
+
+    /// Issue access + refresh tokens (compatible with SimpleJWT-style response).
+    /// `refresh_jti` 由呼叫端產生並寫入 refresh_tokens。
+    pub fn issue_tokens(
+        &self,
+        user_id: i64,
+        username: &str,
+        access_ttl_secs: i64,
+        refresh_ttl_secs: i64,
+        refresh_jti: &str,
+    ) -> Result<(String, String), jsonwebtoken::errors::Error> {
+        let now = now();
+        let access = self.sign(&AccessClaims {
             sub: username.to_string(),
             user_id,
             exp: now + access_ttl_secs,
             iat: now,
-        },
-        &EncodingKey::from_secret(secret),
-    )?;
-    let refresh = encode(
-        &Header::default(),
-        &RefreshClaims {
+            typ: TYP_ACCESS.to_string(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+        })?;
+        let refresh = self.sign(&RefreshClaims {
             sub: username.to_string(),
             user_id,
             exp: now + refresh_ttl_secs,
             iat: now,
-        },
-        &EncodingKey::from_secret(secret),
-    )?;
-    Ok((access, refresh))
-}
+            typ: TYP_REFRESH.to_string(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            jti: refresh_jti.to_string(),
+        })?;
+        Ok((access, refresh))
+    }
+
+    /// 簽發 MFA challenge（typ = mfa）。
+    pub fn issue_mfa_challenge(
+        &self,
+        user_id: i64,
+        username: &str,
+        enroll: bool,
+        method: &str,
+        ttl_secs: i64,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = now();
+        self.sign(&MfaClaims {
+            sub: username.to_string(),
+            user_id,
+            exp: now + ttl_secs,
+            iat: now,
+            typ: TYP_MFA.to_string(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            enroll,
+            method: method.to_string(),
+        })
+    }
+
+    /// Verify MFA challenge (typ = mfa).
+    pub fn verify_mfa(&self, token: &str) -> Result<MfaClaims, jsonwebtoken::errors::Error> {
+        self.verify(token, TYP_MFA)
+    }
+
+    /// Verify access token (typ = access).
+    pub fn verify_access(&self, token: &str) -> Result<AccessClaims, jsonwebtoken::errors::Error> {
+        self.verify(token, TYP_ACCESS)
+    }
 
-/// Verify access token and return user_id.
-pub fn verify_access(token: &str, secret: &[u8]) -> Result<AccessClaims, jsonwebtoken::errors::Error> {
-    let d = decode::<AccessClaims>(
-        token,
-        &DecodingKey::from_secret(secret),
-        &Validation::default(),
-    )?;
-    Ok(d.claims)
+    /// Verify refresh token (typ = refresh).
+    pub fn verify_refresh(&self, token: &str) -> Result<RefreshClaims, jsonwebtoken::errors::Error> {
+        self.verify(token, TYP_REFRESH)
+    }
 }
 
-/// Verify refresh token.
-pub fn verify_refresh(token: &str, secret: &[u8]) -> Result<RefreshClaims, jsonwebtoken::errors::Error> {
-    let d = decode::<RefreshClaims>(
-        token,
-        &DecodingKey::from_secret(secret),
-        &Validation::default(),
-    )?;
-    Ok(d.claims)
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
 }
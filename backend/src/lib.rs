@@ -2,14 +2,21 @@
 //!
 //! 職責：從環境載入設定、建立 DB 連線與 migrations、Docker 連線、Redis 佇列 worker、
 //! 廣播 channel，組裝 Axum router（/health、/api/*、WebSocket）並啟動 HTTP server。
-//! 本專案不使用 Google 登入，僅 JWT。
+//! 本專案不使用 Google 登入：本機帳號以 JWT 登入，另可設定通用的 OIDC 登入。
 
 pub mod api;
+pub mod audit;
 pub mod auth_extractor;
+pub mod authenticator;
 pub mod config;
 pub mod docker;
 pub mod jwt;
+pub mod mfa;
+pub mod oidc;
+pub mod permissions;
 pub mod queue;
+pub mod rate_limit;
+pub mod recording;
 pub mod ws;
 
 pub mod db;
@@ -83,6 +90,20 @@ pub struct AppState {
     pub docker: bollard::Docker,
     /// Broadcasts notification messages to WebSocket clients.
     pub notify_tx: tokio::sync::broadcast::Sender<String>,
+    /// GPU 清單（設定或 nvidia-smi），與 worker 共用。
+    pub gpus: std::sync::Arc<dyn docker::gpu::GpuInventory>,
+    /// NVIDIA 能力探測結果快取（背景刷新）。
+    pub nvidia: std::sync::Arc<docker::nvidia::NvidiaProbe>,
+    /// 由 Docker events 維護的容器狀態/埠索引。
+    pub containers: std::sync::Arc<docker::index::ContainerIndex>,
+    /// 各端點的 per-user 限流。
+    pub limits: std::sync::Arc<rate_limit::Limits>,
+    /// JWT 簽發/驗證金鑰（kid 輪替、HS256 或 RS256/EdDSA）。
+    pub jwt: std::sync::Arc<jwt::JwtKeys>,
+    /// /auth/token 的帳密驗證後端（AUTH_BACKEND：本機或 LDAP）。
+    pub authenticator: std::sync::Arc<dyn authenticator::Authenticator>,
+    /// OIDC 登入（未設定 OIDC_ISSUER 時為 None）。
+    pub oidc: Option<std::sync::Arc<oidc::OidcClient>>,
 }
 
 /// 從環境變數載入設定、初始化 DB/migrations、Docker、Redis worker，組裝路由並啟動 HTTP server。
@@ -96,6 +117,17 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .init();
 
     let config = Config::from_env();
+    config
+        .validate()
+        .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { Box::from(e) })?;
+    if config.dev_mode {
+        tracing::warn!("DEV_MODE is on: JWT secret checks are disabled");
+    }
+    let jwt_keys = jwt::JwtKeys::from_config(&config)
+        .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { Box::from(e) })?;
+    let oidc = oidc::OidcClient::from_config(&config)
+        .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> { Box::from(e) })?
+        .map(std::sync::Arc::new);
     let pool = create_sqlite_pool(&config.database_url).await?;
     let migrations: std::path::PathBuf =
         std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
@@ -108,18 +140,59 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         Box::from(e.to_string())
     })?;
     let (notify_tx, _) = tokio::sync::broadcast::channel::<String>(64);
+    let gpus = docker::gpu::inventory_from_config(&config, &docker);
+    let nvidia = std::sync::Arc::new(docker::nvidia::NvidiaProbe::new(
+        docker.clone(),
+        &config.nvidia_probe_image,
+        std::time::Duration::from_secs(config.nvidia_probe_ttl_secs.max(1)),
+    ));
+    tokio::spawn(nvidia.clone().run_refresher());
+    let containers = std::sync::Arc::new(docker::index::ContainerIndex::new());
+    tokio::spawn(containers.clone().run(docker.clone(), notify_tx.clone()));
         let app_state = AppState {
             config: config.clone(),
             pool,
             docker,
             notify_tx: notify_tx.clone(),
+            gpus: gpus.clone(),
+            nvidia,
+            containers,
+            limits: std::sync::Arc::new(rate_limit::Limits::default()),
+            jwt: std::sync::Arc::new(jwt_keys),
+            authenticator: authenticator::from_config(&config),
+            oidc,
         };
         let redis_url = config.redis_url.clone();
         let docker_network = config.docker_network.clone();
+        let worker_pool = app_state.pool.clone();
+        let bind_addrs = config.port_bind_addrs.clone();
         tokio::spawn(async move {
-            queue::run_worker(redis_url, docker_network, notify_tx).await;
+            queue::run_worker(redis_url, docker_network, notify_tx, gpus, worker_pool, bind_addrs)
+                .await;
         });
-        let app = router()
+        tokio::spawn(db::port_reservation::run_gc(
+            app_state.pool.clone(),
+            std::time::Duration::from_secs(60),
+        ));
+        // 預先算好假雜湊，第一次以不存在的帳號登入時不會比較慢
+        tokio::task::spawn_blocking(|| db::user::verify_dummy_password(""));
+        tokio::spawn(db::login_lockout::run_prune(
+            app_state.pool.clone(),
+            std::time::Duration::from_secs(3600),
+        ));
+        tokio::spawn(db::refresh_token::run_prune(
+            app_state.pool.clone(),
+            std::time::Duration::from_secs(3600),
+        ));
+        recording::close_unfinished(&config, &app_state.pool).await;
+        if config.console_recording_retention_days > 0 {
+            tokio::spawn(recording::run_retention(
+                config.clone(),
+                app_state.pool.clone(),
+                std::time::Duration::from_secs(3600),
+            ));
+        }
+        let app = router(&config)
             .layer(
                 CorsLayer::new()
                     .allow_origin(Any)
@@ -134,7 +207,7 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     tracing::info!("Rust API listening on {}", addr);
     axum::serve(
         tokio::net::TcpListener::bind(addr).await?,
-        app,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
     )
     .await?;
     Ok(())
@@ -147,6 +220,7 @@ pub async fn create_user_cli(
     password: String,
     email: Option<String>,
     is_staff: bool,
+    role: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenvy::dotenv().ok();
     let config = Config::from_env();
@@ -157,19 +231,23 @@ pub async fn create_user_cli(
         .await?
         .run(&pool)
         .await?;
+    let role = match role {
+        Some(r) => permissions::Role::parse(&r).ok_or_else(|| format!("unknown role {:?}", r))?,
+        None => permissions::Role::default_for(is_staff),
+    };
     let hash = db::user::hash_password(&password)
         .map_err(|e| format!("hash_password failed: {}", e))?;
     let email_ref = email.as_deref();
-    let id = db::user::create_user(&pool, &username, &hash, email_ref, is_staff).await?;
-    println!("User created: id={} username={}", id, username);
+    let id = db::user::create_user(&pool, &username, &hash, email_ref, role).await?;
+    println!("User created: id={} username={} role={}", id, username, role.as_str());
     Ok(())
 }
 
 /// 組裝所有路由：/health、/api/*、/dashboard/api/*（同一 REST API）、WebSocket（/ws/console、/ws/notifications）。
-fn router() -> Router<AppState> {
+fn router(config: &Config) -> Router<AppState> {
     Router::new()
         .route("/health", axum::routing::get(|| async { "ok" }))
-        .nest("/api", api::router())
-        .nest("/dashboard/api", api::router())
+        .nest("/api", api::router(config))
+        .nest("/dashboard/api", api::router(config))
         .merge(ws::router())
 }
@@ -0,0 +1,88 @@
+//! TOTP（RFC 6238：SHA-1、6 位數、30 秒，與 Google Authenticator 等 App 相容）與復原碼的產生、驗證。
+//! 儲存見 `db::mfa`；登入流程與 API 見 `api::mfa`。
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// 一個 TOTP 時間步的秒數。
+pub const STEP_SECS: u64 = 30;
+/// 啟用或重新產生時給的復原碼數量。
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// 新的 TOTP secret（20 bytes，base32）。
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    match Secret::Raw(bytes.to_vec()).to_encoded() {
+        Secret::Encoded(s) => s,
+        Secret::Raw(_) => unreachable!("to_encoded returns Secret::Encoded"),
+    }
+}
+
+/// issuer / 帳號名稱不可含 `:`（otpauth URI 的分隔字元）。
+fn totp(secret: &str, issuer: &str, account: &str) -> Result<TOTP, String> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| format!("invalid TOTP secret: {:?}", e))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP_SECS,
+        bytes,
+        Some(issuer.replace(':', "_")),
+        account.replace(':', "_"),
+    )
+    .map_err(|e| format!("invalid TOTP parameters: {}", e))
+}
+
+/// 給驗證器 App 掃描的 `otpauth://totp/...` URI（前端轉成 QR code）。
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> Result<String, String> {
+    Ok(totp(secret, issuer, account)?.get_url())
+}
+
+/// 比對驗證碼，容許前後各一個時間步的時鐘誤差；只接受晚於 `last_used_step` 的時間步（防重放）。
+/// 符合時回傳該時間步。
+pub fn verify_code(secret: &str, code: &str, last_used_step: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != 6 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let totp = totp(secret, "x", "x").ok()?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    let current = (now / STEP_SECS) as i64;
+    (current - 1..=current + 1)
+        .filter(|step| *step > last_used_step)
+        .find(|step| totp.check(code, *step as u64 * STEP_SECS))
+}
+
+/// 產生一組復原碼（`xxxxx-xxxxx`，小寫英數），明文只回給使用者一次。
+pub fn generate_recovery_codes() -> Vec<String> {
+    // 32 個字元（去掉易混淆的 i、l、o、1），每個 byte 取模不會偏差
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz023456789";
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 10];
+            OsRng.fill_bytes(&mut bytes);
+            let chars: String = bytes
+                .iter()
+                .map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char)
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// 復原碼的 SHA-256（忽略大小寫、空白與 `-`）。
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
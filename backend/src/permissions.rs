@@ -0,0 +1,307 @@
+//! 角色與權限：每個使用者有一個角色（admin / operator / user / viewer），角色決定可用的權限。
+//! handler 以 `auth_extractor::Require<P>` 宣告需要的權限；要看請求內容才知道的權限
+//! （例如 `privileged: true` 需要 `containers.privileged`）以 `AuthUser::require` 檢查。
+//! 原本的 staff 權限（存取所有人的容器、稽核紀錄、登入鎖定與 MFA 政策等）也是權限，只有 admin 擁有；
+//! `User::is_staff` 由角色推導，不另外授權。
+//! 個人 API token 另有 scope（`Scope`），只能使用 scope 涵蓋的端點，且仍受角色權限限制。
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// 全部權限。
+    Admin,
+    /// 建立容器（含 privileged / GPU）、管理映像、console。
+    Operator,
+    /// 建立一般容器、console。
+    User,
+    /// 唯讀。
+    Viewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Permission {
+    #[serde(rename = "containers.create")]
+    ContainersCreate,
+    #[serde(rename = "containers.privileged")]
+    ContainersPrivileged,
+    #[serde(rename = "containers.gpu")]
+    ContainersGpu,
+    #[serde(rename = "images.manage")]
+    ImagesManage,
+    #[serde(rename = "console.attach")]
+    ConsoleAttach,
+    #[serde(rename = "users.manage")]
+    UsersManage,
+    /// 存取所有人的容器（含 logs、stats、console）。
+    #[serde(rename = "containers.any")]
+    ContainersAny,
+    /// 不受 MAX_CONTAINERS_PER_USER 限制。
+    #[serde(rename = "containers.unlimited")]
+    ContainersUnlimited,
+    /// 稽核紀錄與所有人的 console 錄影。
+    #[serde(rename = "audit.read")]
+    AuditRead,
+    /// 刪除 console 錄影。
+    #[serde(rename = "audit.manage")]
+    AuditManage,
+    /// 登入鎖定與 MFA 政策。
+    #[serde(rename = "auth.manage")]
+    AuthManage,
+    /// 主機層級操作（強制 NVIDIA 偵測）。
+    #[serde(rename = "system.manage")]
+    SystemManage,
+}
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::ContainersCreate => "containers.create",
+            Permission::ContainersPrivileged => "containers.privileged",
+            Permission::ContainersGpu => "containers.gpu",
+            Permission::ImagesManage => "images.manage",
+            Permission::ConsoleAttach => "console.attach",
+            Permission::UsersManage => "users.manage",
+            Permission::ContainersAny => "containers.any",
+            Permission::ContainersUnlimited => "containers.unlimited",
+            Permission::AuditRead => "audit.read",
+            Permission::AuditManage => "audit.manage",
+            Permission::AuthManage => "auth.manage",
+            Permission::SystemManage => "system.manage",
+        }
+    }
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+            Role::User => "user",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Role> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "admin" => Some(Role::Admin),
+            "operator" => Some(Role::Operator),
+            "user" => Some(Role::User),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+
+    /// 建立使用者未指定角色時的預設：staff 為 admin，其餘為 user。
+    pub fn default_for(is_staff: bool) -> Role {
+        if is_staff {
+            Role::Admin
+        } else {
+            Role::User
+        }
+    }
+
+    pub fn permissions(self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            Role::Admin => &[
+                ContainersCreate,
+                ContainersPrivileged,
+                ContainersGpu,
+                ImagesManage,
+                ConsoleAttach,
+                UsersManage,
+                ContainersAny,
+                ContainersUnlimited,
+                AuditRead,
+                AuditManage,
+                AuthManage,
+                SystemManage,
+            ],
+            Role::Operator => &[
+                ContainersCreate,
+                ContainersPrivileged,
+                ContainersGpu,
+                ImagesManage,
+                ConsoleAttach,
+            ],
+            Role::User => &[ContainersCreate, ConsoleAttach],
+            Role::Viewer => &[],
+        }
+    }
+
+    pub fn has(self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+/// 個人 API token 的 scope。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// 讀取（GET）端點與唯讀 WebSocket（logs、stats）。
+    #[serde(rename = "read")]
+    Read,
+    /// 建立、啟動/停止/重啟/刪除、匯出/匯入容器。
+    #[serde(rename = "containers:control")]
+    ContainersControl,
+    /// console 元資料與 /ws/console。
+    #[serde(rename = "console")]
+    Console,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::ContainersControl => "containers:control",
+            Scope::Console => "console",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Scope> {
+        match s {
+            "read" => Some(Scope::Read),
+            "containers:control" => Some(Scope::ContainersControl),
+            "console" => Some(Scope::Console),
+            _ => None,
+        }
+    }
+
+    /// API token 呼叫 REST 端點時需要的 scope（`path` 不含 /api 前綴）；None 表示 API token 不能使用
+    /// （登入/session、使用者管理、token 管理與其他寫入操作只接受 JWT）。
+    pub fn for_request(method: &axum::http::Method, path: &str) -> Option<Scope> {
+        use axum::http::Method;
+        if path.starts_with("/console/") {
+            return Some(Scope::Console);
+        }
+        if path.starts_with("/auth/") || path.starts_with("/users") || path.starts_with("/me/") {
+            return None;
+        }
+        let control = ["/container/new", "/containers/control", "/containers/export", "/containers/import"];
+        if *method != Method::GET && control.iter().any(|p| path.starts_with(p)) {
+            return Some(Scope::ContainersControl);
+        }
+        (*method == Method::GET || *method == Method::HEAD).then_some(Scope::Read)
+    }
+}
+
+/// `Require<P>` 用的型別層級權限標記。
+pub trait PermissionMarker: Send + Sync + 'static {
+    const PERMISSION: Permission;
+}
+
+/// 權限標記型別（`Require<perm::ContainersCreate>` 等）。
+pub mod perm {
+    use super::{Permission, PermissionMarker};
+
+    macro_rules! markers {
+        ($($name:ident),* $(,)?) => {
+            $(
+                pub struct $name;
+                impl PermissionMarker for $name {
+                    const PERMISSION: Permission = Permission::$name;
+                }
+            )*
+        };
+    }
+
+    markers!(
+        ContainersCreate,
+        ContainersPrivileged,
+        ContainersGpu,
+        ImagesManage,
+        ConsoleAttach,
+        UsersManage,
+        ContainersAny,
+        ContainersUnlimited,
+        AuditRead,
+        AuditManage,
+        AuthManage,
+        SystemManage,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+
+    #[test]
+    fn role_permissions() {
+        let all = Role::Admin.permissions();
+        for p in [
+            Permission::UsersManage,
+            Permission::ContainersAny,
+            Permission::ContainersUnlimited,
+            Permission::AuditRead,
+            Permission::AuditManage,
+            Permission::AuthManage,
+            Permission::SystemManage,
+        ] {
+            assert!(Role::Admin.has(p), "{}", p.as_str());
+            for role in [Role::Operator, Role::User, Role::Viewer] {
+                assert!(!role.has(p), "{} has {}", role.as_str(), p.as_str());
+            }
+        }
+        for p in Role::Operator.permissions() {
+            assert!(all.contains(p));
+        }
+        assert!(Role::Operator.has(Permission::ContainersPrivileged) && Role::Operator.has(Permission::ImagesManage));
+        assert_eq!(Role::User.permissions(), &[Permission::ContainersCreate, Permission::ConsoleAttach]);
+        assert!(Role::Viewer.permissions().is_empty());
+        assert_eq!(Role::default_for(true), Role::Admin);
+        assert_eq!(Role::default_for(false), Role::User);
+    }
+
+    #[test]
+    fn permission_names_round_trip() {
+        for p in Role::Admin.permissions() {
+            let json = serde_json::to_value(p).unwrap();
+            assert_eq!(json, p.as_str());
+            assert_eq!(serde_json::from_value::<Permission>(json).unwrap(), *p);
+        }
+        assert_eq!(Role::parse(" Operator "), Some(Role::Operator));
+        assert_eq!(Role::parse("staff"), None);
+    }
+
+    #[test]
+    fn scope_for_request() {
+        let cases = [
+            (Method::GET, "/containers", Some(Scope::Read)),
+            (Method::HEAD, "/containers", Some(Scope::Read)),
+            (Method::GET, "/containers/abc/logs", Some(Scope::Read)),
+            (Method::GET, "/containers/export/abc", Some(Scope::Read)),
+            (Method::POST, "/container/new", Some(Scope::ContainersControl)),
+            (Method::POST, "/containers/control", Some(Scope::ContainersControl)),
+            (Method::POST, "/containers/export", Some(Scope::ContainersControl)),
+            (Method::DELETE, "/containers/export/abc", Some(Scope::ContainersControl)),
+            (Method::POST, "/containers/import", Some(Scope::ContainersControl)),
+            (Method::GET, "/console/abc", Some(Scope::Console)),
+            (Method::POST, "/console/abc", Some(Scope::Console)),
+            // 其他寫入只接受 JWT
+            (Method::POST, "/ports/check", None),
+            (Method::PATCH, "/containers", None),
+            (Method::DELETE, "/recordings/abc", None),
+            // 登入/session、自助帳號與使用者管理不論方法都不接受 API token
+            (Method::GET, "/auth/lockouts", None),
+            (Method::POST, "/auth/token/refresh", None),
+            (Method::GET, "/me/tokens", None),
+            (Method::POST, "/me/password", None),
+            (Method::GET, "/users", None),
+            (Method::GET, "/users/1", None),
+        ];
+        for (method, path, expected) in cases {
+            assert_eq!(Scope::for_request(&method, path), expected, "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn scope_names_round_trip() {
+        for scope in [Scope::Read, Scope::ContainersControl, Scope::Console] {
+            assert_eq!(Scope::parse(scope.as_str()), Some(scope));
+            assert_eq!(serde_json::to_value(scope).unwrap(), scope.as_str());
+        }
+        assert_eq!(Scope::parse("admin"), None);
+    }
+}
@@ -2,19 +2,28 @@
 //! 完成後透過 broadcast 發送通知給 WebSocket 客戶端。
 
 use super::{EnqueuedJob, Job, QUEUE_KEY};
-use bollard::models::{ContainerCreateBody, HostConfig, PortBinding};
+use bollard::models::{ContainerCreateBody, HostConfig};
 use bollard::query_parameters::{
-    CreateContainerOptions, RemoveContainerOptions, StopContainerOptions,
+    CommitContainerOptions, CreateContainerOptions, RemoveContainerOptions, RemoveContainerOptionsBuilder,
+    RemoveImageOptionsBuilder, RenameContainerOptionsBuilder, StopContainerOptions,
 };
 use bollard::Docker;
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::db::port_reservation;
+use crate::docker::gpu::{self, GpuInventory, GpuRequest};
+
 /// 常駐迴圈：連 Redis 與 Docker，BRPOP 取 job、執行 run_job、將結果經 notify_tx 廣播。
 pub async fn run_worker(
     redis_url: String,
     docker_network: String,
     notify_tx: tokio::sync::broadcast::Sender<String>,
+    gpus: Arc<dyn GpuInventory>,
+    pool: sqlx::SqlitePool,
+    bind_addrs: Vec<IpAddr>,
 ) {
     let docker = match crate::docker::connect() {
         Ok(d) => d,
@@ -53,7 +62,12 @@ pub async fn run_worker(
             }
         };
         let task_id = enqueued.task_id;
-        match run_job(&docker, &docker_network, enqueued.job).await {
+        let reserved_port = enqueued.job.reserved_port();
+        let result = run_job(&docker, &docker_network, gpus.as_ref(), &bind_addrs, enqueued.job).await;
+        if let Some(port) = reserved_port {
+            settle_reservation(&pool, port, &task_id, result.is_ok()).await;
+        }
+        match result {
             Ok((action, details)) => {
                 let msg =
                     serde_json::json!({ "message": { "action": action, "details": details } });
@@ -66,9 +80,23 @@ pub async fn run_worker(
     }
 }
 
+/// 建立容器後確認 port 保留，失敗則釋放（見 `db::port_reservation`）。
+async fn settle_reservation(pool: &sqlx::SqlitePool, port: u16, task_id: &str, created: bool) {
+    let res = if created {
+        port_reservation::confirm(pool, port, task_id).await
+    } else {
+        port_reservation::release(pool, port, task_id).await
+    };
+    if let Err(e) = res {
+        tracing::warn!("Worker: port reservation {} for job {}: {}", port, task_id, e);
+    }
+}
+
 async fn run_job(
     docker: &Docker,
     _docker_network: &str,
+    gpus: &dyn GpuInventory,
+    bind_addrs: &[IpAddr],
     job: Job,
 ) -> Result<(String, String), String> {
     match job {
@@ -82,21 +110,69 @@ async fn run_job(
             root_password,
             privileged,
             nvdocker,
+            gpus: gpu_request,
             docker_network: net,
-        } => run_image(
-            docker,
-            &net,
-            &image_name,
+            owner_id,
+        } => {
+            let env = vec![
+                format!("VNC_PW={}", vnc_password),
+                "VNC_RESOLUTION=1600x900".to_string(),
+                format!("DEFAULT_USER={}", user),
+                format!("DEFAULT_USER_PASSWORD={}", password),
+                format!("ROOT_PASSWORD={}", root_password),
+            ];
+            run_image(
+                docker,
+                &net,
+                &image_name,
+                ssh_port,
+                &name,
+                Some(env),
+                privileged,
+                nvdocker.then_some((gpus, &gpu_request)),
+                bind_addrs,
+                owner_id,
+            )
+            .await
+        }
+        Job::ExportContainer { id, image, owner_id } => {
+            run_export(docker, &id, &image, owner_id).await
+        }
+        Job::RestoreImage {
+            image_name,
             ssh_port,
-            &name,
-            &user,
-            &password,
-            &vnc_password,
-            &root_password,
+            name,
             privileged,
             nvdocker,
-        )
-        .await,
+            gpus: gpu_request,
+            docker_network: net,
+            owner_id,
+        } => {
+            // env 為 None：沿用匯出時 commit 進映像的帳密設定
+            let result = run_image(
+                docker,
+                &net,
+                &image_name,
+                ssh_port,
+                &name,
+                None,
+                privileged,
+                nvdocker.then_some((gpus, &gpu_request)),
+                bind_addrs,
+                owner_id,
+            )
+            .await;
+            // 還原失敗時不留下匯入的 tag
+            if result.is_err() {
+                remove_image_quietly(docker, &image_name).await;
+            }
+            result
+        }
+        Job::TransferContainer {
+            id,
+            docker_network: net,
+            owner_id,
+        } => run_transfer(docker, &net, bind_addrs, &id, owner_id).await,
         Job::StartContainer { id } => run_start(docker, &id).await,
         Job::StopContainer { id } => run_stop(docker, &id).await,
         Job::RemoveContainer { id } => run_remove(docker, &id).await,
@@ -104,32 +180,11 @@ async fn run_job(
     }
 }
 
-async fn run_image(
-    docker: &Docker,
-    docker_network: &str,
-    image_name: &str,
-    ssh_port: u16,
-    name: &str,
-    user: &str,
-    password: &str,
-    vnc_password: &str,
-    root_password: &str,
-    privileged: bool,
-    nvdocker: bool,
-) -> Result<(String, String), String> {
-    let mut port_bindings = HashMap::new();
-    port_bindings.insert(
-        "22/tcp".to_string(),
-        Some(vec![PortBinding {
-            host_ip: None,
-            host_port: Some(ssh_port.to_string()),
-        }]),
-    );
-    let mut binds = Vec::new();
-    if crate::docker::is_linux() {
-        binds.push("/etc/localtime:/etc/localtime:ro".to_string());
-    }
+/// 管理器套用在每個容器上的 label：traefik NoVNC 路由，以及擁有者/名稱（見 `docker::LABEL_OWNER`）。
+fn manager_labels(name: &str, docker_network: &str, owner_id: i64) -> HashMap<String, String> {
     let mut labels = HashMap::new();
+    labels.insert(crate::docker::LABEL_OWNER.to_string(), owner_id.to_string());
+    labels.insert(crate::docker::LABEL_NAME.to_string(), name.to_string());
     labels.insert("traefik.enable".to_string(), "true".to_string());
     labels.insert(
         format!("traefik.http.routers.d-gui-{}.rule", name),
@@ -151,26 +206,96 @@ async fn run_image(
         format!("d-gui-{}-strip-prefix", name),
     );
     labels.insert("traefik.docker.network".to_string(), docker_network.to_string());
+    labels
+}
+
+/// 依 GPU 清單與目前佔用選出 device_ids（worker 依序處理 job，因此不會同時配出同一張卡）。
+async fn assign_gpus(
+    docker: &Docker,
+    inventory: &dyn GpuInventory,
+    req: &GpuRequest,
+) -> Result<Vec<String>, String> {
+    let devices = inventory.devices().await?;
+    let held = gpu::held_devices(docker).await.map_err(|e| e.to_string())?;
+    gpu::select_devices(&devices, &held, req)
+}
+
+/// 建立並啟動容器；`env` 為 None 時沿用映像內的環境變數（匯入還原時使用）。
+/// `gpus` 為 Some 時（nvdocker）依請求配置指定的 GPU。
+#[allow(clippy::too_many_arguments)]
+async fn run_image(
+    docker: &Docker,
+    docker_network: &str,
+    image_name: &str,
+    ssh_port: u16,
+    name: &str,
+    env: Option<Vec<String>>,
+    privileged: bool,
+    gpus: Option<(&dyn GpuInventory, &GpuRequest)>,
+    bind_addrs: &[IpAddr],
+    owner_id: i64,
+) -> Result<(String, String), String> {
+    let device_ids = match gpus {
+        Some((inventory, req)) => Some(assign_gpus(docker, inventory, req).await?),
+        None => None,
+    };
+    let id = create_from_image(
+        docker,
+        docker_network,
+        image_name,
+        ssh_port,
+        name,
+        env,
+        privileged,
+        device_ids,
+        bind_addrs,
+        owner_id,
+    )
+    .await?;
+    docker
+        .start_container(&id, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let msg = format!("Container [{}] ({}) has been created", name, image_name);
+    Ok(("CREATED".to_string(), msg))
+}
+
+/// 依映像建立容器（不啟動）並接上 docker_network，回傳容器 ID。`device_ids` 為已配置好的 GPU。
+#[allow(clippy::too_many_arguments)]
+async fn create_from_image(
+    docker: &Docker,
+    docker_network: &str,
+    image_name: &str,
+    ssh_port: u16,
+    name: &str,
+    env: Option<Vec<String>>,
+    privileged: bool,
+    device_ids: Option<Vec<String>>,
+    bind_addrs: &[IpAddr],
+    owner_id: i64,
+) -> Result<String, String> {
+    let mut port_bindings = HashMap::new();
+    port_bindings.insert(
+        "22/tcp".to_string(),
+        Some(crate::docker::ports::port_bindings_for(bind_addrs, ssh_port)),
+    );
+    let mut binds = Vec::new();
+    if crate::docker::is_linux() {
+        binds.push("/etc/localtime:/etc/localtime:ro".to_string());
+    }
+    let labels = manager_labels(name, docker_network, owner_id);
 
     let mut device_requests = Vec::new();
-    if nvdocker {
+    if let Some(device_ids) = device_ids {
         device_requests.push(bollard::models::DeviceRequest {
             driver: Some("nvidia".to_string()),
-            count: Some(-1),
-            device_ids: None,
+            count: None,
+            device_ids: Some(device_ids),
             capabilities: Some(vec![vec!["gpu".to_string()]]),
             options: None,
         });
     }
 
-    let env = vec![
-        format!("VNC_PW={}", vnc_password),
-        "VNC_RESOLUTION=1600x900".to_string(),
-        format!("DEFAULT_USER={}", user),
-        format!("DEFAULT_USER_PASSWORD={}", password),
-        format!("ROOT_PASSWORD={}", root_password),
-    ];
-
     let host_config = HostConfig {
         port_bindings: Some(port_bindings),
         binds: if binds.is_empty() {
@@ -190,7 +315,7 @@ async fn run_image(
     let config = ContainerCreateBody {
         image: Some(image_name.to_string()),
         host_config: Some(host_config),
-        env: Some(env),
+        env,
         labels: Some(labels),
         ..Default::default()
     };
@@ -203,15 +328,11 @@ async fn run_image(
         .create_container(Some(opts), config)
         .await
         .map_err(|e| e.to_string())?;
-    let id = create.id.as_str();
-    docker
-        .start_container(id, None)
-        .await
-        .map_err(|e| e.to_string())?;
+    let id = create.id;
 
     // Connect container to network (bollard 0.20: NetworkConnectRequest in models)
     let connect_body = bollard::models::NetworkConnectRequest {
-        container: id.to_string(),
+        container: id.clone(),
         endpoint_config: None,
     };
     if let Err(e) = docker
@@ -225,9 +346,173 @@ async fn run_image(
             e
         );
     }
+    Ok(id)
+}
 
-    let msg = format!("Container [{}] ({}) has been created", name, image_name);
-    Ok(("CREATED".to_string(), msg))
+/// 將容器 commit 成匯出用映像（暫停容器以取得一致的檔案系統），擁有者寫入映像 label。
+async fn run_export(
+    docker: &Docker,
+    id: &str,
+    image: &str,
+    owner_id: i64,
+) -> Result<(String, String), String> {
+    let inspect = docker.inspect_container(id, None).await.map_err(|e| e.to_string())?;
+    let name = inspect.name.as_deref().unwrap_or(id).trim_start_matches('/').to_string();
+    let (repo, tag) = image.rsplit_once(':').unwrap_or((image, "latest"));
+    let opts = CommitContainerOptions {
+        container: Some(id.to_string()),
+        repo: Some(repo.to_string()),
+        tag: Some(tag.to_string()),
+        comment: Some(format!("dev-dock-manager export of [{}]", name)),
+        pause: true,
+        ..Default::default()
+    };
+    let mut labels = HashMap::new();
+    labels.insert(crate::docker::LABEL_OWNER.to_string(), owner_id.to_string());
+    labels.insert(crate::docker::LABEL_NAME.to_string(), name.clone());
+    let config = bollard::models::ContainerConfig {
+        labels: Some(labels),
+        ..Default::default()
+    };
+    docker
+        .commit_container(opts, config)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((
+        "EXPORTED".to_string(),
+        format!("Container [{}] has been exported as [{}]", name, image),
+    ))
+}
+
+/// 移轉容器擁有者：commit 成 `dev-dock-transfer:<id>`（含檔案系統變更與環境變數），
+/// 以相同名稱、ssh port、privileged 與 GPU 重建並套用新 owner label；原本未執行則只建立不啟動。
+/// 原容器先改名保留，新容器建立（並視需要啟動）成功後才刪除；失敗時刪掉新容器並還原原容器。
+/// 不論成功或還原，最後都移除 `dev-dock-transfer:<id>` tag。
+async fn run_transfer(
+    docker: &Docker,
+    docker_network: &str,
+    bind_addrs: &[IpAddr],
+    id: &str,
+    owner_id: i64,
+) -> Result<(String, String), String> {
+    let inspect = docker.inspect_container(id, None).await.map_err(|e| e.to_string())?;
+    let name = inspect.name.as_deref().unwrap_or(id).trim_start_matches('/').to_string();
+    let host_config = inspect.host_config.clone().unwrap_or_default();
+    let ssh_port = host_config
+        .port_bindings
+        .as_ref()
+        .and_then(|b| b.get("22/tcp").cloned().flatten())
+        .and_then(|bindings| {
+            bindings
+                .iter()
+                .filter_map(|b| b.host_port.as_deref())
+                .find_map(|p| p.parse::<u16>().ok())
+        })
+        .ok_or_else(|| format!("Container [{}] has no SSH port binding", name))?;
+    let device_ids: Vec<String> = host_config
+        .device_requests
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|r| r.device_ids.unwrap_or_default())
+        .collect();
+    let privileged = host_config.privileged.unwrap_or(false);
+    let was_running = inspect.state.as_ref().and_then(|s| s.running).unwrap_or(false);
+    let short_id: String = inspect.id.as_deref().unwrap_or(id).chars().take(12).collect();
+    let image = format!("dev-dock-transfer:{}", short_id);
+
+    run_export(docker, id, &image, owner_id).await?;
+    let transferred = async {
+        if was_running {
+            docker
+                .stop_container(id, None::<StopContainerOptions>)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        // 先改名保留原容器，新容器建立成功後才刪除，失敗時可還原
+        let backup = format!("{}-transfer-{}", name, short_id);
+        docker
+            .rename_container(id, RenameContainerOptionsBuilder::default().name(&backup).build())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // 沿用原本配置的 GPU（原容器仍持有同一批卡，不重新選卡）
+        let device_ids = (!device_ids.is_empty()).then_some(device_ids);
+        let recreated = async {
+            let new_id = create_from_image(
+                docker,
+                docker_network,
+                &image,
+                ssh_port,
+                &name,
+                None,
+                privileged,
+                device_ids,
+                bind_addrs,
+                owner_id,
+            )
+            .await?;
+            if was_running {
+                if let Err(e) = docker.start_container(&new_id, None).await {
+                    remove_quietly(docker, &new_id).await;
+                    return Err(e.to_string());
+                }
+            }
+            Ok::<(), String>(())
+        }
+        .await;
+        if let Err(e) = recreated {
+            restore_original(docker, id, &name, was_running).await;
+            return Err(format!(
+                "recreating [{}] from {} failed, original container kept: {}",
+                name, image, e
+            ));
+        }
+        if let Err(e) = docker
+            .remove_container(id, None::<RemoveContainerOptions>)
+            .await
+        {
+            tracing::warn!("Transfer: [{}] recreated but removing old container {} failed: {}", name, backup, e);
+        }
+        Ok::<(), String>(())
+    }
+    .await;
+    remove_image_quietly(docker, &image).await;
+    transferred?;
+    Ok((
+        "TRANSFERRED".to_string(),
+        format!("Container [{}] has been transferred to user {}", name, owner_id),
+    ))
+}
+
+/// 移除暫時的映像 tag（忽略錯誤）；仍有容器使用時只移除 tag，layer 隨容器保留。
+async fn remove_image_quietly(docker: &Docker, image: &str) {
+    let opts = RemoveImageOptionsBuilder::default().force(true).build();
+    if let Err(e) = docker.remove_image(image, Some(opts), None).await {
+        tracing::warn!("Worker: failed to remove image {}: {}", image, e);
+    }
+}
+
+/// 強制移除建立到一半的容器（忽略錯誤）。
+async fn remove_quietly(docker: &Docker, id: &str) {
+    let opts = RemoveContainerOptionsBuilder::default().force(true).build();
+    if let Err(e) = docker.remove_container(id, Some(opts)).await {
+        tracing::warn!("Transfer: failed to remove container {}: {}", id, e);
+    }
+}
+
+/// 轉移失敗：原容器改回原名，原本在執行的話重新啟動。
+async fn restore_original(docker: &Docker, id: &str, name: &str, was_running: bool) {
+    let renamed = docker
+        .rename_container(id, RenameContainerOptionsBuilder::default().name(name).build())
+        .await;
+    if let Err(e) = renamed {
+        tracing::warn!("Transfer: failed to rename {} back to {}: {}", id, name, e);
+    }
+    if was_running {
+        if let Err(e) = docker.start_container(id, None).await {
+            tracing::warn!("Transfer: failed to restart original [{}]: {}", name, e);
+        }
+    }
 }
 
 async fn run_start(docker: &Docker, id: &str) -> Result<(String, String), String> {
@@ -7,10 +7,12 @@ use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::docker::gpu::GpuRequest;
+
 const QUEUE_KEY: &str = "dev_dock_manager:queue";
 const NOTIFY_CHANNEL: &str = "dev_dock_manager:notifications";
 
-/// 單一任務種類：建立映像容器、啟動/停止/刪除/重啟容器。
+/// 單一任務種類：建立映像容器、啟動/停止/刪除/重啟容器、匯出容器、由匯入映像還原容器、移轉容器擁有者。
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Job {
     RunImage {
@@ -23,7 +25,31 @@ pub enum Job {
         root_password: String,
         privileged: bool,
         nvdocker: bool,
+        /// nvdocker 時要配置的 GPU（數量或指定 device_ids）。
+        #[serde(default)]
+        gpus: GpuRequest,
+        docker_network: String,
+        owner_id: i64,
+    },
+    /// commit 容器為 `image`（見 `docker::export_image_ref`），供之後以 export_image 下載。
+    ExportContainer { id: String, image: String, owner_id: i64 },
+    /// 以匯入的映像建立容器，重新套用管理 label 並歸屬於 owner_id。
+    RestoreImage {
+        image_name: String,
+        ssh_port: u16,
+        name: String,
+        privileged: bool,
+        nvdocker: bool,
+        #[serde(default)]
+        gpus: GpuRequest,
+        docker_network: String,
+        owner_id: i64,
+    },
+    /// 將容器改歸 owner_id：Docker 無法修改既有 label，因此 commit 後以相同名稱、port、GPU 重建。
+    TransferContainer {
+        id: String,
         docker_network: String,
+        owner_id: i64,
     },
     StartContainer { id: String },
     StopContainer { id: String },
@@ -31,13 +57,24 @@ pub enum Job {
     RestartContainer { id: String },
 }
 
+impl Job {
+    /// 此任務建立容器時持有保留的 ssh port（RunImage / RestoreImage）。
+    pub fn reserved_port(&self) -> Option<u16> {
+        match self {
+            Job::RunImage { ssh_port, .. } | Job::RestoreImage { ssh_port, .. } => Some(*ssh_port),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EnqueuedJob {
     pub task_id: String,
     pub job: Job,
 }
 
-fn new_task_id() -> String {
+/// 產生任務 id（十六進位）；需先以 task_id 保留資源（例如 port）時由呼叫端先行產生。
+pub fn new_task_id() -> String {
     format!(
         "{:x}",
         SystemTime::now()
@@ -47,9 +84,28 @@ fn new_task_id() -> String {
     )
 }
 
-/// 將「建立並執行映像容器」任務寫入 Redis 佇列；回傳 task_id 供前端輪詢/通知。
+/// 將 job 以指定 task_id 包裝後 LPUSH 到 Redis 佇列。
+async fn push_job(redis_url: &str, task_id: &str, job: Job) -> Result<(), String> {
+    let client = redis::Client::open(redis_url).map_err(|e| e.to_string())?;
+    let mut conn = client
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| e.to_string())?;
+    let payload = serde_json::to_string(&EnqueuedJob {
+        task_id: task_id.to_string(),
+        job,
+    })
+    .map_err(|e| e.to_string())?;
+    conn.lpush::<_, _, ()>(QUEUE_KEY, payload.as_str())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 將「建立並執行映像容器」任務以指定 task_id 寫入 Redis 佇列（task_id 同時是 ssh port 保留的持有者）。
+#[allow(clippy::too_many_arguments)]
 pub async fn enqueue_run_image(
     redis_url: &str,
+    task_id: &str,
     docker_network: &str,
     image_name: &str,
     ssh_port: u16,
@@ -60,13 +116,9 @@ pub async fn enqueue_run_image(
     root_password: &str,
     privileged: bool,
     nvdocker: bool,
-) -> Result<String, String> {
-    let client = redis::Client::open(redis_url).map_err(|e| e.to_string())?;
-    let mut conn = client
-        .get_multiplexed_async_connection()
-        .await
-        .map_err(|e| e.to_string())?;
-    let task_id = new_task_id();
+    gpus: GpuRequest,
+    owner_id: i64,
+) -> Result<(), String> {
     let job = Job::RunImage {
         image_name: image_name.to_string(),
         ssh_port,
@@ -77,16 +129,70 @@ pub async fn enqueue_run_image(
         root_password: root_password.to_string(),
         privileged,
         nvdocker,
+        gpus,
         docker_network: docker_network.to_string(),
+        owner_id,
     };
-    let payload = serde_json::to_string(&EnqueuedJob {
-        task_id: task_id.clone(),
-        job,
-    })
-    .map_err(|e| e.to_string())?;
-    conn.lpush::<_, _, ()>(QUEUE_KEY, payload.as_str())
-        .await
-        .map_err(|e| e.to_string())?;
+    push_job(redis_url, task_id, job).await
+}
+
+/// 將「匯出容器」任務寫入佇列；worker 會 commit 成 `docker::export_image_ref(task_id)`。
+pub async fn enqueue_export_container(
+    redis_url: &str,
+    id: &str,
+    owner_id: i64,
+) -> Result<String, String> {
+    let task_id = new_task_id();
+    let job = Job::ExportContainer {
+        id: id.to_string(),
+        image: crate::docker::export_image_ref(&task_id),
+        owner_id,
+    };
+    push_job(redis_url, &task_id, job).await?;
+    Ok(task_id)
+}
+
+/// 將「由匯入映像建立容器」任務以指定 task_id 寫入佇列（同 `enqueue_run_image`）。
+#[allow(clippy::too_many_arguments)]
+pub async fn enqueue_restore_image(
+    redis_url: &str,
+    task_id: &str,
+    docker_network: &str,
+    image_name: &str,
+    ssh_port: u16,
+    name: &str,
+    privileged: bool,
+    nvdocker: bool,
+    gpus: GpuRequest,
+    owner_id: i64,
+) -> Result<(), String> {
+    let job = Job::RestoreImage {
+        image_name: image_name.to_string(),
+        ssh_port,
+        name: name.to_string(),
+        privileged,
+        nvdocker,
+        gpus,
+        docker_network: docker_network.to_string(),
+        owner_id,
+    };
+    push_job(redis_url, task_id, job).await
+}
+
+/// 將「移轉容器擁有者」任務寫入佇列；回傳 task_id。
+pub async fn enqueue_transfer_container(
+    redis_url: &str,
+    docker_network: &str,
+    id: &str,
+    owner_id: i64,
+) -> Result<String, String> {
+    let task_id = new_task_id();
+    let job = Job::TransferContainer {
+        id: id.to_string(),
+        docker_network: docker_network.to_string(),
+        owner_id,
+    };
+    push_job(redis_url, &task_id, job).await?;
     Ok(task_id)
 }
 
@@ -0,0 +1,169 @@
+//! 記憶體內的 sliding-window 限流：以 key（例如 user id）記錄視窗內的請求時間，超過上限回 429。
+//! 只適用單一 API 行程；各端點的限額集中在 `Limits`，存放於 AppState。
+
+use axum::{http::StatusCode, Json};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// 每個 key 在 `window` 內最多 `max` 次。
+pub struct SlidingWindow {
+    max: usize,
+    window: Duration,
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl SlidingWindow {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 記錄一次請求；超過上限時不記錄並回傳需等待的時間。
+    pub async fn hit(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().await;
+        // 順便清掉整個視窗都已過期的 key，避免 map 無限成長
+        hits.retain(|_, q| q.back().is_some_and(|t| now.duration_since(*t) < self.window));
+        let q = hits.entry(key.to_string()).or_default();
+        while q.front().is_some_and(|t| now.duration_since(*t) >= self.window) {
+            q.pop_front();
+        }
+        if q.len() >= self.max {
+            let oldest = *q.front().unwrap();
+            return Err(self.window.saturating_sub(now.duration_since(oldest)));
+        }
+        q.push_back(now);
+        Ok(())
+    }
+
+    /// 同 `hit`，超過上限時直接轉成 API 的 429 錯誤。
+    pub async fn check(&self, key: &str) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+        self.hit(key).await.map_err(|retry| {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(serde_json::json!({
+                    "error": "Too many requests",
+                    "retry_after": retry.as_secs().max(1),
+                })),
+            )
+        })
+    }
+}
+
+/// 請求來源 IP：連線位址在 `trusted` 之內時，由右往左略過同樣可信任的代理，取 X-Forwarded-For 中第一筆不可信任的位址；
+/// 其他來源（例如同一網路上的使用者容器直接連線）自帶的 X-Forwarded-For 一律忽略，使用 TCP 連線位址。
+pub fn client_ip(
+    headers: &axum::http::HeaderMap,
+    peer: Option<std::net::SocketAddr>,
+    trusted: &[ipnet::IpNet],
+) -> Option<String> {
+    let peer = peer?.ip();
+    let is_trusted = |ip: &std::net::IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return Some(peer.to_string());
+    }
+    let forwarded = headers.get("x-forwarded-for").and_then(|v| v.to_str().ok());
+    let mut client = peer;
+    for entry in forwarded.into_iter().flat_map(|v| v.rsplit(',')).map(str::trim) {
+        let Ok(ip) = entry.parse::<std::net::IpAddr>() else {
+            // 格式錯誤：不再往左相信
+            break;
+        };
+        client = ip;
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    Some(client.to_string())
+}
+
+/// handler 參數形式的 `client_ip`（依 TRUST_PROXY_HEADERS / TRUSTED_PROXIES），供稽核紀錄等使用；取不到時為 None。
+pub struct ClientIp(pub Option<String>);
+
+#[async_trait::async_trait]
+impl axum::extract::FromRequestParts<crate::AppState> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &crate::AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+            .map(|axum::extract::ConnectInfo(addr)| *addr);
+        Ok(ClientIp(client_ip(&parts.headers, peer, state.config.forwarded_trust())))
+    }
+}
+
+/// 各端點的限額（未特別註明為 per user）。
+pub struct Limits {
+    /// GET /ports：每次最多掃描 FREE_PORTS_MAX 個 host port。
+    pub free_ports: SlidingWindow,
+    /// GET /ports/check：單一 port 連線檢查。
+    pub port_check: SlidingWindow,
+    /// POST /nvdocker/refresh：會建立 NVIDIA 探測容器。
+    pub nvdocker_refresh: SlidingWindow,
+    /// POST /auth/token：per client IP。
+    pub login_ip: SlidingWindow,
+    /// POST /auth/token：per username（另有 SQLite 的漸進式鎖定，見 `db::login_lockout`）。
+    pub login_user: SlidingWindow,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        let minute = Duration::from_secs(60);
+        Self {
+            free_ports: SlidingWindow::new(10, minute),
+            port_check: SlidingWindow::new(60, minute),
+            nvdocker_refresh: SlidingWindow::new(2, minute),
+            login_ip: SlidingWindow::new(20, minute),
+            login_user: SlidingWindow::new(10, minute),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+
+    fn xff(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    fn peer(ip: &str) -> Option<std::net::SocketAddr> {
+        Some(std::net::SocketAddr::new(ip.parse().unwrap(), 40000))
+    }
+
+    #[test]
+    fn forwarded_for_needs_a_trusted_peer() {
+        let trusted = crate::config::parse_proxy_list("172.18.0.2/32, 10.1.0.0/16, not-an-ip");
+        assert_eq!(trusted.len(), 2);
+        let headers = xff("1.2.3.4, 5.6.7.8");
+        // 使用者容器直接連線：自帶的 X-Forwarded-For 不算數
+        assert_eq!(client_ip(&headers, peer("172.18.0.9"), &trusted).as_deref(), Some("172.18.0.9"));
+        // TRUST_PROXY_HEADERS 關閉（清單為空）
+        assert_eq!(client_ip(&headers, peer("172.18.0.2"), &[]).as_deref(), Some("172.18.0.2"));
+        // 可信任的代理：取它加上的最右一筆
+        assert_eq!(client_ip(&headers, peer("172.18.0.2"), &trusted).as_deref(), Some("5.6.7.8"));
+        assert_eq!(client_ip(&HeaderMap::new(), peer("172.18.0.2"), &trusted).as_deref(), Some("172.18.0.2"));
+        assert_eq!(client_ip(&headers, None, &trusted), None);
+    }
+
+    #[test]
+    fn forwarded_for_skips_chained_trusted_proxies() {
+        let trusted = crate::config::parse_proxy_list("172.18.0.2,10.1.0.0/16");
+        let headers = xff("9.9.9.9, 1.2.3.4, 10.1.2.3");
+        assert_eq!(client_ip(&headers, peer("172.18.0.2"), &trusted).as_deref(), Some("1.2.3.4"));
+        // 無法解析的項目：停在最後一個可信任的位址
+        let headers = xff("1.2.3.4, garbage");
+        assert_eq!(client_ip(&headers, peer("172.18.0.2"), &trusted).as_deref(), Some("172.18.0.2"));
+    }
+}
@@ -0,0 +1,433 @@
+//! Console 錄影（asciicast v2，可用 asciinema 播放）：CONSOLE_RECORDING 開啟時，每個 shell / attach session
+//! 寫一個 `.cast` 檔到 CONSOLE_RECORDING_DIR，metadata 存在 `db::recording`。
+//! 事件為 `[經過秒數, "o" | "i" | "r", 資料]`：輸出、輸入（CONSOLE_RECORDING_INPUT）與 resize（`COLSxROWS`）。
+//! header 的終端機大小取自第一個 pty_resize；在那之前的事件先暫存在記憶體。寫入不逐筆 flush，由背景定期 flush。
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use sqlx::SqlitePool;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::db::recording::{self, NewRecording};
+use crate::db::User;
+
+/// 收到 pty_resize 前暫存事件的上限；超過時以預設大小寫出 header。
+const HEADER_WAIT_BYTES: usize = 64 * 1024;
+/// 前端沒有送出大小時 header 使用的終端機大小。
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+/// 背景 flush 的間隔（服務中斷時最多遺失這段時間的輸出）。
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 錄影所屬的 session。
+pub struct SessionInfo<'a> {
+    pub container_id: &'a str,
+    pub container_name: Option<&'a str>,
+    pub mode: &'a str,
+}
+
+struct Output {
+    /// finish / discard 後為 None，之後的事件直接忽略。
+    writer: Option<BufWriter<tokio::fs::File>>,
+    /// 尚未寫出的 header（等第一個 pty_resize 決定大小）。
+    header: Option<serde_json::Value>,
+    /// header 寫出前的事件（每筆含換行）。
+    pending: Vec<String>,
+    pending_bytes: usize,
+    written: u64,
+    truncated: bool,
+    /// 上次 flush 之後有新寫入。
+    dirty: bool,
+}
+
+impl Output {
+    /// 寫入一行，超過大小上限或寫入失敗時停止錄影（保留已寫的部分）。
+    async fn write_line(&mut self, id: &str, max_bytes: u64, line: &str) {
+        if self.truncated {
+            return;
+        }
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        if self.written + line.len() as u64 > max_bytes {
+            self.truncated = true;
+            tracing::warn!("recording {}: size limit reached, truncating", id);
+            return;
+        }
+        match writer.write_all(line.as_bytes()).await {
+            Ok(()) => {
+                self.written += line.len() as u64;
+                self.dirty = true;
+            }
+            Err(e) => {
+                // 磁碟滿等錯誤：停止寫入
+                tracing::warn!("recording {}: write failed: {}", id, e);
+                self.truncated = true;
+            }
+        }
+    }
+
+    /// 以給定大小寫出 header，接著寫出暫存的事件。header 已寫出時不做事。
+    async fn write_header(&mut self, id: &str, max_bytes: u64, (cols, rows): (u16, u16)) {
+        let Some(mut header) = self.header.take() else {
+            return;
+        };
+        header["width"] = cols.into();
+        header["height"] = rows.into();
+        let mut line = header.to_string();
+        line.push('\n');
+        self.write_line(id, max_bytes, &line).await;
+        self.pending_bytes = 0;
+        for line in std::mem::take(&mut self.pending) {
+            self.write_line(id, max_bytes, &line).await;
+        }
+    }
+}
+
+pub struct Recorder {
+    id: String,
+    path: PathBuf,
+    started: Instant,
+    record_input: bool,
+    max_bytes: u64,
+    out: Mutex<Output>,
+}
+
+impl Recorder {
+    /// 建立檔案並新增 metadata（header 等第一個 pty_resize 才寫出）；CONSOLE_RECORDING 關閉時回 Ok(None)。
+    pub async fn start(
+        config: &Config,
+        pool: &SqlitePool,
+        user: &User,
+        session: SessionInfo<'_>,
+    ) -> Result<Option<Arc<Recorder>>, String> {
+        if !config.console_recording {
+            return Ok(None);
+        }
+        let dir = Path::new(&config.console_recording_dir);
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("recording: cannot create {}: {}", dir.display(), e))?;
+        let id = uuid::Uuid::new_v4().to_string();
+        let file = format!("{}.cast", id);
+        let path = dir.join(&file);
+        let mut opts = tokio::fs::OpenOptions::new();
+        opts.write(true).create_new(true);
+        #[cfg(unix)]
+        opts.mode(0o600);
+        let handle = opts
+            .open(&path)
+            .await
+            .map_err(|e| format!("recording: cannot create {}: {}", path.display(), e))?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let title = format!(
+            "{} {} by {}",
+            session.mode,
+            session.container_name.unwrap_or(session.container_id),
+            user.username
+        );
+        let header = serde_json::json!({
+            "version": 2,
+            "width": DEFAULT_SIZE.0,
+            "height": DEFAULT_SIZE.1,
+            "timestamp": timestamp,
+            "title": title,
+            "env": { "TERM": "xterm-256color", "SHELL": "/bin/bash" },
+        });
+        let new = NewRecording {
+            id: &id,
+            container_id: session.container_id,
+            container_name: session.container_name,
+            user_id: user.id,
+            username: &user.username,
+            mode: session.mode,
+            file: &file,
+        };
+        if let Err(e) = recording::insert(pool, &new).await {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(format!("recording: cannot start: {}", e));
+        }
+        let recorder = Arc::new(Recorder {
+            id,
+            path,
+            started: Instant::now(),
+            record_input: config.console_recording_input,
+            max_bytes: config.console_recording_max_bytes,
+            out: Mutex::new(Output {
+                writer: Some(BufWriter::new(handle)),
+                header: Some(header),
+                pending: Vec::new(),
+                pending_bytes: 0,
+                written: 0,
+                truncated: false,
+                dirty: false,
+            }),
+        });
+        tokio::spawn(flush_periodically(Arc::downgrade(&recorder)));
+        Ok(Some(recorder))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// 終端機輸出（送到 WebSocket 的同一段文字）。
+    pub async fn output(&self, data: &str) {
+        if !data.is_empty() {
+            self.event("o", data).await;
+        }
+    }
+
+    /// 使用者輸入；CONSOLE_RECORDING_INPUT=false 時不記錄。
+    pub async fn input(&self, data: &str) {
+        if self.record_input {
+            self.event("i", data).await;
+        }
+    }
+
+    /// 第一次呼叫決定 header 的大小，之後記成 `r` 事件。
+    pub async fn resize(&self, cols: u16, rows: u16) {
+        let mut out = self.out.lock().await;
+        if out.header.is_some() {
+            out.write_header(&self.id, self.max_bytes, (cols, rows)).await;
+            return;
+        }
+        let line = self.event_line("r", &format!("{}x{}", cols, rows));
+        out.write_line(&self.id, self.max_bytes, &line).await;
+    }
+
+    fn event_line(&self, kind: &str, data: &str) -> String {
+        let elapsed = (self.started.elapsed().as_secs_f64() * 1e6).round() / 1e6;
+        let mut line = serde_json::json!([elapsed, kind, data]).to_string();
+        line.push('\n');
+        line
+    }
+
+    async fn event(&self, kind: &str, data: &str) {
+        let line = self.event_line(kind, data);
+        let mut out = self.out.lock().await;
+        if out.header.is_none() {
+            out.write_line(&self.id, self.max_bytes, &line).await;
+            return;
+        }
+        out.pending_bytes += line.len();
+        out.pending.push(line);
+        // 前端一直沒有送出大小：不再等待
+        if out.pending_bytes > HEADER_WAIT_BYTES {
+            out.write_header(&self.id, self.max_bytes, DEFAULT_SIZE).await;
+        }
+    }
+
+    /// 把緩衝中的資料寫到磁碟；回傳 false 表示錄影已結束。
+    async fn flush(&self) -> bool {
+        let mut out = self.out.lock().await;
+        if !out.dirty {
+            return out.writer.is_some();
+        }
+        out.dirty = false;
+        let Some(writer) = out.writer.as_mut() else {
+            return false;
+        };
+        if let Err(e) = writer.flush().await {
+            tracing::warn!("recording {}: flush failed: {}", self.id, e);
+        }
+        true
+    }
+
+    /// session 結束：關閉檔案並更新 metadata。重複呼叫無作用。
+    pub async fn finish(&self, pool: &SqlitePool) {
+        let mut out = self.out.lock().await;
+        out.write_header(&self.id, self.max_bytes, DEFAULT_SIZE).await;
+        let Some(mut writer) = out.writer.take() else {
+            return;
+        };
+        let _ = writer.shutdown().await;
+        if let Err(e) = recording::finish(pool, &self.id, out.written, out.truncated).await {
+            tracing::warn!("recording {}: failed to update metadata: {}", self.id, e);
+        }
+    }
+
+    /// session 沒有成功開始：刪除檔案與 metadata。
+    pub async fn discard(&self, pool: &SqlitePool) {
+        self.out.lock().await.writer = None;
+        let _ = tokio::fs::remove_file(&self.path).await;
+        if let Err(e) = recording::delete(pool, &self.id).await {
+            tracing::warn!("recording {}: failed to delete metadata: {}", self.id, e);
+        }
+    }
+}
+
+/// 錄影進行中定期 flush；Recorder 被釋放或結束後停止。
+async fn flush_periodically(recorder: Weak<Recorder>) {
+    loop {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        let Some(recorder) = recorder.upgrade() else {
+            return;
+        };
+        if !recorder.flush().await {
+            return;
+        }
+    }
+}
+
+/// 錄影檔的路徑；`file` 只取檔名，避免跳出錄影目錄。
+pub fn file_path(config: &Config, file: &str) -> PathBuf {
+    let name = Path::new(file).file_name().unwrap_or_default();
+    Path::new(&config.console_recording_dir).join(name)
+}
+
+/// 啟動時收尾上次服務中斷時仍在錄的 session：以檔案修改時間與大小補上 metadata。
+pub async fn close_unfinished(config: &Config, pool: &SqlitePool) {
+    let open = match recording::unfinished(pool).await {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("recording: failed to list unfinished recordings: {}", e);
+            return;
+        }
+    };
+    for rec in open {
+        let meta = tokio::fs::metadata(file_path(config, &rec.file)).await.ok();
+        let size = meta.as_ref().map(|m| m.len()).unwrap_or(0);
+        let ended_at = meta
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(rec.started_at);
+        if let Err(e) = recording::finish_at(pool, &rec.id, ended_at, size, rec.truncated).await {
+            tracing::warn!("recording {}: failed to close: {}", rec.id, e);
+        }
+    }
+}
+
+/// 刪除結束超過 `retention_days` 天的錄影（先刪檔案再刪 metadata）。
+pub async fn prune_expired(config: &Config, pool: &SqlitePool, retention_days: u64) -> Result<u64, sqlx::Error> {
+    let cutoff = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+        - (retention_days * 86_400) as i64;
+    let mut removed = 0;
+    for rec in recording::ended_before(pool, cutoff).await? {
+        match tokio::fs::remove_file(file_path(config, &rec.file)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                tracing::warn!("recording {}: failed to delete file: {}", rec.id, e);
+                continue;
+            }
+        }
+        if recording::delete(pool, &rec.id).await? {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// 常駐迴圈：定期清除超過保留期限的錄影。CONSOLE_RECORDING_RETENTION_DAYS=0 時不啟動。
+pub async fn run_retention(config: Config, pool: SqlitePool, interval: Duration) {
+    let days = config.console_recording_retention_days;
+    loop {
+        tokio::time::sleep(interval).await;
+        match prune_expired(&config, &pool, days).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Recordings: deleted {} expired recording(s)", n),
+            Err(e) => tracing::warn!("Recordings: prune failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn recorder(dir: &tempfile::TempDir, max_bytes: u64) -> Recorder {
+        let path = dir.path().join("test.cast");
+        let file = tokio::fs::File::create(&path).await.unwrap();
+        Recorder {
+            id: "test".into(),
+            path,
+            started: Instant::now(),
+            record_input: true,
+            max_bytes,
+            out: Mutex::new(Output {
+                writer: Some(BufWriter::new(file)),
+                header: Some(serde_json::json!({ "version": 2, "width": 80, "height": 24 })),
+                pending: Vec::new(),
+                pending_bytes: 0,
+                written: 0,
+                truncated: false,
+                dirty: false,
+            }),
+        }
+    }
+
+    async fn lines(rec: &Recorder) -> Vec<serde_json::Value> {
+        let data = tokio::fs::read_to_string(&rec.path).await.unwrap();
+        data.lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn header_waits_for_first_resize() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let rec = recorder(&dir, 1 << 20).await;
+        rec.output("prompt$ ").await;
+        rec.input("ls\r").await;
+        rec.resize(120, 40).await;
+        rec.resize(100, 30).await;
+        rec.finish(&pool).await;
+
+        let lines = lines(&rec).await;
+        assert_eq!((lines[0]["width"].as_u64(), lines[0]["height"].as_u64()), (Some(120), Some(40)));
+        let events: Vec<(&str, &str)> = lines[1..]
+            .iter()
+            .map(|e| (e[1].as_str().unwrap(), e[2].as_str().unwrap()))
+            .collect();
+        assert_eq!(events, [("o", "prompt$ "), ("i", "ls\r"), ("r", "100x30")]);
+    }
+
+    #[tokio::test]
+    async fn header_falls_back_to_default_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let rec = recorder(&dir, 1 << 20).await;
+        rec.output("bye").await;
+        rec.finish(&pool).await;
+        let lines = lines(&rec).await;
+        assert_eq!((lines[0]["width"].as_u64(), lines[0]["height"].as_u64()), (Some(80), Some(24)));
+        assert_eq!(lines[1][2], "bye");
+
+        // 一直沒有 resize：暫存超過上限後先以預設大小寫出
+        let rec = recorder(&dir, 1 << 20).await;
+        rec.output(&"x".repeat(HEADER_WAIT_BYTES)).await;
+        assert!(rec.out.lock().await.header.is_none());
+    }
+
+    #[tokio::test]
+    async fn events_are_buffered_until_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let rec = recorder(&dir, 1 << 20).await;
+        rec.resize(80, 24).await;
+        rec.output("hello").await;
+        assert_eq!(tokio::fs::read(&rec.path).await.unwrap().len(), 0);
+        assert!(rec.flush().await);
+        assert_eq!(lines(&rec).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_at_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = crate::db::test_pool(&dir).await;
+        let rec = recorder(&dir, 200).await;
+        rec.resize(80, 24).await;
+        rec.output("short").await;
+        rec.output(&"y".repeat(200)).await;
+        rec.output("after").await;
+        rec.finish(&pool).await;
+        assert!(rec.out.lock().await.truncated);
+        let lines = lines(&rec).await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1][2], "short");
+    }
+}
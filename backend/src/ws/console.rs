@@ -1,5 +1,8 @@
 //! 終端機 WebSocket：先連線（query 僅 ?container=ID），第一則訊息須帶 token 驗證後才處理。
 //! 對應 Django ConsoleConsumer：建立 exec 或 attach 取得 Docker 串流，轉發到 WebSocket；支援 PTY 輸入與 resize。
+//! 預設以 text frame 傳送（跨 chunk 的 UTF-8 字元會正確接回）；`?binary=true` 時輸出改為 binary frame 原樣傳送，
+//! client 也可用 binary frame 直接寫入 stdin（供 sz/rz 等），控制訊息仍為 JSON text frame。
+//! session 開啟（或被拒絕）與結束都寫入稽核紀錄（`console.open` / `console.close`）；CONSOLE_RECORDING 開啟時另錄成 asciicast（見 `recording`）。
 
 use axum::{
     extract::{Query, State},
@@ -17,19 +20,28 @@ use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use crate::db::get_by_id;
-use crate::jwt;
+use super::utf8::Utf8Decoder;
+use super::{authenticate_first_message, close_policy, close_unauthorized};
+use crate::audit::{self, Event};
+use crate::db::User;
+use crate::permissions::{Permission, Scope};
+use crate::rate_limit::ClientIp;
+use crate::recording::{Recorder, SessionInfo};
 use crate::AppState;
 
 /// Query 參數：?container=CONTAINER_ID（token 改由第一則訊息傳送，避免進 URL/log）
 #[derive(serde::Deserialize)]
 pub struct ConsoleQuery {
     pub container: Option<String>,
+    /// true 時以 binary frame 傳送原始位元組。
+    #[serde(default)]
+    pub binary: bool,
 }
 
 /// 先接受連線，token 於第一則訊息內驗證（見 handle_socket）。
 pub async fn handler(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Query(params): Query<ConsoleQuery>,
     upgrade: WebSocketUpgrade,
 ) -> Response {
@@ -38,27 +50,48 @@ pub async fn handler(
         return (StatusCode::BAD_REQUEST, "missing container query").into_response();
     }
     let state = Arc::new(state);
-    upgrade.on_upgrade(move |socket| handle_socket(socket, state, container_id))
+    let binary = params.binary;
+    upgrade.on_upgrade(move |socket| handle_socket(socket, state, container_id, ip, binary))
 }
 
+/// exec / attach 的 stdin 寫入端（共用於 pty_input）。
+type StdinWriter = Arc<Mutex<Pin<Box<dyn tokio::io::AsyncWrite + Send>>>>;
+
 /// Session state for one console connection.
 struct Session {
     container_id: String,
+    /// `shell` 或 `attach`。
+    mode: &'static str,
+    opened_at: std::time::Instant,
     exec_id: Option<String>,
     pid_path: Option<String>,
     /// Write half for exec stdin (shell) or attach stdin.
-    stdin_tx: Option<Arc<Mutex<Pin<Box<dyn tokio::io::AsyncWrite + Send>>>>>,
+    stdin_tx: Option<StdinWriter>,
+    recorder: Option<Arc<Recorder>>,
 }
 
-fn close_unauthorized() -> Option<CloseFrame<'static>> {
-    use axum::extract::ws::close_code;
-    Some(CloseFrame {
-        code: close_code::POLICY,
-        reason: std::borrow::Cow::Borrowed("Unauthorized"),
-    })
+/// 需要 `console.attach`，且只能連到自己可存取的容器（見 `docker::user_can_access`）。
+async fn may_attach(state: &AppState, user: &User, container_id: &str) -> bool {
+    if !user.can(Permission::ConsoleAttach) {
+        return false;
+    }
+    matches!(
+        crate::docker::container_owner(&state.docker, container_id).await,
+        Ok(owner) if crate::docker::user_can_access(user, owner)
+    )
+}
+
+fn console_event(action: &str, user: &User, container_id: &str, ip: Option<&str>) -> Event {
+    Event::new(action).user(user).target("container", container_id).ip(ip)
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>, _container_id: String) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    container_id: String,
+    ip: Option<String>,
+    binary: bool,
+) {
     let (ws_tx, mut ws_rx) = socket.split();
     let session: Arc<Mutex<Option<Session>>> = Arc::new(Mutex::new(None));
     let authenticated = Arc::new(AtomicBool::new(false));
@@ -70,7 +103,12 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, _container_id: S
     let ws_tx = Arc::new(Mutex::new(ws_tx));
     let ws_tx_recv = ws_tx.clone();
 
+    let recv_ip = ip.clone();
+    let recv_container_id = container_id.clone();
+    // 回傳通過驗證的使用者，供結束時記錄 console.close
     let recv_task = tokio::spawn(async move {
+        let (ip, container_id) = (recv_ip, recv_container_id);
+        let mut user: Option<User> = None;
         while let Some(msg) = ws_rx.next().await {
             match msg {
                 Ok(Message::Text(text)) => {
@@ -80,38 +118,64 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, _container_id: S
                     };
                     let is_first = !auth_clone.load(Ordering::Relaxed);
                     if is_first {
-                        let token = parsed.get("token").and_then(|t| t.as_str()).map(str::to_string);
-                        let token = match token {
-                            Some(t) if !t.is_empty() => t,
-                            _ => {
-                                let _ = ws_tx_recv.lock().await.send(Message::Close(close_unauthorized())).await;
-                                break;
-                            }
-                        };
-                        let claims = match jwt::verify_access(&token, state_clone.config.jwt_secret.as_bytes()) {
-                            Ok(c) => c,
-                            Err(_) => {
-                                let _ = ws_tx_recv.lock().await.send(Message::Close(close_unauthorized())).await;
-                                break;
-                            }
-                        };
-                        if get_by_id(&state_clone.pool, claims.user_id).await.ok().flatten().is_none() {
+                        let Some(authed) = authenticate_first_message(&state_clone, &parsed, Scope::Console).await else {
                             let _ = ws_tx_recv.lock().await.send(Message::Close(close_unauthorized())).await;
                             break;
+                        };
+                        if !may_attach(&state_clone, &authed, &container_id).await {
+                            audit::record(
+                                &state_clone.pool,
+                                console_event("console.open", &authed, &container_id, ip.as_deref()).denied(),
+                            )
+                            .await;
+                            let _ = ws_tx_recv.lock().await.send(Message::Close(close_policy("Forbidden"))).await;
+                            break;
                         }
                         auth_clone.store(true, Ordering::Relaxed);
+                        user = Some(authed);
                     }
+                    let Some(current) = &user else { break };
                     let action = parsed.get("action").and_then(|a| a.as_str());
                     let payload = parsed.get("payload").cloned().unwrap_or_default();
-                    if let Err(e) = handle_message(
+                    // 存取權限只檢查過 ?container=，shell / attach 不可改連其他容器
+                    let requested = payload.get("Id").and_then(|v| v.as_str());
+                    if let (Some("shell" | "attach"), Some(requested), Some(user)) = (action, requested, &user) {
+                        if requested != container_id {
+                            audit::record(
+                                &state_clone.pool,
+                                console_event("console.open", user, requested, ip.as_deref()).denied(),
+                            )
+                            .await;
+                            let _ = ws_tx_recv.lock().await.send(Message::Close(close_policy("Forbidden"))).await;
+                            break;
+                        }
+                    }
+                    let result = handle_message(
                         &state_clone,
                         &session_clone,
+                        current,
                         action,
                         payload,
                         &ws_tx_recv,
+                        binary,
                     )
-                    .await
-                    {
+                    .await;
+                    if let (Some(mode @ ("shell" | "attach")), Some(user)) = (action, &user) {
+                        let event = console_event("console.open", user, &container_id, ip.as_deref());
+                        let event = match &result {
+                            Ok(()) => event.details(serde_json::json!({ "mode": mode })),
+                            Err(e) => event.failed().details(serde_json::json!({ "mode": mode, "error": e })),
+                        };
+                        audit::record(&state_clone.pool, event).await;
+                    }
+                    if let Err(e) = result {
+                        tracing::warn!("console message error: {}", e);
+                        break;
+                    }
+                }
+                // binary 模式：frame 內容原樣寫入 stdin
+                Ok(Message::Binary(data)) if binary && user.is_some() => {
+                    if let Err(e) = write_stdin(&session_clone, &data).await {
                         tracing::warn!("console message error: {}", e);
                         break;
                     }
@@ -120,22 +184,39 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>, _container_id: S
                 _ => {}
             }
         }
+        user
     });
 
-    recv_task.await.ok();
+    let user = recv_task.await.ok().flatten();
 
     let mut guard = session.lock().await;
     if let Some(s) = guard.take() {
         cleanup_shell(&state.docker, &s).await;
+        if let Some(recorder) = &s.recorder {
+            recorder.finish(&state.pool).await;
+        }
+        if let Some(user) = &user {
+            audit::record(
+                &state.pool,
+                console_event("console.close", user, &s.container_id, ip.as_deref()).details(serde_json::json!({
+                    "mode": s.mode,
+                    "duration_secs": s.opened_at.elapsed().as_secs(),
+                    "recording": s.recorder.as_ref().map(|r| r.id()),
+                })),
+            )
+            .await;
+        }
     }
 }
 
 async fn handle_message(
     state: &AppState,
     session: &Arc<Mutex<Option<Session>>>,
+    user: &User,
     action: Option<&str>,
     payload: serde_json::Value,
     ws_tx: &Arc<Mutex<futures_util::stream::SplitSink<WebSocket, Message>>>,
+    binary: bool,
 ) -> Result<(), String> {
     match action {
         Some("shell") => {
@@ -143,28 +224,21 @@ async fn handle_message(
                 .get("Id")
                 .and_then(|v| v.as_str())
                 .ok_or("shell: missing Id")?;
-            start_shell(state, session, id, ws_tx).await
+            start_shell(state, session, user, id, ws_tx, binary).await
         }
         Some("attach") => {
             let id = payload
                 .get("Id")
                 .and_then(|v| v.as_str())
                 .ok_or("attach: missing Id")?;
-            start_attach(state, session, id, ws_tx).await
+            start_attach(state, session, user, id, ws_tx, binary).await
         }
         Some("pty_input") => {
             let input = payload
                 .get("input")
                 .and_then(|v| v.as_str())
                 .ok_or("pty_input: missing input")?;
-            let guard = session.lock().await;
-            if let Some(ref tx) = guard.as_ref().and_then(|s| s.stdin_tx.as_ref()) {
-                let mut w = tx.lock().await;
-                use tokio::io::AsyncWriteExt;
-                w.as_mut().write_all(input.as_bytes()).await.map_err(|e| e.to_string())?;
-                w.as_mut().flush().await.map_err(|e| e.to_string())?;
-            }
-            Ok(())
+            write_stdin(session, input.as_bytes()).await
         }
         Some("pty_resize") => {
             let size = payload.get("size").ok_or("pty_resize: missing size")?;
@@ -180,6 +254,10 @@ async fn handle_message(
                         .await
                         .map_err(|e| e.to_string())?;
                 }
+                // attach 沒有 exec 可 resize，但播放時仍需要使用者當時的終端機大小
+                if let Some(recorder) = &s.recorder {
+                    recorder.resize(cols, rows).await;
+                }
             }
             Ok(())
         }
@@ -187,34 +265,116 @@ async fn handle_message(
     }
 }
 
-/// 查詢容器是否為 running 狀態（inspect 的 state.status == RUNNING）。
-fn container_running<'a>(
-    docker: &'a bollard::Docker,
-    id: &'a str,
-) -> impl std::future::Future<Output = bool> + 'a {
-    async move {
-        let inspect = match docker.inspect_container(id, None).await {
-            Ok(i) => i,
-            Err(_) => return false,
-        };
-        inspect
-            .state
-            .as_ref()
-            .and_then(|s| s.status.as_ref())
-            .map(|st| matches!(st, ContainerStateStatusEnum::RUNNING))
-            .unwrap_or(false)
+/// 寫入目前 session 的 stdin（尚未開始 session 時忽略），有錄影時一併記錄。
+async fn write_stdin(session: &Arc<Mutex<Option<Session>>>, input: &[u8]) -> Result<(), String> {
+    let guard = session.lock().await;
+    if let Some(s) = guard.as_ref() {
+        if let Some(tx) = s.stdin_tx.as_ref() {
+            let mut w = tx.lock().await;
+            use tokio::io::AsyncWriteExt;
+            w.as_mut().write_all(input).await.map_err(|e| e.to_string())?;
+            w.as_mut().flush().await.map_err(|e| e.to_string())?;
+            if let Some(recorder) = &s.recorder {
+                recorder.input(&String::from_utf8_lossy(input)).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 容器為 running 狀態（inspect 的 state.status == RUNNING）時回傳完整 ID 與名稱（供錄影 metadata）。
+async fn running_container(docker: &bollard::Docker, id: &str) -> Option<(String, Option<String>)> {
+    let inspect = docker.inspect_container(id, None).await.ok()?;
+    let running = inspect
+        .state
+        .as_ref()
+        .and_then(|s| s.status.as_ref())
+        .map(|st| matches!(st, ContainerStateStatusEnum::RUNNING))
+        .unwrap_or(false);
+    if !running {
+        return None;
+    }
+    let name = inspect.name.map(|n| n.trim_start_matches('/').to_string());
+    Some((inspect.id.unwrap_or_else(|| id.to_string()), name))
+}
+
+/// 開始錄影（CONSOLE_RECORDING 關閉時為 None）；開啟但無法錄影時拒絕開啟 session。
+async fn start_recording(
+    state: &AppState,
+    user: &User,
+    container: &(String, Option<String>),
+    mode: &str,
+) -> Result<Option<Arc<Recorder>>, String> {
+    let info = SessionInfo {
+        container_id: &container.0,
+        container_name: container.1.as_deref(),
+        mode,
+    };
+    Recorder::start(&state.config, &state.pool, user, info).await.map_err(|e| {
+        tracing::warn!("console: {}", e);
+        "recording unavailable".to_string()
+    })
+}
+
+/// 換上新的 session；同一連線先前的 session 若有錄影則先結束。
+async fn replace_session(state: &AppState, session: &Arc<Mutex<Option<Session>>>, new: Session) {
+    let old = session.lock().await.replace(new);
+    if let Some(recorder) = old.and_then(|s| s.recorder) {
+        recorder.finish(&state.pool).await;
+    }
+}
+
+async fn discard_recording(state: &AppState, recorder: &Option<Arc<Recorder>>) {
+    if let Some(recorder) = recorder {
+        recorder.discard(&state.pool).await;
     }
 }
 
 async fn start_shell(
     state: &AppState,
     session: &Arc<Mutex<Option<Session>>>,
+    user: &User,
     container_id: &str,
     ws_tx: &Arc<Mutex<futures_util::stream::SplitSink<WebSocket, Message>>>,
+    binary: bool,
 ) -> Result<(), String> {
-    if !container_running(&state.docker, container_id).await {
-        return Err("container not running".into());
+    let container = running_container(&state.docker, container_id)
+        .await
+        .ok_or("container not running")?;
+    let recorder = start_recording(state, user, &container, "shell").await?;
+    match exec_shell(state, container_id).await {
+        Ok((exec_id, pid_path, output, input)) => {
+            replace_session(
+                state,
+                session,
+                Session {
+                    container_id: container_id.to_string(),
+                    mode: "shell",
+                    opened_at: std::time::Instant::now(),
+                    exec_id: Some(exec_id),
+                    pid_path: Some(pid_path),
+                    stdin_tx: Some(Arc::new(Mutex::new(input))),
+                    recorder: recorder.clone(),
+                },
+            )
+            .await;
+            tokio::spawn(forward_docker_stream_to_ws(output, ws_tx.clone(), recorder, binary));
+            Ok(())
+        }
+        Err(e) => {
+            discard_recording(state, &recorder).await;
+            Err(e)
+        }
     }
+}
+
+type ExecOutput = Pin<Box<dyn futures_util::Stream<Item = Result<LogOutput, bollard::errors::Error>> + Send>>;
+
+/// 建立並啟動互動 shell exec，回傳 (exec_id, pid_path, output, stdin)。
+async fn exec_shell(
+    state: &AppState,
+    container_id: &str,
+) -> Result<(String, String, ExecOutput, Pin<Box<dyn tokio::io::AsyncWrite + Send>>), String> {
     let pid_path = format!("/tmp/_process_{}.pid", uuid::Uuid::new_v4());
     let create_opts = CreateExecOptions::<String> {
         attach_stdin: Some(true),
@@ -243,30 +403,24 @@ async fn start_shell(
         .start_exec(&exec_id, Some(start_opts))
         .await
         .map_err(|e| e.to_string())?;
-    let (output, input) = match start_res {
-        bollard::exec::StartExecResults::Attached { output, input } => (output, input),
-        bollard::exec::StartExecResults::Detached => return Err("exec detached".into()),
-    };
-    let stdin_tx = Arc::new(Mutex::new(Pin::from(input)));
-    *session.lock().await = Some(Session {
-        container_id: container_id.to_string(),
-        exec_id: Some(exec_id.clone()),
-        pid_path: Some(pid_path.clone()),
-        stdin_tx: Some(stdin_tx.clone()),
-    });
-    tokio::spawn(forward_docker_stream_to_ws(output, ws_tx.clone()));
-    Ok(())
+    match start_res {
+        bollard::exec::StartExecResults::Attached { output, input } => Ok((exec_id, pid_path, output, input)),
+        bollard::exec::StartExecResults::Detached => Err("exec detached".into()),
+    }
 }
 
 async fn start_attach(
     state: &AppState,
     session: &Arc<Mutex<Option<Session>>>,
+    user: &User,
     container_id: &str,
     ws_tx: &Arc<Mutex<futures_util::stream::SplitSink<WebSocket, Message>>>,
+    binary: bool,
 ) -> Result<(), String> {
-    if !container_running(&state.docker, container_id).await {
-        return Err("container not running".into());
-    }
+    let container = running_container(&state.docker, container_id)
+        .await
+        .ok_or("container not running")?;
+    let recorder = start_recording(state, user, &container, "attach").await?;
     let opts = AttachContainerOptionsBuilder::default()
         .stdin(true)
         .stdout(true)
@@ -274,38 +428,64 @@ async fn start_attach(
         .stream(true)
         .logs(true)
         .build();
-    let attach_res = state
-        .docker
-        .attach_container(container_id, Some(opts))
-        .await
-        .map_err(|e| e.to_string())?;
+    let attach_res = match state.docker.attach_container(container_id, Some(opts)).await {
+        Ok(res) => res,
+        Err(e) => {
+            discard_recording(state, &recorder).await;
+            return Err(e.to_string());
+        }
+    };
     let (output, input) = (attach_res.output, attach_res.input);
-    *session.lock().await = Some(Session {
-        container_id: container_id.to_string(),
-        exec_id: None,
-        pid_path: None,
-        stdin_tx: Some(Arc::new(Mutex::new(Pin::from(input)))),
-    });
-    tokio::spawn(forward_docker_stream_to_ws(output, ws_tx.clone()));
+    replace_session(
+        state,
+        session,
+        Session {
+            container_id: container_id.to_string(),
+            mode: "attach",
+            opened_at: std::time::Instant::now(),
+            exec_id: None,
+            pid_path: None,
+            stdin_tx: Some(Arc::new(Mutex::new(input))),
+            recorder: recorder.clone(),
+        },
+    )
+    .await;
+    tokio::spawn(forward_docker_stream_to_ws(output, ws_tx.clone(), recorder, binary));
     Ok(())
 }
 
-/// Forward bollard LogOutput stream to WebSocket text frames (decode as UTF-8; fallback lossy).
+/// Forward bollard LogOutput stream to WebSocket frames：text 模式以 `Utf8Decoder` 解碼，binary 模式原樣傳送。
 /// 在背景執行，不阻塞 recv 迴圈。串流結束時（例如 shell exit / Ctrl+D）主動關閉 WebSocket，讓前端收到 onclose。
+/// 有錄影時，解碼後的文字也寫入錄影檔。
 async fn forward_docker_stream_to_ws<S>(
     mut stream: S,
     ws_tx: Arc<Mutex<futures_util::stream::SplitSink<WebSocket, Message>>>,
+    recorder: Option<Arc<Recorder>>,
+    binary: bool,
 ) where
     S: futures_util::Stream<Item = Result<LogOutput, bollard::errors::Error>> + Unpin + Send,
 {
+    let mut decoder = Utf8Decoder::default();
+    // binary 模式只有錄影需要解碼
+    let decode = !binary || recorder.is_some();
     while let Some(item) = stream.next().await {
         match item {
             Ok(LogOutput::StdOut { message })
             | Ok(LogOutput::StdErr { message })
             | Ok(LogOutput::Console { message }) => {
-                let text = String::from_utf8_lossy(&message).to_string();
+                let text = if decode { decoder.decode(&message) } else { String::new() };
+                if let Some(recorder) = &recorder {
+                    recorder.output(&text).await;
+                }
+                let frame = if binary {
+                    Message::Binary(message.to_vec())
+                } else if text.is_empty() {
+                    continue;
+                } else {
+                    Message::Text(text)
+                };
                 let mut guard = ws_tx.lock().await;
-                if guard.send(Message::Text(text)).await.is_err() {
+                if guard.send(frame).await.is_err() {
                     break;
                 }
             }
@@ -313,6 +493,15 @@ async fn forward_docker_stream_to_ws<S>(
             Err(_) => break,
         }
     }
+    let rest = decoder.finish();
+    if !rest.is_empty() {
+        if let Some(recorder) = &recorder {
+            recorder.output(&rest).await;
+        }
+        if !binary {
+            let _ = ws_tx.lock().await.send(Message::Text(rest)).await;
+        }
+    }
     // 串流結束（shell exit / attach 斷開）時關閉 WebSocket，前端才能顯示 [Connection closed]
     let mut guard = ws_tx.lock().await;
     let _ = guard
@@ -0,0 +1,98 @@
+//! 唯讀日誌 WebSocket：?container=ID（可帶 tail、since、timestamps），第一則訊息須帶 token。
+//! 驗證身分與容器擁有權後持續推送 stdout/stderr；不接受任何輸入（與 console 的 attach 不同）。
+
+use axum::{
+    extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures_util::{SinkExt, StreamExt};
+
+use super::utf8::Utf8Decoder;
+use super::{await_first_message_auth, close_policy, close_unauthorized};
+use crate::docker;
+use crate::docker::logs::LogsParams;
+use crate::AppState;
+
+
+#[derive(serde::Deserialize)]
+pub struct LogsQuery {
+    pub container: Option<String>,
+    pub tail: Option<String>,
+    pub since: Option<i32>,
+    #[serde(default)]
+    pub timestamps: bool,
+}
+
+pub async fn handler(
+    State(state): State<AppState>,
+    Query(q): Query<LogsQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let container_id = q.container.unwrap_or_default();
+    if container_id.is_empty() {
+        return (StatusCode::BAD_REQUEST, "missing container query").into_response();
+    }
+    let params = LogsParams {
+        tail: q.tail,
+        since: q.since,
+        timestamps: q.timestamps,
+        follow: true,
+    };
+    upgrade.on_upgrade(move |socket| handle_socket(socket, state, container_id, params))
+}
+
+async fn handle_socket(socket: WebSocket, state: AppState, container_id: String, params: LogsParams) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    let user = match await_first_message_auth(&state, &mut ws_rx).await {
+        Some(u) => u,
+        None => {
+            let _ = ws_tx.send(Message::Close(close_unauthorized())).await;
+            return;
+        }
+    };
+    let owner = docker::container_owner(&state.docker, &container_id).await;
+    if !matches!(owner, Ok(o) if docker::user_can_access(&user, o)) {
+        let _ = ws_tx.send(Message::Close(close_policy("Forbidden"))).await;
+        return;
+    }
+
+    let mut stream = docker::logs::log_stream(&state.docker, &container_id, &params);
+    let send_task = async {
+        // 多位元組字元可能被切在兩個 chunk 之間
+        let mut decoder = Utf8Decoder::default();
+        while let Some(Ok(chunk)) = stream.next().await {
+            let text = decoder.decode(&chunk);
+            if text.is_empty() {
+                continue;
+            }
+            if ws_tx.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+        let rest = decoder.finish();
+        if !rest.is_empty() && ws_tx.send(Message::Text(rest)).await.is_err() {
+            return;
+        }
+        let _ = ws_tx
+            .send(Message::Close(Some(CloseFrame {
+                code: axum::extract::ws::close_code::NORMAL,
+                reason: std::borrow::Cow::Borrowed("logs ended"),
+            })))
+            .await;
+    };
+    let recv_task = async {
+        // 唯讀：忽略客戶端訊息，僅偵測斷線
+        while let Some(Ok(msg)) = ws_rx.next().await {
+            if matches!(msg, Message::Close(_)) {
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        _ = send_task => {}
+        _ = recv_task => {}
+    }
+}
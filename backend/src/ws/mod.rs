@@ -1,18 +1,72 @@
-//! WebSocket 路由：/ws/console（終端機）、/ws/notifications（任務狀態通知）。
+//! WebSocket 路由：/ws/console（終端機）、/ws/logs（唯讀日誌）、/ws/stats（資源用量）、/ws/notifications（任務狀態通知）。
 //! 與 Django 的 ConsoleConsumer、通知推送對齊。
 
 mod console;
+mod logs;
 mod notifications;
+mod stats;
+mod utf8;
 
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
 use axum::Router;
+use futures_util::stream::SplitStream;
+use futures_util::StreamExt;
+use std::time::Duration;
 
+use crate::auth_extractor::{authenticate_access_token, authenticate_api_token};
+use crate::db::{api_token, User};
+use crate::permissions::Scope;
 use crate::AppState;
 
-/// 掛載 /ws/console 與 /ws/notifications（含尾端斜線以配合前端）。
+/// 以 POLICY close code 關閉連線（token 缺少/無效）。
+fn close_unauthorized() -> Option<CloseFrame<'static>> {
+    close_policy("Unauthorized")
+}
+
+fn close_policy(reason: &'static str) -> Option<CloseFrame<'static>> {
+    use axum::extract::ws::close_code;
+    Some(CloseFrame {
+        code: close_code::POLICY,
+        reason: std::borrow::Cow::Borrowed(reason),
+    })
+}
+
+/// 驗證第一則訊息 `{"token": "..."}` 內的 access token 或個人 API token（須具備 `scope`），並確認使用者仍存在。
+async fn authenticate_first_message(
+    state: &AppState,
+    parsed: &serde_json::Value,
+    scope: Scope,
+) -> Option<User> {
+    let token = parsed.get("token").and_then(|t| t.as_str()).filter(|t| !t.is_empty())?;
+    if api_token::is_api_token(token) {
+        authenticate_api_token(&state.pool, token, Some(scope)).await.ok()
+    } else {
+        authenticate_access_token(state, token).await.ok()
+    }
+}
+
+/// 唯讀 socket 等待第一則（token）訊息的上限。
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 唯讀 socket（logs、stats）：在 AUTH_TIMEOUT 內讀取第一則訊息並驗證 token（API token 需 `read`）。
+async fn await_first_message_auth(state: &AppState, ws_rx: &mut SplitStream<WebSocket>) -> Option<User> {
+    let first = tokio::time::timeout(AUTH_TIMEOUT, ws_rx.next()).await;
+    let parsed = match first {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<serde_json::Value>(&text).ok()?,
+        _ => return None,
+    };
+    authenticate_first_message(state, &parsed, Scope::Read).await
+}
+
+/// 掛載 /ws/console、/ws/logs、/ws/stats 與 /ws/notifications（含尾端斜線以配合前端）。
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/ws/console", axum::routing::get(console::handler))
         .route("/ws/console/", axum::routing::get(console::handler))
+        .route("/ws/logs", axum::routing::get(logs::handler))
+        .route("/ws/logs/", axum::routing::get(logs::handler))
+        .route("/ws/stats", axum::routing::get(stats::handler))
+        .route("/ws/stats/", axum::routing::get(stats::handler))
         .route("/ws/notifications", axum::routing::get(notifications::handler))
         .route("/ws/notifications/", axum::routing::get(notifications::handler))
 }
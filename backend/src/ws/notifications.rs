@@ -32,7 +32,7 @@ pub async fn handler(
             }
         };
         let recv_task = async move {
-            while let Some(_) = ws_recv.next().await {
+            while ws_recv.next().await.is_some() {
                 // ignore incoming; we only push from server
             }
         };
@@ -0,0 +1,70 @@
+//! 資源用量 WebSocket：第一則訊息帶 token，之後每隔 STATS_INTERVAL 推送呼叫者所有執行中容器的取樣。
+//! 訊息格式：`{"stats": [ContainerStats, ...]}`；有 `containers.any` 會收到所有管理中的容器。
+
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::State,
+    response::Response,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+
+use super::{await_first_message_auth, close_unauthorized};
+use crate::api::containers::containers_snapshot;
+use crate::db::User;
+use crate::docker;
+use crate::AppState;
+
+/// 推送間隔（每次取樣本身約需 1 秒）。
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+pub async fn handler(State(state): State<AppState>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+/// 呼叫者可存取且正在執行的容器 id。
+async fn running_container_ids(state: &AppState, user: &User) -> Vec<String> {
+    containers_snapshot(state)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|c| c.status == "running" && docker::user_can_access(user, c.owner_id))
+        .map(|c| c.id)
+        .collect()
+}
+
+async fn handle_socket(socket: WebSocket, state: AppState) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    let user = match await_first_message_auth(&state, &mut ws_rx).await {
+        Some(u) => u,
+        None => {
+            let _ = ws_tx.send(Message::Close(close_unauthorized())).await;
+            return;
+        }
+    };
+
+    let send_task = async {
+        let mut ticker = tokio::time::interval(STATS_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let ids = running_container_ids(&state, &user).await;
+            let stats = docker::stats::stats_for(&state.docker, &ids).await;
+            let msg = serde_json::json!({ "stats": stats });
+            if ws_tx.send(Message::Text(msg.to_string())).await.is_err() {
+                break;
+            }
+        }
+    };
+    let recv_task = async {
+        while let Some(Ok(msg)) = ws_rx.next().await {
+            if matches!(msg, Message::Close(_)) {
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        _ = send_task => {}
+        _ = recv_task => {}
+    }
+}
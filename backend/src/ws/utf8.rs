@@ -0,0 +1,105 @@
+//! WebSocket 文字訊框用的 UTF-8 解碼：console 與日誌串流共用。
+
+/// 逐段解碼 Docker 輸出：被切在兩個 chunk 之間的多位元組字元（中文、emoji、框線字元）留到下一段再解，
+/// 只有真正無效的位元組才換成 U+FFFD。
+#[derive(Default)]
+pub(super) struct Utf8Decoder {
+    /// 上一段結尾不完整的字元（最多 3 bytes）。
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn decode(&mut self, chunk: &[u8]) -> String {
+        self.pending.extend_from_slice(chunk);
+        let mut out = String::with_capacity(self.pending.len());
+        let mut rest: &[u8] = &self.pending;
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(s) => {
+                    out.push_str(s);
+                    rest = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    out.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            out.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        // 結尾不完整：等下一段
+                        None => {
+                            rest = after;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        let consumed = self.pending.len() - rest.len();
+        self.pending.drain(..consumed);
+        out
+    }
+
+    /// 串流結束：剩下不完整的位元組以 U+FFFD 輸出。
+    pub fn finish(&mut self) -> String {
+        String::from_utf8_lossy(&std::mem::take(&mut self.pending)).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Utf8Decoder;
+
+    /// 以每個切點把輸入分成兩段解碼，結果都應與整段解碼相同。
+    fn assert_every_split(bytes: &[u8], expected: &str) {
+        for cut in 0..=bytes.len() {
+            let mut decoder = Utf8Decoder::default();
+            let mut out = decoder.decode(&bytes[..cut]);
+            out.push_str(&decoder.decode(&bytes[cut..]));
+            out.push_str(&decoder.finish());
+            assert_eq!(out, expected, "split at {}", cut);
+        }
+    }
+
+    #[test]
+    fn keeps_split_three_byte_cjk() {
+        let text = "中文 prompt$ ";
+        let bytes = text.as_bytes();
+        let mut decoder = Utf8Decoder::default();
+        // 「中」= E4 B8 AD：只收到前兩個 byte 時先不輸出
+        assert_eq!(decoder.decode(&bytes[..2]), "");
+        assert_eq!(decoder.decode(&bytes[2..4]), "中");
+        assert_eq!(decoder.decode(&bytes[4..]), "文 prompt$ ");
+        assert_eq!(decoder.finish(), "");
+        assert_every_split(bytes, text);
+    }
+
+    #[test]
+    fn keeps_split_four_byte_emoji() {
+        let text = "ok 🚀 done";
+        let bytes = text.as_bytes();
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(&bytes[..4]), "ok ");
+        assert_eq!(decoder.decode(&bytes[4..5]), "");
+        assert_eq!(decoder.decode(&bytes[5..7]), "🚀");
+        assert_every_split(bytes, text);
+    }
+
+    #[test]
+    fn replaces_invalid_bytes_mid_stream() {
+        let bytes = [b"a".as_slice(), &[0xFF], "中".as_bytes(), &[0xC3, b'b']].concat();
+        assert_every_split(&bytes, "a\u{FFFD}中\u{FFFD}b");
+    }
+
+    #[test]
+    fn finish_flushes_dangling_prefix() {
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(&"x中".as_bytes()[..3]), "x");
+        assert_eq!(decoder.finish(), "\u{FFFD}");
+        // finish 之後重新開始
+        assert_eq!(decoder.decode("y".as_bytes()), "y");
+        assert_eq!(decoder.finish(), "");
+    }
+}
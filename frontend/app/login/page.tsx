@@ -3,7 +3,30 @@
 import { useEffect, useState } from "react";
 import { useRouter } from "next/navigation";
 import { useAuth } from "@/contexts/AuthContext";
-import { apiFetch } from "@/lib/api";
+import { apiFetch, getApiBase } from "@/lib/api";
+import type { AuthMethods, MfaChallenge, TotpSetup } from "@/types/api";
+
+/** Error codes the OIDC callback puts in the URL fragment. */
+const OIDC_ERRORS: Record<string, string> = {
+  access_denied: "Sign-in was cancelled at the identity provider.",
+  account_disabled: "Your account is disabled.",
+  invalid_state: "The sign-in session expired, please try again.",
+};
+
+type Tokens = { access_token: string; refresh_token: string };
+
+/** Starts TOTP setup for a challenge that requires enrolling first. */
+async function fetchEnrollment(mfaToken: string): Promise<TotpSetup> {
+  const res = await apiFetch("/api/auth/mfa/enroll", {
+    method: "POST",
+    body: JSON.stringify({ mfa_token: mfaToken }),
+  });
+  if (!res.ok) throw new Error("enroll failed");
+  return (await res.json()) as TotpSetup;
+}
+
+const inputClass =
+  "w-full rounded-xl border border-border bg-background-elevated px-4 py-3 text-text placeholder:text-text-muted focus:border-primary focus:outline-none focus:ring-2 focus:ring-primary/20 transition-colors";
 
 export default function LoginPage() {
   const router = useRouter();
@@ -12,15 +35,54 @@ export default function LoginPage() {
   const [password, setPassword] = useState("");
   const [error, setError] = useState("");
   const [loading, setLoading] = useState(false);
+  const [methods, setMethods] = useState<AuthMethods>({ password: true, oidc: false });
+  // Second step: set once the password was accepted but a TOTP code is needed
+  const [challenge, setChallenge] = useState<MfaChallenge | null>(null);
+  const [setup, setSetup] = useState<TotpSetup | null>(null);
+  const [code, setCode] = useState("");
+  // Shown once after enrolling at login; tokens are applied when the user continues
+  const [recovery, setRecovery] = useState<{ codes: string[]; tokens: Tokens } | null>(null);
+
+  useEffect(() => {
+    apiFetch("/api/auth/methods")
+      .then((res) => (res.ok ? res.json() : null))
+      .then((data: AuthMethods | null) => data && setMethods(data))
+      .catch(() => {});
+  }, []);
 
   useEffect(() => {
     if (!isReady) return;
+    // OIDC callback redirects here with the tokens, an MFA challenge or an error code in the fragment
+    const hash = new URLSearchParams(window.location.hash.slice(1));
+    const mfaToken = hash.get("mfa_token");
+    if (mfaToken) {
+      window.history.replaceState(null, "", window.location.pathname);
+      const enroll = hash.get("enroll") === "true";
+      setChallenge({ mfa_required: true, mfa_token: mfaToken, enroll });
+      if (enroll) {
+        fetchEnrollment(mfaToken)
+          .then(setSetup)
+          .catch(() => setError("Single sign-on failed, please try again."));
+      }
+      return;
+    }
+    if (hash.has("access_token") || hash.has("error")) {
+      window.history.replaceState(null, "", window.location.pathname);
+      const access = hash.get("access_token");
+      const refresh = hash.get("refresh_token");
+      if (access && refresh) {
+        login(access, refresh);
+        router.replace("/dashboard/containers");
+        return;
+      }
+      setError(OIDC_ERRORS[hash.get("error") ?? ""] ?? "Single sign-on failed, please try again.");
+    }
     const check = async () => {
       const ok = await ensureAuth();
       if (ok) router.replace("/dashboard/containers");
     };
     check();
-  }, [isReady, ensureAuth, router]);
+  }, [isReady, ensureAuth, login, router]);
 
   async function handleSubmit(e: React.FormEvent) {
     e.preventDefault();
@@ -35,9 +97,16 @@ export default function LoginPage() {
         setError("Login failed, please try again.");
         return;
       }
-      const data = (await res.json()) as { access_token: string; refresh_token: string };
-      login(data.access_token, data.refresh_token);
-      router.replace("/dashboard/containers");
+      const data = (await res.json()) as Tokens | MfaChallenge;
+      if ("mfa_required" in data) {
+        setPassword("");
+        setChallenge(data);
+        if (data.enroll) {
+          setSetup(await fetchEnrollment(data.mfa_token));
+        }
+        return;
+      }
+      finish(data);
     } catch {
       setError("Login failed, please try again.");
     } finally {
@@ -45,6 +114,56 @@ export default function LoginPage() {
     }
   }
 
+  function finish(tokens: Tokens) {
+    login(tokens.access_token, tokens.refresh_token);
+    router.replace("/dashboard/containers");
+  }
+
+  function restart() {
+    setChallenge(null);
+    setSetup(null);
+    setCode("");
+    setError("");
+  }
+
+  async function handleVerify(e: React.FormEvent) {
+    e.preventDefault();
+    if (!challenge) return;
+    setError("");
+    setLoading(true);
+    try {
+      const res = await apiFetch("/api/auth/mfa/verify", {
+        method: "POST",
+        body: JSON.stringify({ mfa_token: challenge.mfa_token, code }),
+      });
+      if (res.status === 401) {
+        const data = (await res.json().catch(() => null)) as { error?: string } | null;
+        if (data?.error === "Invalid code") {
+          setError("Invalid code, please try again.");
+          setCode("");
+        } else {
+          restart();
+          setError("The sign-in session expired, please try again.");
+        }
+        return;
+      }
+      if (!res.ok) {
+        setError(res.status === 429 ? "Too many attempts. Try again later." : "Verification failed, please try again.");
+        return;
+      }
+      const data = (await res.json()) as Tokens & { recovery_codes?: string[] };
+      if (data.recovery_codes) {
+        setRecovery({ codes: data.recovery_codes, tokens: data });
+        return;
+      }
+      finish(data);
+    } catch {
+      setError("Verification failed, please try again.");
+    } finally {
+      setLoading(false);
+    }
+  }
+
   if (!isReady) {
     return (
       <div className="flex min-h-screen items-center justify-center bg-background">
@@ -64,59 +183,159 @@ export default function LoginPage() {
             Sign in to manage your containers
           </p>
         </div>
-        <form onSubmit={handleSubmit} className="space-y-5">
-          <div className="space-y-2">
-            <label
-              htmlFor="username"
-              className="block text-sm font-medium text-text"
+        {!challenge && methods.oidc && (
+          <a
+            href={`${getApiBase()}/api/auth/oidc/login`}
+            className="mb-5 block w-full rounded-xl border border-border py-3 text-center text-sm font-medium text-text transition-colors hover:bg-background focus:outline-none focus:ring-2 focus:ring-primary/30"
+          >
+            Sign in with SSO
+          </a>
+        )}
+        {!challenge && !methods.password && error && (
+          <p
+            className="rounded-lg border border-error/40 bg-error/10 px-3 py-2 text-sm text-error"
+            role="alert"
+          >
+            {error}
+          </p>
+        )}
+        {!challenge && methods.password && (
+          <form onSubmit={handleSubmit} className="space-y-5">
+            <div className="space-y-2">
+              <label
+                htmlFor="username"
+                className="block text-sm font-medium text-text"
+              >
+                Username
+              </label>
+              <input
+                id="username"
+                type="text"
+                value={username}
+                onChange={(e) => setUsername(e.target.value)}
+                required
+                autoComplete="username"
+                placeholder="Enter your username"
+                className={inputClass}
+              />
+            </div>
+            <div className="space-y-2">
+              <label
+                htmlFor="password"
+                className="block text-sm font-medium text-text"
+              >
+                Password
+              </label>
+              <input
+                id="password"
+                type="password"
+                value={password}
+                onChange={(e) => setPassword(e.target.value)}
+                required
+                autoComplete="current-password"
+                placeholder="Enter your password"
+                className={inputClass}
+              />
+            </div>
+            {error && (
+              <p
+                className="rounded-lg border border-error/40 bg-error/10 px-3 py-2 text-sm text-error"
+                role="alert"
+              >
+                {error}
+              </p>
+            )}
+            <button
+              type="submit"
+              disabled={loading}
+              className="w-full rounded-xl bg-primary py-3 text-sm font-medium text-white transition-colors hover:bg-primary-hover focus:outline-none focus:ring-2 focus:ring-primary/30 focus:ring-offset-2 focus:ring-offset-background disabled:opacity-50 disabled:pointer-events-none"
             >
-              Username
-            </label>
-            <input
-              id="username"
-              type="text"
-              value={username}
-              onChange={(e) => setUsername(e.target.value)}
-              required
-              autoComplete="username"
-              placeholder="Enter your username"
-              className="w-full rounded-xl border border-border bg-background-elevated px-4 py-3 text-text placeholder:text-text-muted focus:border-primary focus:outline-none focus:ring-2 focus:ring-primary/20 transition-colors"
-            />
-          </div>
-          <div className="space-y-2">
-            <label
-              htmlFor="password"
-              className="block text-sm font-medium text-text"
+              {loading ? "Signing in…" : "Sign in"}
+            </button>
+          </form>
+        )}
+        {challenge && recovery && (
+          <div className="space-y-5">
+            <p className="text-sm text-text">
+              Two-factor authentication is enabled. Save these recovery codes somewhere safe; each
+              one signs you in once if you lose your authenticator. They will not be shown again.
+            </p>
+            <ul className="grid grid-cols-2 gap-2 rounded-xl border border-border bg-background p-4 font-mono text-sm text-text">
+              {recovery.codes.map((c) => (
+                <li key={c}>{c}</li>
+              ))}
+            </ul>
+            <button
+              type="button"
+              onClick={() => finish(recovery.tokens)}
+              className="w-full rounded-xl bg-primary py-3 text-sm font-medium text-white transition-colors hover:bg-primary-hover focus:outline-none focus:ring-2 focus:ring-primary/30 focus:ring-offset-2 focus:ring-offset-background"
             >
-              Password
-            </label>
-            <input
-              id="password"
-              type="password"
-              value={password}
-              onChange={(e) => setPassword(e.target.value)}
-              required
-              autoComplete="current-password"
-              placeholder="Enter your password"
-              className="w-full rounded-xl border border-border bg-background-elevated px-4 py-3 text-text placeholder:text-text-muted focus:border-primary focus:outline-none focus:ring-2 focus:ring-primary/20 transition-colors"
-            />
+              Continue
+            </button>
           </div>
-          {error && (
-            <p
-              className="rounded-lg border border-error/40 bg-error/10 px-3 py-2 text-sm text-error"
-              role="alert"
+        )}
+        {challenge && !recovery && (
+          <form onSubmit={handleVerify} className="space-y-5">
+            {setup ? (
+              <div className="space-y-2 text-sm text-text">
+                <p>
+                  Two-factor authentication is required. Add this account to an authenticator app,
+                  then enter the 6-digit code it shows.
+                </p>
+                <a href={setup.otpauth_url} className="block text-primary hover:underline">
+                  Open in authenticator app
+                </a>
+                <p className="text-text-muted">Or enter this key manually:</p>
+                <code className="block break-all rounded-lg border border-border bg-background px-3 py-2 font-mono text-text">
+                  {setup.secret}
+                </code>
+              </div>
+            ) : (
+              <p className="text-sm text-text">
+                Enter the 6-digit code from your authenticator app, or one of your recovery codes.
+              </p>
+            )}
+            <div className="space-y-2">
+              <label htmlFor="code" className="block text-sm font-medium text-text">
+                Verification code
+              </label>
+              <input
+                id="code"
+                type="text"
+                value={code}
+                onChange={(e) => setCode(e.target.value)}
+                required
+                autoFocus
+                autoComplete="one-time-code"
+                inputMode={setup ? "numeric" : "text"}
+                placeholder="123456"
+                className={inputClass}
+              />
+            </div>
+            {error && (
+              <p
+                className="rounded-lg border border-error/40 bg-error/10 px-3 py-2 text-sm text-error"
+                role="alert"
+              >
+                {error}
+              </p>
+            )}
+            <button
+              type="submit"
+              disabled={loading || (challenge.enroll && !setup)}
+              className="w-full rounded-xl bg-primary py-3 text-sm font-medium text-white transition-colors hover:bg-primary-hover focus:outline-none focus:ring-2 focus:ring-primary/30 focus:ring-offset-2 focus:ring-offset-background disabled:opacity-50 disabled:pointer-events-none"
             >
-              {error}
-            </p>
-          )}
-          <button
-            type="submit"
-            disabled={loading}
-            className="w-full rounded-xl bg-primary py-3 text-sm font-medium text-white transition-colors hover:bg-primary-hover focus:outline-none focus:ring-2 focus:ring-primary/30 focus:ring-offset-2 focus:ring-offset-background disabled:opacity-50 disabled:pointer-events-none"
-          >
-            {loading ? "Signing in…" : "Sign in"}
-          </button>
-        </form>
+              {loading ? "Verifying…" : "Verify"}
+            </button>
+            <button
+              type="button"
+              onClick={restart}
+              className="w-full text-center text-sm text-text-muted hover:text-text"
+            >
+              Back to sign in
+            </button>
+          </form>
+        )}
       </div>
     </div>
   );
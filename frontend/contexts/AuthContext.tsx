@@ -11,6 +11,7 @@ import { useRouter } from "next/navigation";
 import {
   clearTokens,
   getAccessToken,
+  revokeRefreshToken,
   setTokens,
   verifyToken,
 } from "@/lib/auth";
@@ -36,6 +37,7 @@ export function AuthProvider({ children }: { children: React.ReactNode }) {
   }, []);
 
   const logout = useCallback(() => {
+    void revokeRefreshToken();
     clearTokens();
     setToken(null);
     router.push("/login");
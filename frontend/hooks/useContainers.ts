@@ -16,7 +16,7 @@ export function useContainers() {
     setLoading(true);
     setError(null);
     try {
-      const res = await apiFetch("/dashboard/api/containers", { token });
+      const res = await apiFetch("/dashboard/api/containers?size=true", { token });
       if (!res.ok) {
         if (res.status === 401) return;
         throw new Error("Failed to fetch containers");
@@ -43,9 +43,20 @@ export async function refreshAccessToken(): Promise<string | null> {
     body: JSON.stringify({ refresh_token: refresh }),
   });
   if (!res.ok) return null;
-  const data = (await res.json()) as { access_token: string };
-  if (typeof window !== "undefined") {
-    localStorage.setItem(ACCESS_KEY, data.access_token);
-  }
+  // Refresh tokens are single-use: the response carries the rotated one.
+  const data = (await res.json()) as { access_token: string; refresh_token: string };
+  setTokens(data.access_token, data.refresh_token);
   return data.access_token;
 }
+
+/** Revoke the stored refresh token on the server; errors are ignored (local tokens are cleared anyway). */
+export async function revokeRefreshToken(): Promise<void> {
+  const refresh = getRefreshToken();
+  if (!refresh) return;
+  const { getApiBase } = await import("./api");
+  await fetch(`${getApiBase()}/api/auth/logout`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ refresh_token: refresh }),
+  }).catch(() => undefined);
+}
@@ -6,10 +6,12 @@ export interface Container {
   short_id: string;
   image_tag: string;
   ports: { ssh?: number };
+  port_addresses: { [service: string]: string[] };
   privileged: boolean;
   nvdocker: boolean;
   size_raw: number;
   size_fs: number;
+  owner_id: number | null;
 }
 
 export interface Image {
@@ -44,4 +46,56 @@ export interface RunContainerBody {
   root_password: string;
   privileged?: boolean;
   nvdocker?: boolean;
+  gpu_count?: number;
+  device_ids?: string[];
+}
+
+export interface ContainerDefaults {
+  privileged: boolean | null;
+  nvdocker: boolean | null;
+  gpu_count: number | null;
+}
+
+/** GET /api/me */
+export interface Me {
+  id: number;
+  username: string;
+  email: string | null;
+  is_staff: boolean;
+  is_active: boolean;
+  role: 'admin' | 'operator' | 'user' | 'viewer';
+  /** 'local' or the external identity provider (e.g. 'oidc'); only local accounts can change their password. */
+  auth_provider: string;
+  permissions: string[];
+  container_defaults: ContainerDefaults;
+  quota: { max_containers: number | null };
+  usage: { containers: number; running: number; pending: number; gpus: number } | null;
+}
+
+/** GET /api/auth/methods */
+export interface AuthMethods {
+  password: boolean;
+  oidc: boolean;
+}
+
+/** POST /api/auth/token when a second step is needed (instead of tokens). */
+export interface MfaChallenge {
+  mfa_required: true;
+  mfa_token: string;
+  /** TOTP must be set up first (staff require MFA and this account has none). */
+  enroll: boolean;
+}
+
+/** POST /api/auth/mfa/enroll, POST /api/me/mfa/totp */
+export interface TotpSetup {
+  secret: string;
+  otpauth_url: string;
+}
+
+/** GET /api/me/mfa */
+export interface MfaStatus {
+  enabled: boolean;
+  pending: boolean;
+  recovery_codes_remaining: number;
+  required: boolean;
 }